@@ -0,0 +1,4 @@
+ALTER TABLE issue_delivery_queue
+    ADD COLUMN n_retries INT NOT NULL DEFAULT 0,
+    ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now(),
+    ADD COLUMN last_error TEXT NULL;
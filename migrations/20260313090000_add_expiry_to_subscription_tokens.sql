@@ -0,0 +1,8 @@
+-- Tokens can now be used once, and only for a limited time
+ALTER TABLE subscription_tokens
+    ADD COLUMN created_at timestamptz NOT NULL DEFAULT now(),
+    -- The tokens that were already around get a full lifetime from now
+    ADD COLUMN expires_at timestamptz NOT NULL DEFAULT now() + interval '24 hours',
+    ADD COLUMN consumed_at timestamptz NULL;
+-- New tokens must say explicitly when they expire
+ALTER TABLE subscription_tokens ALTER COLUMN expires_at DROP DEFAULT;
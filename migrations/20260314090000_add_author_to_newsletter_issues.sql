@@ -0,0 +1,4 @@
+-- The admin who published the issue.
+-- The issues published before we started tracking it don't have one.
+ALTER TABLE newsletter_issues
+    ADD COLUMN author_user_id uuid NULL REFERENCES users(user_id);
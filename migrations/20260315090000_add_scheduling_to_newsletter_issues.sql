@@ -0,0 +1,5 @@
+-- Issues can be written in advance and released into delivery later on:
+-- `published_at` stays NULL until the issue is released.
+ALTER TABLE newsletter_issues
+    ALTER COLUMN published_at DROP NOT NULL,
+    ADD COLUMN scheduled_for timestamptz NULL;
@@ -0,0 +1,26 @@
+CREATE TABLE newsletter_issue_drafts (
+    draft_id uuid NOT NULL,
+    author_user_id uuid NOT NULL REFERENCES users(user_id),
+    created_at timestamptz NOT NULL,
+    -- Set when the draft is published, the draft can't be edited anymore.
+    -- Cancelling a scheduled issue makes its draft editable again.
+    newsletter_issue_id uuid NULL
+        REFERENCES newsletter_issues(newsletter_issue_id) ON DELETE SET NULL,
+    PRIMARY KEY(draft_id)
+);
+
+-- Every save of a draft is a new version, the previous ones are kept around
+-- to see what changed between two test sends.
+CREATE TABLE newsletter_issue_draft_versions (
+    draft_id uuid NOT NULL
+        REFERENCES newsletter_issue_drafts(draft_id) ON DELETE CASCADE,
+    version integer NOT NULL,
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    saved_at timestamptz NOT NULL,
+    -- The last test send of this version, if any
+    test_sent_to TEXT NULL,
+    test_sent_at timestamptz NULL,
+    PRIMARY KEY(draft_id, version)
+);
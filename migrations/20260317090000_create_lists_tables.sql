@@ -0,0 +1,52 @@
+-- Subscribers can now join several mailing lists, each with its own confirmation
+CREATE TABLE lists (
+    list_id uuid NOT NULL,
+    -- How subscription forms and API clients refer to the list
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (list_id)
+);
+
+-- Everybody was implicitly subscribed to this one so far
+INSERT INTO lists (list_id, slug, name, created_at)
+VALUES (gen_random_uuid(), 'newsletter', 'Newsletter', now());
+
+CREATE TABLE list_subscriptions (
+    list_id uuid NOT NULL
+        REFERENCES lists (list_id) ON DELETE CASCADE,
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    status TEXT NOT NULL,
+    subscribed_at timestamptz NOT NULL,
+    PRIMARY KEY (list_id, subscriber_id)
+);
+
+INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+SELECT l.list_id, s.id, s.status, s.subscribed_at
+FROM subscriptions s, lists l
+WHERE l.slug = 'newsletter';
+
+-- The status now lives with each list subscription
+ALTER TABLE subscriptions DROP COLUMN status;
+
+-- A confirmation link confirms the subscription to one list
+ALTER TABLE subscription_tokens
+    ADD COLUMN list_id uuid NULL REFERENCES lists (list_id) ON DELETE CASCADE;
+UPDATE subscription_tokens
+SET list_id = (SELECT list_id FROM lists WHERE slug = 'newsletter');
+ALTER TABLE subscription_tokens ALTER COLUMN list_id SET NOT NULL;
+
+-- The lists an issue goes out to
+CREATE TABLE newsletter_issue_lists (
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    list_id uuid NOT NULL
+        REFERENCES lists (list_id),
+    PRIMARY KEY (newsletter_issue_id, list_id)
+);
+
+INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+SELECT i.newsletter_issue_id, l.list_id
+FROM newsletter_issues i, lists l
+WHERE l.slug = 'newsletter';
@@ -0,0 +1,19 @@
+-- Labels used to send issues to a segment of the subscribers, e.g. "beta" or "paid"
+CREATE TABLE subscriber_tags (
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (subscriber_id, tag)
+);
+
+-- Free-form key/value pairs, e.g. plan = "pro"
+CREATE TABLE subscriber_attributes (
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (subscriber_id, name)
+);
+
+-- The segment expression an issue was published to, everybody on its lists if NULL
+ALTER TABLE newsletter_issues ADD COLUMN segment TEXT NULL;
@@ -0,0 +1,33 @@
+-- What the subscribers agreed to and when: subscribed, imported, confirmed or unsubscribed
+CREATE TABLE consent_events (
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    list_id uuid NOT NULL
+        REFERENCES lists (list_id) ON DELETE CASCADE,
+    event TEXT NOT NULL,
+    occurred_at timestamptz NOT NULL
+);
+CREATE INDEX consent_events_subscriber_id_idx ON consent_events (subscriber_id);
+
+-- We only know when the existing subscriptions started
+INSERT INTO consent_events (subscriber_id, list_id, event, occurred_at)
+SELECT subscriber_id, list_id, 'subscribed', subscribed_at
+FROM list_subscriptions;
+
+-- The issues that were sent to each address
+CREATE TABLE issue_deliveries (
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    delivered_at timestamptz NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
+CREATE INDEX issue_deliveries_subscriber_email_idx ON issue_deliveries (subscriber_email);
+
+-- The addresses of the subscribers who asked to be erased, hashed, so that they
+-- can't be imported again
+CREATE TABLE suppressed_emails (
+    email_hash TEXT NOT NULL,
+    suppressed_at timestamptz NOT NULL,
+    PRIMARY KEY (email_hash)
+);
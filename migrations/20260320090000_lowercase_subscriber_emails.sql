@@ -0,0 +1,25 @@
+-- Addresses are stored lowercased from now on, so that `Ursula@Example.com` and
+-- `ursula@example.com` are the same subscriber. The addresses that already exist in
+-- several cases are left alone: an admin has to pick the subscriber to keep
+CREATE TEMPORARY TABLE lowercased_emails AS
+SELECT s.email AS old_email, lower(s.email) AS new_email
+FROM subscriptions s
+WHERE
+    s.email <> lower(s.email) AND
+    NOT EXISTS (
+        SELECT 1 FROM subscriptions o
+        WHERE o.id <> s.id AND lower(o.email) = lower(s.email)
+    );
+
+UPDATE subscriptions SET email = new_email
+FROM lowercased_emails WHERE email = old_email;
+
+-- The deliveries only know the address
+UPDATE issue_delivery_queue SET subscriber_email = new_email
+FROM lowercased_emails WHERE subscriber_email = old_email;
+UPDATE issue_delivery_dead_letters SET subscriber_email = new_email
+FROM lowercased_emails WHERE subscriber_email = old_email;
+UPDATE issue_deliveries SET subscriber_email = new_email
+FROM lowercased_emails WHERE subscriber_email = old_email;
+
+DROP TABLE lowercased_emails;
@@ -0,0 +1,72 @@
+//! src/authentication/basic.rs
+use actix_web::{
+    HttpResponse,
+    http::{
+        StatusCode,
+        header::{self, HeaderMap, HeaderValue},
+    },
+};
+use anyhow::Context;
+use base64::Engine;
+use secrecy::Secret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{AuthError, Credentials, validate_credentials};
+
+/// Checks the Basic credentials of an API request, and records who made it
+/// on the current span (`username` and `user_id`, if it has those fields)
+pub async fn authenticate_basic(headers: &HeaderMap, pool: &PgPool) -> Result<Uuid, AuthError> {
+    let credentials = basic_authentication(headers).map_err(AuthError::InvalidCredentials)?;
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    let user_id = validate_credentials(credentials, pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    Ok(user_id)
+}
+
+/// The 401 that asks the client for Basic credentials of `realm`
+pub fn basic_auth_challenge(realm: &str) -> HttpResponse {
+    let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+    let header_value = HeaderValue::from_str(&format!(r#"Basic realm="{}""#, realm)).unwrap();
+    response
+        .headers_mut()
+        // actix_web::http::header provides a collection of constants
+        // for the names of several well-known/standart HTTP headers
+        .insert(header::WWW_AUTHENTICATE, header_value);
+    response
+}
+
+/// Reads the credentials of the `Authorization: Basic ...` header, as used by the API
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    // The header value, if present, must be a valid UTF8 string
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string")?;
+
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The Authorization scheme was not 'Basic'.")?;
+
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .context("failed to base64-decode 'Basic' credentials")?;
+
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8.")?;
+
+    let mut credentials = decoded_credentials.splitn(2, ':');
+    let username = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth. Check that the base64 encoded string you provide after 'Basic' has the format 'username:password'"))?.to_string();
+
+    let password = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth. Check that the base64 encoded string you provide after 'Basic' has the format 'username:password'"))?.to_string();
+
+    Ok(Credentials {
+        username,
+        password: Secret::new(password),
+    })
+}
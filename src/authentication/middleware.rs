@@ -0,0 +1,59 @@
+use crate::{
+    session_state::TypedSession,
+    utils::{e500, see_other},
+};
+use actix_web::{
+    FromRequest, HttpMessage,
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    middleware::Next,
+};
+use std::ops::Deref;
+use uuid::Uuid;
+
+/// Identifier of the user that is currently logged in.
+/// It is injected into the request extensions by `reject_anonymous_users`,
+/// so any handler behind that middleware can get it using `web::ReqData<UserId>`.
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Middleware that only lets requests coming from a logged-in user go through.
+/// Anonymous requests are redirected to `/login`.
+pub async fn reject_anonymous_users(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    // `TypedSession` is an extractor, so we have to split the `ServiceRequest`
+    // to get access to the `HttpRequest` and the payload
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+
+    match session.get_user_id().map_err(e500)? {
+        Some(user_id) => {
+            req.extensions_mut().insert(UserId(user_id));
+            next.call(req).await
+        }
+        None => {
+            let response = see_other("/login");
+            let e = anyhow::anyhow!("The user has not logged in");
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
@@ -0,0 +1,8 @@
+//! src/authentication/mod.rs
+mod basic;
+mod middleware;
+mod password;
+
+pub use basic::{authenticate_basic, basic_auth_challenge, basic_authentication};
+pub use middleware::{UserId, reject_anonymous_users};
+pub use password::{AuthError, Credentials, change_password, validate_credentials};
@@ -6,13 +6,19 @@ use serde_aux::field_attributes::deserialize_number_from_string;
 use secrecy::ExposeSecret;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 
-use crate::domain::SubscriberEmail;
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{EmailClient, EmailSender, FileSender, PostmarkSender, SmtpSender},
+};
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub html_sanitizer: HtmlSanitizerSettings,
     pub redis_uri: Secret<String>,
 }
 
@@ -47,13 +53,75 @@ impl DatabaseSettings {
 
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
+    /// Which transport is used to deliver the emails, defaults to Postmark
+    #[serde(default)]
+    pub transport: EmailTransport,
+    // Postmark settings
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    /// How many times the delivery of an issue to a subscriber is attempted
+    /// before giving up and moving it to the dead letters
+    pub max_delivery_attempts: u32,
+    /// Required if `transport` is `smtp`
+    pub smtp: Option<SmtpSettings>,
+    /// Required if `transport` is `file`
+    pub file_sink: Option<FileSinkSettings>,
+}
+
+/// The available ways of delivering emails
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailTransport {
+    /// Postmark's HTTP API
+    #[default]
+    Postmark,
+    /// A plain SMTP relay
+    Smtp,
+    /// A local mbox file, for development
+    File,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct FileSinkSettings {
+    pub path: String,
 }
 
 impl EmailClientSettings {
+    /// Builds an `EmailClient` that delivers emails through the configured transport
+    pub fn client(self) -> EmailClient {
+        let sender_email = self.sender().expect("Invalid sender email address");
+        let timeout = self.timeout();
+        let transport: Box<dyn EmailSender> = match self.transport {
+            EmailTransport::Postmark => Box::new(PostmarkSender::new(
+                self.base_url,
+                self.authorization_token,
+                timeout,
+            )),
+            EmailTransport::Smtp => {
+                let smtp = self
+                    .smtp
+                    .expect("The `smtp` settings are required by the `smtp` transport");
+                Box::new(SmtpSender::new(&smtp.host, smtp.port, timeout))
+            }
+            EmailTransport::File => {
+                let file_sink = self
+                    .file_sink
+                    .expect("The `file_sink` settings are required by the `file` transport");
+                Box::new(FileSender::new(file_sink.path))
+            }
+        };
+        EmailClient::new(sender_email, transport)
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
@@ -63,6 +131,16 @@ impl EmailClientSettings {
     }
 }
 
+/// What is kept in the HTML content of the issues, see `HtmlSanitizer`
+#[derive(serde::Deserialize, Clone)]
+pub struct HtmlSanitizerSettings {
+    pub allowed_tags: HashSet<String>,
+    /// The attributes allowed on each tag
+    pub allowed_attributes: HashMap<String, HashSet<String>>,
+    /// The URL schemes allowed in links, e.g. `https`
+    pub allowed_url_schemes: HashSet<String>,
+}
+
 #[derive(serde::Deserialize, Clone)]
 pub struct ApplicationSettings {
     // Converts from str to u16 in case we up an environment variable
@@ -71,6 +149,9 @@ pub struct ApplicationSettings {
     pub host: String,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    /// Key of the suppression hashes of the erased addresses. Unlike `hmac_secret`
+    /// it must never be rotated: the suppressions would be lost
+    pub suppression_key: Secret<String>,
 }
 
 /// The possible runtime environment for our application
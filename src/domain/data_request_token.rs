@@ -0,0 +1,118 @@
+//! src/domain/data_request_token.rs
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// Token of the link that lets a subscriber download or erase their data.
+///
+/// Like `UnsubscribeToken` it is an HMAC and is not stored, but it also signs the
+/// expiry of the link: whoever gets hold of an old email can't use it forever.
+#[derive(Debug)]
+pub struct DataRequestToken(String);
+
+impl DataRequestToken {
+    pub fn generate(
+        subscriber_id: Uuid,
+        expires_at: DateTime<Utc>,
+        hmac_secret: &Secret<String>,
+    ) -> Self {
+        let mac = Self::mac(subscriber_id, expires_at, hmac_secret)
+            .finalize()
+            .into_bytes();
+        Self(hex::encode(mac))
+    }
+
+    /// Checks that the token was generated for `subscriber_id` and `expires_at`,
+    /// in constant time. Whether the link has expired is up to the caller.
+    pub fn verify(
+        &self,
+        subscriber_id: Uuid,
+        expires_at: DateTime<Utc>,
+        hmac_secret: &Secret<String>,
+    ) -> bool {
+        let Ok(tag) = hex::decode(&self.0) else {
+            return false;
+        };
+        Self::mac(subscriber_id, expires_at, hmac_secret)
+            .verify_slice(&tag)
+            .is_ok()
+    }
+
+    fn mac(
+        subscriber_id: Uuid,
+        expires_at: DateTime<Utc>,
+        hmac_secret: &Secret<String>,
+    ) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(hmac_secret.expose_secret().as_bytes())
+            .expect("HMAC can take keys of any size");
+        mac.update(b"data-request:");
+        mac.update(subscriber_id.as_bytes());
+        mac.update(&expires_at.timestamp().to_be_bytes());
+        mac
+    }
+}
+
+impl From<String> for DataRequestToken {
+    fn from(s: String) -> Self {
+        Self(s)
+    }
+}
+
+impl AsRef<str> for DataRequestToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DataRequestToken;
+    use crate::domain::UnsubscribeToken;
+    use chrono::{TimeDelta, Utc};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".to_string())
+    }
+
+    #[test]
+    fn a_generated_token_is_valid_for_its_subscriber_and_expiry() {
+        let subscriber_id = Uuid::new_v4();
+        let expires_at = Utc::now();
+        let token = DataRequestToken::generate(subscriber_id, expires_at, &secret());
+        assert!(token.verify(subscriber_id, expires_at, &secret()));
+        assert!(!token.verify(Uuid::new_v4(), expires_at, &secret()));
+    }
+
+    #[test]
+    fn the_expiry_can_not_be_pushed_back() {
+        let subscriber_id = Uuid::new_v4();
+        let expires_at = Utc::now();
+        let token = DataRequestToken::generate(subscriber_id, expires_at, &secret());
+        assert!(!token.verify(subscriber_id, expires_at + TimeDelta::days(1), &secret()));
+    }
+
+    #[test]
+    fn a_token_generated_with_another_secret_is_rejected() {
+        let subscriber_id = Uuid::new_v4();
+        let expires_at = Utc::now();
+        let token = DataRequestToken::generate(
+            subscriber_id,
+            expires_at,
+            &Secret::new("another-key".to_string()),
+        );
+        assert!(!token.verify(subscriber_id, expires_at, &secret()));
+    }
+
+    #[test]
+    fn an_unsubscribe_token_is_not_a_data_request_token() {
+        let subscriber_id = Uuid::new_v4();
+        let unsubscribe_token = UnsubscribeToken::generate(subscriber_id, &secret());
+        let token = DataRequestToken::from(unsubscribe_token.as_ref().to_string());
+        assert!(!token.verify(subscriber_id, Utc::now(), &secret()));
+    }
+}
@@ -0,0 +1,53 @@
+//! src/domain/list_slug.rs
+
+/// The name of a mailing list in URLs, forms and API bodies, e.g. `rust-weekly`
+#[derive(Debug)]
+pub struct ListSlug(String);
+
+impl ListSlug {
+    /// Lowercase ASCII letters, digits and dashes, up to 64 characters
+    pub fn parse(s: String) -> Result<ListSlug, String> {
+        let is_valid = !s.is_empty()
+            && s.len() <= 64
+            && s.chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        if is_valid {
+            Ok(Self(s))
+        } else {
+            Err(format!(
+                "'{}' is not a valid list slug: use lowercase letters, digits and dashes.",
+                s
+            ))
+        }
+    }
+}
+
+impl AsRef<str> for ListSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::ListSlug;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn a_valid_slug_is_parsed_successfully() {
+        assert_ok!(ListSlug::parse("rust-weekly-2".to_string()));
+    }
+
+    #[test]
+    fn empty_and_too_long_slugs_are_rejected() {
+        assert_err!(ListSlug::parse("".to_string()));
+        assert_err!(ListSlug::parse("a".repeat(65)));
+    }
+
+    #[test]
+    fn slugs_with_other_characters_are_rejected() {
+        for slug in ["Rust", "rust weekly", "rust_weekly", "café"] {
+            assert_err!(ListSlug::parse(slug.to_string()));
+        }
+    }
+}
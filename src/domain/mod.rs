@@ -1,9 +1,19 @@
 //! src/domain/mod.rs
 
+mod data_request_token;
+mod list_slug;
 mod new_subscriber;
+mod subscriber_attribute;
 mod subscriber_email;
 mod subscriber_name;
+mod subscriber_tag;
+mod unsubscribe_token;
 
+pub use data_request_token::DataRequestToken;
+pub use list_slug::ListSlug;
 pub use new_subscriber::NewSubscriber;
+pub use subscriber_attribute::SubscriberAttribute;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscriber_tag::SubscriberTag;
+pub use unsubscribe_token::UnsubscribeToken;
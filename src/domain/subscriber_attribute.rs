@@ -0,0 +1,69 @@
+//! src/domain/subscriber_attribute.rs
+
+use crate::domain::SubscriberTag;
+
+/// A key/value pair stored on a subscriber, e.g. `plan = "pro"`
+#[derive(Debug)]
+pub struct SubscriberAttribute {
+    name: String,
+    value: String,
+}
+
+impl SubscriberAttribute {
+    /// Names follow the same rules as tags, values are free text up to 256 characters
+    pub fn parse(name: String, value: String) -> Result<SubscriberAttribute, String> {
+        let name = SubscriberTag::parse(name)
+            .map_err(|_| "Attribute names can only use letters, digits, dashes and underscores.")?;
+        let value = value.trim();
+        if value.is_empty() || value.chars().count() > 256 {
+            return Err(format!(
+                "The value of '{}' must be between 1 and 256 characters long.",
+                name.as_ref()
+            ));
+        }
+        Ok(Self {
+            name: name.as_ref().to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberAttribute;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn a_valid_attribute_is_parsed_successfully() {
+        let attribute = assert_ok!(SubscriberAttribute::parse(
+            "Plan".to_string(),
+            " pro plus ".to_string()
+        ));
+        assert_eq!(attribute.name(), "plan");
+        assert_eq!(attribute.value(), "pro plus");
+    }
+
+    #[test]
+    fn invalid_names_and_values_are_rejected() {
+        assert_err!(SubscriberAttribute::parse(
+            "the plan".to_string(),
+            "pro".to_string()
+        ));
+        assert_err!(SubscriberAttribute::parse(
+            "plan".to_string(),
+            " ".to_string()
+        ));
+        assert_err!(SubscriberAttribute::parse(
+            "plan".to_string(),
+            "a".repeat(257)
+        ));
+    }
+}
@@ -14,13 +14,20 @@ impl std::fmt::Display for SubscriberEmail {
 }
 
 impl SubscriberEmail {
+    /// The address is stored lowercased: `Ursula@Example.com` and `ursula@example.com`
+    /// are the same subscriber
     pub fn parse(s: String) -> Result<SubscriberEmail, String> {
         if s.validate_email() {
-            Ok(Self(s))
+            Ok(Self(Self::normalize(&s)))
         } else {
             Err(format!("{} is not a valid subscriber email", s))
         }
     }
+
+    /// The form under which two addresses are the same one, whatever their case
+    pub fn normalize(email: &str) -> String {
+        email.trim().to_lowercase()
+    }
 }
 
 impl AsRef<str> for SubscriberEmail {
@@ -81,6 +88,11 @@ mod tests {
         let email = "@domain.com".to_string();
         assert_err!(SubscriberEmail::parse(email));
     }
+    #[test]
+    fn emails_are_lowercased() {
+        let email = SubscriberEmail::parse("Ursula@Example.com".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "ursula@example.com");
+    }
     #[quickcheck_macros::quickcheck]
     fn valid_emails_are_parsed_successfully(valid_email: ValidEmailFixture) -> bool {
         SubscriberEmail::parse(valid_email.0).is_ok()
@@ -0,0 +1,87 @@
+//! src/domain/subscriber_tag.rs
+
+/// A label on a subscriber, e.g. `beta` or `es-locale`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SubscriberTag(String);
+
+impl SubscriberTag {
+    /// Tags are case-insensitive: they are stored in lowercase.
+    /// Letters, digits, dashes and underscores, up to 64 characters.
+    /// The operators of segments can't be tags, a segment couldn't name them
+    pub fn parse(s: String) -> Result<SubscriberTag, String> {
+        let tag = s.trim().to_lowercase();
+        if ["and", "or", "not"].contains(&tag.as_str()) {
+            return Err(format!(
+                "'{}' is not a valid tag: it is an operator of segments.",
+                s
+            ));
+        }
+        let is_valid = !tag.is_empty()
+            && tag.len() <= 64
+            && tag
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if is_valid {
+            Ok(Self(tag))
+        } else {
+            Err(format!(
+                "'{}' is not a valid tag: use letters, digits, dashes and underscores.",
+                s
+            ))
+        }
+    }
+
+    /// Parses tags separated by commas or whitespace, e.g. `beta, paid`
+    pub fn parse_list(s: &str) -> Result<Vec<SubscriberTag>, String> {
+        s.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| SubscriberTag::parse(tag.to_string()))
+            .collect()
+    }
+}
+
+impl AsRef<str> for SubscriberTag {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberTag;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn tags_are_stored_in_lowercase() {
+        let tag = assert_ok!(SubscriberTag::parse(" ES-locale ".to_string()));
+        assert_eq!(tag.as_ref(), "es-locale");
+    }
+
+    #[test]
+    fn empty_and_too_long_tags_are_rejected() {
+        assert_err!(SubscriberTag::parse(" ".to_string()));
+        assert_err!(SubscriberTag::parse("a".repeat(65)));
+    }
+
+    #[test]
+    fn lists_of_tags_are_split_on_commas_and_whitespace() {
+        let tags = SubscriberTag::parse_list(" beta,paid  es-locale, ").unwrap();
+        let tags: Vec<_> = tags.iter().map(|t| t.as_ref()).collect();
+        assert_eq!(tags, ["beta", "paid", "es-locale"]);
+        assert_err!(SubscriberTag::parse_list("beta, (paid)"));
+    }
+
+    #[test]
+    fn tags_with_other_characters_are_rejected() {
+        for tag in ["paid user", "beta!", "(paid)", "\"pro\""] {
+            assert_err!(SubscriberTag::parse(tag.to_string()));
+        }
+    }
+
+    #[test]
+    fn the_operators_of_segments_are_rejected() {
+        for tag in ["and", "OR", " Not "] {
+            assert_err!(SubscriberTag::parse(tag.to_string()));
+        }
+    }
+}
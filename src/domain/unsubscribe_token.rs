@@ -0,0 +1,93 @@
+//! src/domain/unsubscribe_token.rs
+
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// Token that lets a subscriber leave the newsletter without logging in.
+///
+/// It is the HMAC of the subscriber id, so it does not need to be stored:
+/// whoever knows the secret can regenerate it and check it, nobody else can forge it.
+#[derive(Debug)]
+pub struct UnsubscribeToken(String);
+
+impl UnsubscribeToken {
+    pub fn generate(subscriber_id: Uuid, hmac_secret: &Secret<String>) -> Self {
+        let mac = Self::mac(subscriber_id, hmac_secret)
+            .finalize()
+            .into_bytes();
+        Self(hex::encode(mac))
+    }
+
+    /// Checks that the token was generated for `subscriber_id`.
+    /// The comparison runs in constant time, to avoid timing attacks.
+    pub fn verify(&self, subscriber_id: Uuid, hmac_secret: &Secret<String>) -> bool {
+        let Ok(tag) = hex::decode(&self.0) else {
+            return false;
+        };
+        Self::mac(subscriber_id, hmac_secret)
+            .verify_slice(&tag)
+            .is_ok()
+    }
+
+    fn mac(subscriber_id: Uuid, hmac_secret: &Secret<String>) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(hmac_secret.expose_secret().as_bytes())
+            .expect("HMAC can take keys of any size");
+        // Tag the payload with its purpose, so the token can not be mixed up
+        // with other HMACs computed with the same secret
+        mac.update(b"unsubscribe:");
+        mac.update(subscriber_id.as_bytes());
+        mac
+    }
+}
+
+impl From<String> for UnsubscribeToken {
+    fn from(s: String) -> Self {
+        Self(s)
+    }
+}
+
+impl AsRef<str> for UnsubscribeToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnsubscribeToken;
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".to_string())
+    }
+
+    #[test]
+    fn a_generated_token_is_valid_for_its_subscriber() {
+        let subscriber_id = Uuid::new_v4();
+        let token = UnsubscribeToken::generate(subscriber_id, &secret());
+        assert!(token.verify(subscriber_id, &secret()));
+    }
+
+    #[test]
+    fn a_token_is_not_valid_for_another_subscriber() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), &secret());
+        assert!(!token.verify(Uuid::new_v4(), &secret()));
+    }
+
+    #[test]
+    fn a_token_generated_with_another_secret_is_rejected() {
+        let subscriber_id = Uuid::new_v4();
+        let token =
+            UnsubscribeToken::generate(subscriber_id, &Secret::new("another-key".to_string()));
+        assert!(!token.verify(subscriber_id, &secret()));
+    }
+
+    #[test]
+    fn a_token_that_is_not_hex_is_rejected() {
+        let token = UnsubscribeToken::from("not-an-hex-string".to_string());
+        assert!(!token.verify(Uuid::new_v4(), &secret()));
+    }
+}
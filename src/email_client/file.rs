@@ -0,0 +1,137 @@
+//! src/email_client/file.rs
+
+use std::path::PathBuf;
+
+use chrono::Utc;
+use tokio::{fs::OpenOptions, io::AsyncWriteExt, sync::Mutex};
+
+use super::{Email, EmailSender};
+use crate::domain::SubscriberEmail;
+
+/// Appends emails to a local file in mbox format, instead of delivering them.
+/// Meant for development: the file can be opened with any mail client.
+pub struct FileSender {
+    path: PathBuf,
+    // Serializes the writes, so that concurrent emails are not interleaved in the file
+    lock: Mutex<()>,
+}
+
+impl FileSender {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for FileSender {
+    async fn send(&self, from: &SubscriberEmail, email: &Email<'_>) -> Result<(), anyhow::Error> {
+        let message = email.to_mime_message(from)?.formatted();
+        let entry = mbox_entry(from.as_ref(), &String::from_utf8_lossy(&message));
+
+        let _guard = self.lock.lock().await;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(entry.as_bytes()).await?;
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+/// Formats a message as an mbox entry: a `From ` separator line followed by the message.
+/// Lines of the message that start with `From ` are quoted with `>` (mboxrd),
+/// otherwise they would be mistaken for the start of a new message.
+fn mbox_entry(from: &str, message: &str) -> String {
+    let mut entry = format!(
+        "From {} {}\n",
+        from,
+        Utc::now().format("%a %b %e %H:%M:%S %Y")
+    );
+    for line in message.lines() {
+        if line.trim_start_matches('>').starts_with("From ") {
+            entry.push('>');
+        }
+        entry.push_str(line);
+        entry.push('\n');
+    }
+    entry.push('\n');
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileSender, mbox_entry};
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{Email, EmailClient},
+    };
+
+    #[test]
+    fn lines_starting_with_from_are_quoted() {
+        let entry = mbox_entry("a@b.com", "Subject: hi\n\nFrom here\n>From there\nFromage");
+        assert!(entry.starts_with("From a@b.com "));
+        assert!(entry.contains("\n>From here\n"));
+        assert!(entry.contains("\n>>From there\n"));
+        assert!(entry.contains("\nFromage\n"));
+    }
+
+    #[tokio::test]
+    async fn emails_are_appended_to_the_file() {
+        // Arrange
+        let path = std::env::temp_dir().join(format!("{}.mbox", uuid::Uuid::new_v4()));
+        let email_client = EmailClient::new(
+            SubscriberEmail::parse("sender@example.com".into()).unwrap(),
+            Box::new(FileSender::new(&path)),
+        );
+        let recipient = SubscriberEmail::parse("recipient@example.com".into()).unwrap();
+
+        // Act
+        for subject in ["First", "Second"] {
+            email_client
+                .send_email(&recipient, subject, "<p>Hi!</p>", "Hi!")
+                .await
+                .unwrap();
+        }
+
+        // Assert
+        let mbox = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(mbox.matches("\nFrom sender@example.com ").count(), 1);
+        assert!(mbox.contains("Subject: First"));
+        assert!(mbox.contains("Subject: Second"));
+        assert!(mbox.contains("To: recipient@example.com"));
+    }
+
+    #[tokio::test]
+    async fn the_unsubscribe_url_is_advertised_in_the_headers() {
+        // Arrange
+        let path = std::env::temp_dir().join(format!("{}.mbox", uuid::Uuid::new_v4()));
+        let email_client = EmailClient::new(
+            SubscriberEmail::parse("sender@example.com".into()).unwrap(),
+            Box::new(FileSender::new(&path)),
+        );
+        let recipient = SubscriberEmail::parse("recipient@example.com".into()).unwrap();
+        let email = Email {
+            to: &recipient,
+            subject: "Issue #1",
+            html_content: "<p>Hi!</p>",
+            text_content: "Hi!",
+            unsubscribe_url: Some("https://example.com/unsubscribe?token=abc"),
+        };
+
+        // Act
+        let outcomes = email_client.send_batch(&[email]).await.unwrap();
+
+        // Assert
+        let mbox = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(outcomes[0].is_ok());
+        assert!(mbox.contains("List-Unsubscribe: <https://example.com/unsubscribe?token=abc>"));
+        assert!(mbox.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+    }
+}
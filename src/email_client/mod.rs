@@ -0,0 +1,126 @@
+//! src/email_client/mod.rs
+mod file;
+mod postmark;
+mod smtp;
+
+pub use file::FileSender;
+pub use postmark::PostmarkSender;
+pub use smtp::SmtpSender;
+
+use crate::domain::SubscriberEmail;
+use lettre::message::{
+    MultiPart,
+    header::{HeaderName, HeaderValue},
+};
+
+/// An email, ready to be handed over to an `EmailSender`
+pub struct Email<'a> {
+    pub to: &'a SubscriberEmail,
+    pub subject: &'a str,
+    // Some people dont want to receive HTML emails, so we need to support plain text emails too
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    /// One-click unsubscribe URL (RFC 8058).
+    /// If present, it is advertised in the `List-Unsubscribe` headers, so mail clients
+    /// can show their own "Unsubscribe" button.
+    pub unsubscribe_url: Option<&'a str>,
+}
+
+impl Email<'_> {
+    /// The headers to add to the email, as (name, value) pairs
+    fn headers(&self) -> Vec<(&'static str, String)> {
+        match self.unsubscribe_url {
+            Some(url) => vec![
+                ("List-Unsubscribe", format!("<{}>", url)),
+                ("List-Unsubscribe-Post", "List-Unsubscribe=One-Click".into()),
+            ],
+            None => vec![],
+        }
+    }
+
+    /// Builds the MIME message, for the transports that speak raw email
+    /// (i.e. everything but HTTP APIs)
+    fn to_mime_message(&self, from: &SubscriberEmail) -> Result<lettre::Message, anyhow::Error> {
+        let mut builder = lettre::Message::builder()
+            .from(from.as_ref().parse()?)
+            .to(self.to.as_ref().parse()?)
+            .subject(self.subject);
+        for (name, value) in self.headers() {
+            builder = builder.raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str(name),
+                value,
+            ));
+        }
+        let message = builder.multipart(MultiPart::alternative_plain_html(
+            self.text_content.to_owned(),
+            self.html_content.to_owned(),
+        ))?;
+        Ok(message)
+    }
+}
+
+/// A way of delivering emails: an HTTP API, an SMTP relay, a local file...
+///
+/// `async fn` in traits can not be used behind a `dyn` pointer yet,
+/// `async_trait` boxes the returned futures for us.
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send(&self, from: &SubscriberEmail, email: &Email<'_>) -> Result<(), anyhow::Error>;
+
+    /// Sends several emails at once.
+    ///
+    /// The outer `Result` fails if the whole batch could not be sent, otherwise there is
+    /// one outcome per email, in the same order as `emails`.
+    /// The default implementation sends the emails one by one, transports with a bulk
+    /// API should override it.
+    async fn send_batch(
+        &self,
+        from: &SubscriberEmail,
+        emails: &[Email<'_>],
+    ) -> Result<Vec<Result<(), anyhow::Error>>, anyhow::Error> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for email in emails {
+            outcomes.push(self.send(from, email).await);
+        }
+        Ok(outcomes)
+    }
+}
+
+/// The email client used by the rest of the application.
+/// It knows who the emails are sent from, and delegates the delivery to the
+/// transport selected in the configuration.
+pub struct EmailClient {
+    sender: SubscriberEmail,
+    transport: Box<dyn EmailSender>,
+}
+
+impl EmailClient {
+    pub fn new(sender: SubscriberEmail, transport: Box<dyn EmailSender>) -> Self {
+        Self { sender, transport }
+    }
+
+    pub async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), anyhow::Error> {
+        let email = Email {
+            to: recipient,
+            subject,
+            html_content,
+            text_content,
+            unsubscribe_url: None,
+        };
+        self.transport.send(&self.sender, &email).await
+    }
+
+    /// Sends several emails at once, see `EmailSender::send_batch`
+    pub async fn send_batch(
+        &self,
+        emails: &[Email<'_>],
+    ) -> Result<Vec<Result<(), anyhow::Error>>, anyhow::Error> {
+        self.transport.send_batch(&self.sender, emails).await
+    }
+}
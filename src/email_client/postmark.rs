@@ -0,0 +1,540 @@
+//! src/email_client/postmark.rs
+
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+
+use super::{Email, EmailSender};
+use crate::domain::SubscriberEmail;
+
+/// Postmark accepts at most 500 messages per call to the batch endpoint
+const MAX_BATCH_SIZE: usize = 500;
+
+/// Sends emails through Postmark's HTTP API
+pub struct PostmarkSender {
+    http_client: Client,
+    base_url: String,
+    authorization_token: Secret<String>,
+}
+
+impl PostmarkSender {
+    pub fn new(
+        base_url: String,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        Self {
+            http_client,
+            base_url,
+            authorization_token,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for PostmarkSender {
+    async fn send(&self, from: &SubscriberEmail, email: &Email<'_>) -> Result<(), anyhow::Error> {
+        let url = format!("{}/email", self.base_url);
+
+        let request_body = SendEmailRequest::new(from, email);
+
+        self.http_client
+            .post(url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Uses the `/email/batch` endpoint, in chunks of `MAX_BATCH_SIZE` messages.
+    ///
+    /// Postmark answers with one result per message, in the same order as the request:
+    /// a message was accepted only if its `ErrorCode` is 0.
+    /// A chunk that fails as a whole fails each of its messages, the chunks that went
+    /// through keep their outcomes; the batch only fails if no chunk went through.
+    async fn send_batch(
+        &self,
+        from: &SubscriberEmail,
+        emails: &[Email<'_>],
+    ) -> Result<Vec<Result<(), anyhow::Error>>, anyhow::Error> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        let mut sent_a_chunk = false;
+        let mut chunk_error = None;
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            match self.send_chunk(from, chunk).await {
+                Ok(chunk_outcomes) => {
+                    sent_a_chunk = true;
+                    outcomes.extend(chunk_outcomes);
+                }
+                Err(e) => {
+                    let error = format!("{:#}", e);
+                    outcomes.extend(chunk.iter().map(|_| Err(anyhow::anyhow!("{}", error))));
+                    chunk_error = Some(e);
+                }
+            }
+        }
+        match chunk_error {
+            Some(e) if !sent_a_chunk => Err(e),
+            _ => Ok(outcomes),
+        }
+    }
+}
+
+impl PostmarkSender {
+    /// Sends at most `MAX_BATCH_SIZE` messages in a single call to `/email/batch`
+    async fn send_chunk(
+        &self,
+        from: &SubscriberEmail,
+        chunk: &[Email<'_>],
+    ) -> Result<Vec<Result<(), anyhow::Error>>, anyhow::Error> {
+        let request_body: Vec<_> = chunk
+            .iter()
+            .map(|email| SendEmailRequest::new(from, email))
+            .collect();
+        let results: Vec<BatchMessageResult> = self
+            .http_client
+            .post(format!("{}/email/batch", self.base_url))
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if results.len() != chunk.len() {
+            anyhow::bail!(
+                "Postmark returned {} results for a batch of {} messages",
+                results.len(),
+                chunk.len()
+            );
+        }
+        Ok(results
+            .into_iter()
+            .map(|r| match r.error_code {
+                0 => Ok(()),
+                error_code => Err(anyhow::anyhow!(
+                    "Postmark rejected the message (error code {}): {}",
+                    error_code,
+                    r.message
+                )),
+            })
+            .collect())
+    }
+}
+
+#[derive(serde::Serialize)]
+// Postmark required fields to be in pascal case
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    // Postmark rejects `null`, so we leave the field out when there are no custom headers
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<EmailHeader>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct EmailHeader {
+    name: &'static str,
+    value: String,
+}
+
+impl<'a> SendEmailRequest<'a> {
+    fn new(from: &'a SubscriberEmail, email: &'a Email<'a>) -> Self {
+        Self {
+            from: from.as_ref(),
+            to: email.to.as_ref(),
+            subject: email.subject,
+            html_body: email.html_content,
+            text_body: email.text_content,
+            headers: email
+                .headers()
+                .into_iter()
+                .map(|(name, value)| EmailHeader { name, value })
+                .collect(),
+        }
+    }
+}
+
+/// Outcome of a single message of a batch
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchMessageResult {
+    error_code: i64,
+    message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+    use fake::{
+        Fake, Faker,
+        faker::{
+            internet::en::SafeEmail,
+            lorem::en::{Paragraph, Sentence},
+        },
+    };
+    use secrecy::Secret;
+    use wiremock::{
+        Mock, MockServer, Request, ResponseTemplate,
+        http::Method,
+        matchers::{any, header, header_exists, method, path},
+    };
+
+    use super::PostmarkSender;
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{Email, EmailClient},
+    };
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            // try to parse the body as a JSON value
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                dbg!(&body);
+                // Check that all the mandatory fields are populated
+                // without inspecting the field values
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                // If parsing failed, do not match the request
+                false
+            }
+        }
+    }
+
+    /// Generate a random email subject
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    /// Generates a random email content
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    /// Generate a random email subscriber email
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    /// Get a test instance of `EmailClient`
+    fn email_client(base_url: String) -> EmailClient {
+        let transport = PostmarkSender::new(
+            base_url,
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+        );
+        EmailClient::new(email(), Box::new(transport))
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method(Method::POST))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        // there is not need for nothing here.
+        // Expectations are verified when MockServer goes out of scope.
+        // If the conditions specified in the MockServer are not met, it triggers a panic
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180)))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    /// The same email for all the `recipients`
+    fn batch<'a>(recipients: &'a [SubscriberEmail], content: &'a str) -> Vec<Email<'a>> {
+        recipients
+            .iter()
+            .map(|to| Email {
+                to,
+                subject: content,
+                html_content: content,
+                text_content: content,
+                unsubscribe_url: None,
+            })
+            .collect()
+    }
+
+    /// Replies with one result per message of the batch, rejecting the messages
+    /// sent to the recipients in `rejected`
+    struct BatchResponder {
+        rejected: Vec<String>,
+    }
+
+    impl wiremock::Respond for BatchResponder {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+            let results: Vec<_> = messages
+                .iter()
+                .map(|m| {
+                    if self.rejected.iter().any(|r| m["To"] == r.as_str()) {
+                        serde_json::json!({"ErrorCode": 406, "Message": "Inactive recipient"})
+                    } else {
+                        serde_json::json!({"ErrorCode": 0, "Message": "OK"})
+                    }
+                })
+                .collect();
+            ResponseTemplate::new(200).set_body_json(results)
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_sends_all_the_messages_in_a_single_request() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients: Vec<_> = (0..3).map(|_| email()).collect();
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(path("/email/batch"))
+            .and(method(Method::POST))
+            .respond_with(BatchResponder { rejected: vec![] })
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcomes = email_client
+            .send_batch(&batch(&recipients, &content()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(outcomes.len(), 3);
+        for outcome in outcomes {
+            assert_ok!(outcome);
+        }
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body.len(), 3);
+        assert!(body.iter().all(|m| m.get("HtmlBody").is_some()));
+        // No unsubscribe URL, no custom headers
+        assert!(body.iter().all(|m| m.get("Headers").is_none()));
+    }
+
+    #[tokio::test]
+    async fn send_batch_adds_the_list_unsubscribe_headers() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipient = email();
+        let content = content();
+        let email = Email {
+            unsubscribe_url: Some("https://example.com/unsubscribe?token=abc"),
+            ..batch(std::slice::from_ref(&recipient), &content).remove(0)
+        };
+
+        Mock::given(path("/email/batch"))
+            .respond_with(BatchResponder { rejected: vec![] })
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        email_client.send_batch(&[email]).await.unwrap();
+
+        // Assert
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(
+            body[0]["Headers"],
+            serde_json::json!([
+                {"Name": "List-Unsubscribe", "Value": "<https://example.com/unsubscribe?token=abc>"},
+                {"Name": "List-Unsubscribe-Post", "Value": "List-Unsubscribe=One-Click"},
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_the_messages_rejected_by_postmark() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients: Vec<_> = (0..3).map(|_| email()).collect();
+
+        Mock::given(path("/email/batch"))
+            .respond_with(BatchResponder {
+                rejected: vec![recipients[1].as_ref().to_owned()],
+            })
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcomes = email_client
+            .send_batch(&batch(&recipients, &content()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_ok!(&outcomes[0]);
+        assert_err!(&outcomes[1]);
+        assert_ok!(&outcomes[2]);
+    }
+
+    #[tokio::test]
+    async fn send_batch_splits_large_batches() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients: Vec<_> = (0..super::MAX_BATCH_SIZE + 1).map(|_| email()).collect();
+
+        Mock::given(path("/email/batch"))
+            .respond_with(BatchResponder { rejected: vec![] })
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcomes = email_client
+            .send_batch(&batch(&recipients, &content()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(outcomes.len(), recipients.len());
+    }
+
+    #[tokio::test]
+    async fn send_batch_keeps_the_outcomes_of_the_chunks_sent_before_a_failure() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients: Vec<_> = (0..super::MAX_BATCH_SIZE + 1).map(|_| email()).collect();
+
+        Mock::given(path("/email/batch"))
+            .respond_with(BatchResponder { rejected: vec![] })
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcomes = email_client
+            .send_batch(&batch(&recipients, &content()))
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(outcomes.len(), recipients.len());
+        assert!(outcomes[..super::MAX_BATCH_SIZE].iter().all(|o| o.is_ok()));
+        assert_err!(&outcomes[super::MAX_BATCH_SIZE]);
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_if_the_server_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_batch(&batch(&[email()], &content()))
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+}
@@ -0,0 +1,32 @@
+//! src/email_client/smtp.rs
+
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+
+use super::{Email, EmailSender};
+use crate::domain::SubscriberEmail;
+
+/// Sends emails to an SMTP relay, e.g. a local MailHog-like server.
+/// The connection is neither encrypted nor authenticated, so the relay
+/// must be reachable on a trusted network.
+pub struct SmtpSender {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpSender {
+    pub fn new(host: &str, port: u16, timeout: std::time::Duration) -> Self {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+            .port(port)
+            .timeout(Some(timeout))
+            .build();
+        Self { transport }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SmtpSender {
+    async fn send(&self, from: &SubscriberEmail, email: &Email<'_>) -> Result<(), anyhow::Error> {
+        let message = email.to_mime_message(from)?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+//! src/html_sanitizer.rs
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use html5ever::tendril::StrTendril;
+use html5ever::tokenizer::states::RawKind;
+use html5ever::tokenizer::{
+    BufferQueue, StartTag, TagToken, Token, TokenSink, TokenSinkResult, Tokenizer,
+};
+
+use crate::configuration::HtmlSanitizerSettings;
+
+/// Allowlist-based cleaner for the HTML content of the issues.
+///
+/// Whatever is not explicitly allowed by the configuration is stripped: tags,
+/// attributes, links with other URL schemes, comments. The content of `<script>`
+/// and `<style>` tags is dropped along with them.
+#[derive(Clone, Debug)]
+pub struct HtmlSanitizer {
+    allowed_tags: HashSet<String>,
+    allowed_attributes: HashMap<String, HashSet<String>>,
+    allowed_url_schemes: HashSet<String>,
+}
+
+/// The cleaned HTML, and what had to be removed to get there
+#[derive(Debug)]
+pub struct Sanitized {
+    pub html: String,
+    pub report: SanitizationReport,
+}
+
+/// How many tags (by name) and attributes (as `tag[attribute]`) were removed
+#[derive(Debug, Default, PartialEq, serde::Serialize)]
+pub struct SanitizationReport {
+    pub removed_tags: BTreeMap<String, usize>,
+    pub removed_attributes: BTreeMap<String, usize>,
+}
+
+impl SanitizationReport {
+    pub fn is_empty(&self) -> bool {
+        self.removed_tags.is_empty() && self.removed_attributes.is_empty()
+    }
+}
+
+impl std::fmt::Display for SanitizationReport {
+    /// e.g. `<script> (x2), a[onclick]`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let tags = self
+            .removed_tags
+            .iter()
+            .map(|(tag, n)| (format!("<{}>", tag), n));
+        let attributes = self
+            .removed_attributes
+            .iter()
+            .map(|(attribute, n)| (attribute.clone(), n));
+        let items: Vec<_> = tags
+            .chain(attributes)
+            .map(|(item, n)| match n {
+                1 => item,
+                n => format!("{} (x{})", item, n),
+            })
+            .collect();
+        write!(f, "{}", items.join(", "))
+    }
+}
+
+impl HtmlSanitizer {
+    pub fn new(settings: &HtmlSanitizerSettings) -> Self {
+        Self {
+            allowed_tags: settings.allowed_tags.clone(),
+            allowed_attributes: settings.allowed_attributes.clone(),
+            allowed_url_schemes: settings.allowed_url_schemes.clone(),
+        }
+    }
+
+    pub fn sanitize(&self, html: &str) -> Sanitized {
+        let mut builder = ammonia::Builder::default();
+        builder
+            .tags(self.allowed_tags.iter().map(String::as_str).collect())
+            // ammonia refuses to both keep a tag and drop its content
+            .rm_clean_content_tags(&self.allowed_tags)
+            .generic_attributes(HashSet::new())
+            .tag_attributes(
+                self.allowed_attributes
+                    .iter()
+                    .map(|(tag, attributes)| {
+                        (
+                            tag.as_str(),
+                            attributes.iter().map(String::as_str).collect(),
+                        )
+                    })
+                    .collect(),
+            )
+            .url_schemes(
+                self.allowed_url_schemes
+                    .iter()
+                    .map(String::as_str)
+                    .collect(),
+            );
+        let cleaned = builder.clean(html).to_string();
+        let report = compare(&count_markup(html), &count_markup(&cleaned));
+        Sanitized {
+            html: cleaned,
+            report,
+        }
+    }
+}
+
+/// Occurrences of the start tags and of their attributes in a document
+#[derive(Default)]
+struct MarkupCount {
+    tags: HashMap<String, usize>,
+    attributes: HashMap<String, usize>,
+}
+
+/// What `before` has and `after` doesn't
+fn compare(before: &MarkupCount, after: &MarkupCount) -> SanitizationReport {
+    fn missing(
+        before: &HashMap<String, usize>,
+        after: &HashMap<String, usize>,
+    ) -> BTreeMap<String, usize> {
+        before
+            .iter()
+            .filter_map(|(name, &n)| {
+                let n_removed = n.saturating_sub(after.get(name).copied().unwrap_or(0));
+                (n_removed > 0).then(|| (name.clone(), n_removed))
+            })
+            .collect()
+    }
+    SanitizationReport {
+        removed_tags: missing(&before.tags, &after.tags),
+        removed_attributes: missing(&before.attributes, &after.attributes),
+    }
+}
+
+/// Collects the start tags of `html` with their attributes.
+///
+/// We only need a tokenizer for this, not a full parser: ammonia does the actual
+/// parsing, we count what goes in and what comes out.
+fn count_markup(html: &str) -> MarkupCount {
+    let input = BufferQueue::default();
+    input.push_back(StrTendril::from_slice(html));
+    let tokenizer = Tokenizer::new(MarkupCounter::default(), Default::default());
+    let _ = tokenizer.feed(&input);
+    tokenizer.end();
+    tokenizer.sink.count.into_inner()
+}
+
+#[derive(Default)]
+struct MarkupCounter {
+    count: RefCell<MarkupCount>,
+}
+
+impl TokenSink for MarkupCounter {
+    type Handle = ();
+
+    fn process_token(&self, token: Token, _line_number: u64) -> TokenSinkResult<()> {
+        let TagToken(tag) = token else {
+            return TokenSinkResult::Continue;
+        };
+        if tag.kind != StartTag {
+            return TokenSinkResult::Continue;
+        }
+        let name = tag.name.to_string();
+        let mut count = self.count.borrow_mut();
+        for attribute in &tag.attrs {
+            let key = format!("{}[{}]", name, attribute.name.local);
+            *count.attributes.entry(key).or_default() += 1;
+        }
+        *count.tags.entry(name).or_default() += 1;
+        // The tree builder normally tells the tokenizer that the content of these
+        // tags is not markup, we have to do it ourselves
+        match &*tag.name {
+            "script" => TokenSinkResult::RawData(RawKind::ScriptData),
+            "style" | "xmp" | "iframe" | "noembed" | "noframes" => {
+                TokenSinkResult::RawData(RawKind::Rawtext)
+            }
+            "title" | "textarea" => TokenSinkResult::RawData(RawKind::Rcdata),
+            "plaintext" => TokenSinkResult::Plaintext,
+            _ => TokenSinkResult::Continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::HtmlSanitizer;
+    use crate::configuration::HtmlSanitizerSettings;
+
+    fn sanitizer() -> HtmlSanitizer {
+        let set = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<HashSet<_>>();
+        HtmlSanitizer::new(&HtmlSanitizerSettings {
+            allowed_tags: set(&["p", "a", "em"]),
+            allowed_attributes: HashMap::from([("a".to_string(), set(&["href"]))]),
+            allowed_url_schemes: set(&["https"]),
+        })
+    }
+
+    #[test]
+    fn allowed_markup_is_kept() {
+        let html = r#"<p>Hello <em>there</em>, <a href="https://example.com">link</a></p>"#;
+        let sanitized = sanitizer().sanitize(html);
+        assert_eq!(
+            sanitized.html,
+            r#"<p>Hello <em>there</em>, <a href="https://example.com" rel="noopener noreferrer">link</a></p>"#
+        );
+        assert!(sanitized.report.is_empty());
+    }
+
+    #[test]
+    fn scripts_are_removed_with_their_content() {
+        let sanitized =
+            sanitizer().sanitize("<p>Hi</p><script>document.write('<img src=x>')</script>");
+        assert_eq!(sanitized.html, "<p>Hi</p>");
+        assert_eq!(sanitized.report.to_string(), "<script>");
+    }
+
+    #[test]
+    fn disallowed_tags_and_attributes_are_reported() {
+        let html = r#"<p onclick="steal()">Hi<img src="https://t.example.com/pixel.gif"><img src="x"></p><a href="javascript:alert(1)">x</a>"#;
+        let sanitized = sanitizer().sanitize(html);
+        assert_eq!(
+            sanitized.html,
+            r#"<p>Hi</p><a rel="noopener noreferrer">x</a>"#
+        );
+        assert_eq!(
+            sanitized.report.to_string(),
+            "<img> (x2), a[href], img[src] (x2), p[onclick]"
+        );
+    }
+}
@@ -0,0 +1,6 @@
+//! src/idempotency/mod.rs
+mod key;
+mod persistence;
+
+pub use key::IdempotencyKey;
+pub use persistence::{NextAction, save_response, try_processing};
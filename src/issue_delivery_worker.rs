@@ -0,0 +1,534 @@
+//! src/issue_delivery_worker.rs
+
+use crate::{
+    configuration::Settings,
+    domain::{SubscriberEmail, UnsubscribeToken},
+    email_client::{Email, EmailClient},
+    personalization::{self, Recipient},
+    startup::get_connection_pool,
+};
+use askama::Template;
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use secrecy::Secret;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use std::time::Duration;
+use tracing::Span;
+use uuid::Uuid;
+
+/// Delay before the first retry of a failed delivery
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// Upper bound for the delay between two attempts
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+/// How many deliveries a worker takes from the queue at once
+const DEQUEUE_BATCH_SIZE: i64 = 500;
+
+/// Outcome of a single `try_execute_task` run
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// What the worker needs to know, besides the email client, to deliver an issue
+pub struct DeliverySettings {
+    pub max_delivery_attempts: u32,
+    /// Used to build the links in the emails
+    pub base_url: String,
+    /// Used to sign the unsubscribe links
+    pub hmac_secret: Secret<String>,
+}
+
+impl DeliverySettings {
+    pub fn new(configuration: &Settings) -> Self {
+        Self {
+            max_delivery_attempts: configuration.email_client.max_delivery_attempts,
+            base_url: configuration.application.base_url.clone(),
+            hmac_secret: configuration.application.hmac_secret.clone(),
+        }
+    }
+
+    /// The web version of an issue
+    pub fn issue_url(&self, issue_id: Uuid) -> String {
+        format!("{}/issues/{}", self.base_url, issue_id)
+    }
+
+    /// The link a subscriber can follow to stop receiving the newsletter
+    pub fn unsubscribe_url(&self, subscriber_id: Uuid) -> String {
+        let token = UnsubscribeToken::generate(subscriber_id, &self.hmac_secret);
+        format!(
+            "{}/subscriptions/unsubscribe?subscriber_id={}&token={}",
+            self.base_url,
+            subscriber_id,
+            token.as_ref()
+        )
+    }
+}
+
+/// Entrypoint of the background worker that delivers newsletter issues.
+/// It only returns if the worker loop fails.
+pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let settings = DeliverySettings::new(&configuration);
+    let email_client = configuration.email_client.client();
+    worker_loop(connection_pool, email_client, settings).await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    settings: DeliverySettings,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_task(&pool, &email_client, &settings).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+/// Dequeues a batch of delivery tasks that are due and sends the emails.
+///
+/// The task rows stay locked until the batch is completed, so several workers can run
+/// concurrently without sending the same email twice.
+/// A failed delivery is rescheduled with an exponential backoff; once it has been
+/// attempted `max_delivery_attempts` times it is moved to the dead letters table.
+#[tracing::instrument(skip_all, fields(n_tasks=tracing::field::Empty), err)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    settings: &DeliverySettings,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let (mut transaction, tasks) = dequeue_tasks(pool).await?;
+    if tasks.is_empty() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    Span::current().record("n_tasks", tasks.len());
+    // Tasks are sorted by issue, so each chunk holds all the dequeued tasks of an issue
+    for issue_tasks in tasks.chunk_by(|a, b| a.newsletter_issue_id == b.newsletter_issue_id) {
+        deliver_issue(&mut transaction, pool, email_client, issue_tasks, settings).await?;
+    }
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Sends one issue to the subscribers of `tasks`, with as few calls to the email
+/// provider as possible, and records the outcome of each delivery
+#[tracing::instrument(
+    skip_all,
+    fields(newsletter_issue_id=%tasks[0].newsletter_issue_id, n_tasks=tasks.len())
+)]
+async fn deliver_issue(
+    transaction: &mut PgTransaction,
+    pool: &PgPool,
+    email_client: &EmailClient,
+    tasks: &[DeliveryTask],
+    settings: &DeliverySettings,
+) -> Result<(), anyhow::Error> {
+    let mut deliverable_tasks = Vec::with_capacity(tasks.len());
+    let mut recipients = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        // The subscriber may have left between the publication and the delivery
+        let (subscriber_id, subscriber_name) = match (
+            task.subscriber_id,
+            task.subscriber_confirmed,
+            task.subscriber_name.as_deref(),
+        ) {
+            (Some(subscriber_id), true, Some(name)) => (subscriber_id, name),
+            _ => {
+                tracing::info!(
+                    subscriber_email = %task.subscriber_email,
+                    "Skipping a delivery, the subscriber is no longer confirmed",
+                );
+                delete_task(transaction, task).await?;
+                continue;
+            }
+        };
+        match SubscriberEmail::parse(task.subscriber_email.clone()) {
+            Ok(email) => {
+                deliverable_tasks.push(task);
+                recipients.push((
+                    email,
+                    subscriber_name,
+                    settings.unsubscribe_url(subscriber_id),
+                ));
+            }
+            Err(e) => {
+                tracing::error!(
+                    error.message = %e,
+                    subscriber_email = %task.subscriber_email,
+                    "Skipping a confirmed subscriber. \
+                    Their stored contact details are invalid",
+                );
+                // Retrying would not help, the address will still be invalid
+                move_to_dead_letters(transaction, task, &e).await?;
+            }
+        }
+    }
+    if recipients.is_empty() {
+        return Ok(());
+    }
+
+    let issue_id = tasks[0].newsletter_issue_id;
+    let (issue, bodies) = match render_issue(pool, issue_id, &recipients, settings).await {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            // The other issues of the batch must still go out: these deliveries are
+            // retried later like any other failure, and eventually dead-lettered
+            let error = format!("Failed to render the issue: {:#}", e);
+            for task in deliverable_tasks {
+                let e = anyhow::anyhow!("{}", error);
+                handle_failed_delivery(transaction, task, e, settings.max_delivery_attempts)
+                    .await?;
+            }
+            return Ok(());
+        }
+    };
+    let emails: Vec<_> = recipients
+        .iter()
+        .zip(&bodies)
+        .map(|((recipient, _, unsubscribe_url), (html, text))| Email {
+            to: recipient,
+            subject: &issue.title,
+            html_content: html,
+            text_content: text,
+            unsubscribe_url: Some(unsubscribe_url),
+        })
+        .collect();
+    let outcomes = match email_client.send_batch(&emails).await {
+        Ok(outcomes) => outcomes,
+        Err(e) => {
+            // The whole batch failed, every delivery has to be retried
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send a batch of newsletter issues",
+            );
+            let error = e.to_string();
+            recipients
+                .iter()
+                .map(|_| Err(anyhow::anyhow!("{}", error)))
+                .collect()
+        }
+    };
+
+    for (task, outcome) in deliverable_tasks.into_iter().zip(outcomes) {
+        match outcome {
+            Ok(()) => {
+                delete_task(transaction, task).await?;
+                record_delivery(transaction, task).await?;
+            }
+            Err(e) => {
+                handle_failed_delivery(transaction, task, e, settings.max_delivery_attempts).await?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The issue, with the (html, text) bodies of each recipient.
+/// Every subscriber gets their own unsubscribe link and personalized content,
+/// so the bodies differ
+async fn render_issue(
+    pool: &PgPool,
+    issue_id: Uuid,
+    recipients: &[(SubscriberEmail, &str, String)],
+    settings: &DeliverySettings,
+) -> Result<(NewsletterIssue, Vec<(String, String)>), anyhow::Error> {
+    let issue = get_issue(pool, issue_id).await?;
+    let issue_url = settings.issue_url(issue_id);
+    let bodies = recipients
+        .iter()
+        .map(|(email, name, unsubscribe_url)| {
+            let recipient = Recipient {
+                name,
+                email: email.as_ref(),
+                unsubscribe_url,
+            };
+            issue.render_bodies(&issue_url, &recipient)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((issue, bodies))
+}
+
+async fn handle_failed_delivery(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    e: anyhow::Error,
+    max_delivery_attempts: u32,
+) -> Result<(), anyhow::Error> {
+    let n_attempts = task.n_retries as u32 + 1;
+    if n_attempts >= max_delivery_attempts {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            subscriber_email = %task.subscriber_email,
+            "Failed to deliver issue to a confirmed subscriber. \
+            Giving up after {} attempts.",
+            n_attempts
+        );
+        move_to_dead_letters(transaction, task, &e.to_string()).await
+    } else {
+        tracing::warn!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            subscriber_email = %task.subscriber_email,
+            "Failed to deliver issue to a confirmed subscriber. \
+            Retrying later.",
+        );
+        let execute_after = Utc::now() + retry_delay(task.n_retries as u32);
+        reschedule_task(transaction, task, execute_after, &e.to_string()).await
+    }
+}
+
+/// Exponential backoff: the delay doubles after each failed attempt, up to `MAX_RETRY_DELAY`.
+/// A random jitter spreads the retries, so that the deliveries that failed together during an
+/// outage of the email provider don't hit it all at once when it comes back.
+fn retry_delay(n_retries: u32) -> Duration {
+    let delay = BASE_RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(n_retries))
+        .min(MAX_RETRY_DELAY);
+    // Pick a delay between 50% and 100% of the exponential one
+    let jitter = rand::thread_rng().gen_range(0.5..=1.0);
+    delay.mul_f64(jitter)
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i32,
+    // `None` if the subscriber has been deleted in the meantime
+    subscriber_id: Option<Uuid>,
+    subscriber_name: Option<String>,
+    /// Whether the subscriber is still confirmed on one of the lists of the issue
+    subscriber_confirmed: bool,
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_tasks(pool: &PgPool) -> Result<(PgTransaction, Vec<DeliveryTask>), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    // `SKIP LOCKED` makes concurrent workers pick different tasks
+    // instead of waiting on the rows that are already being processed
+    let tasks = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+        SELECT
+            q.newsletter_issue_id,
+            q.subscriber_email,
+            q.n_retries,
+            s.id AS "subscriber_id?",
+            s.name AS "subscriber_name?",
+            EXISTS (
+                SELECT 1
+                FROM list_subscriptions ls
+                JOIN newsletter_issue_lists il ON il.list_id = ls.list_id
+                WHERE
+                    ls.subscriber_id = s.id AND
+                    il.newsletter_issue_id = q.newsletter_issue_id AND
+                    ls.status = 'confirmed'
+            ) AS "subscriber_confirmed!"
+        FROM issue_delivery_queue q
+        LEFT JOIN subscriptions s ON s.email = q.subscriber_email
+        WHERE q.execute_after <= now()
+        ORDER BY q.newsletter_issue_id
+        FOR UPDATE OF q
+        SKIP LOCKED
+        LIMIT $1
+        "#,
+        DEQUEUE_BATCH_SIZE
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    Ok((transaction, tasks))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+/// Keeps the history of what was sent to each address, so subscribers can ask for it
+#[tracing::instrument(skip_all)]
+async fn record_delivery(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (newsletter_issue_id, subscriber_email, delivered_at)
+        VALUES ($1, $2, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email)
+        DO UPDATE SET delivered_at = EXCLUDED.delivered_at
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn reschedule_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    execute_after: DateTime<Utc>,
+    last_error: &str,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = $3,
+            last_error = $4
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        execute_after,
+        last_error
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn move_to_dead_letters(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    last_error: &str,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_dead_letters (
+            newsletter_issue_id,
+            subscriber_email,
+            n_attempts,
+            last_error,
+            failed_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        -- It may have been re-enqueued while still queued, the last failure wins
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE SET
+            n_attempts = EXCLUDED.n_attempts,
+            last_error = EXCLUDED.last_error,
+            failed_at = EXCLUDED.failed_at
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        task.n_retries + 1,
+        last_error
+    );
+    transaction.execute(query).await?;
+    delete_task(transaction, task).await
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[derive(Template)]
+#[template(path = "emails/newsletter.html")]
+struct NewsletterHtml<'a> {
+    // Written by an admin, it is HTML already
+    content: &'a str,
+    issue_url: &'a str,
+    unsubscribe_url: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "emails/newsletter.txt")]
+struct NewsletterText<'a> {
+    content: &'a str,
+    issue_url: &'a str,
+    unsubscribe_url: &'a str,
+}
+
+impl NewsletterIssue {
+    /// The (html, text) bodies of the issue for `recipient`, with a link to its web
+    /// version at the top and the unsubscribe link at the bottom
+    fn render_bodies(
+        &self,
+        issue_url: &str,
+        recipient: &Recipient,
+    ) -> Result<(String, String), askama::Error> {
+        let html = NewsletterHtml {
+            content: &personalization::render_html(&self.html_content, recipient),
+            issue_url,
+            unsubscribe_url: recipient.unsubscribe_url,
+        }
+        .render()?;
+        let text = NewsletterText {
+            content: &personalization::render_text(&self.text_content, recipient),
+            issue_url,
+            unsubscribe_url: recipient.unsubscribe_url,
+        }
+        .render()?;
+        Ok((html, text))
+    }
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(issue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BASE_RETRY_DELAY, MAX_RETRY_DELAY, retry_delay};
+
+    #[test]
+    fn the_first_retry_waits_at_most_the_base_delay() {
+        let delay = retry_delay(0);
+        assert!(delay >= BASE_RETRY_DELAY / 2 && delay <= BASE_RETRY_DELAY);
+    }
+
+    #[test]
+    fn the_retry_delay_grows_exponentially() {
+        let delay = retry_delay(3);
+        assert!(delay >= BASE_RETRY_DELAY * 4 && delay <= BASE_RETRY_DELAY * 8);
+    }
+
+    #[test]
+    fn the_retry_delay_is_capped() {
+        for n_retries in [10, 31, 32, u32::MAX] {
+            assert!(retry_delay(n_retries) <= MAX_RETRY_DELAY);
+        }
+    }
+}
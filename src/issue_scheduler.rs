@@ -0,0 +1,164 @@
+//! src/issue_scheduler.rs
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::time::Duration;
+use tracing::Span;
+use uuid::Uuid;
+
+use crate::routes::enqueue_delivery_tasks;
+
+/// How often the scheduler looks for issues that are due
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Releases the scheduled issues into delivery when they are due.
+/// It runs alongside the API, see `Application::run_until_stopped`.
+pub async fn run_scheduler_until_stopped(pool: PgPool) -> Result<(), anyhow::Error> {
+    loop {
+        // We just try again later
+        if let Err(e) = release_due_issues(&pool).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to release the due issues"
+            );
+        }
+        tokio::time::sleep(SCHEDULER_INTERVAL).await;
+    }
+}
+
+/// Publishes the scheduled issues whose time has come, enqueueing their deliveries.
+/// Returns the number of released issues.
+///
+/// Each issue is released in a transaction of its own: one that can't be enqueued is
+/// logged and left for the next run, it does not hold back the others. Its row is
+/// locked until the end of the transaction, so several instances of the application
+/// can run their scheduler without releasing the same issue twice.
+#[tracing::instrument(skip_all, fields(n_issues=tracing::field::Empty))]
+pub async fn release_due_issues(pool: &PgPool) -> Result<usize, anyhow::Error> {
+    let mut n_released = 0;
+    // The issues that failed during this run, not to be tried again until the next one
+    let mut failed_issue_ids = vec![];
+    loop {
+        let mut transaction = pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
+        let Some(issue_id) = sqlx::query_scalar!(
+            r#"
+            SELECT newsletter_issue_id
+            FROM newsletter_issues
+            WHERE
+                published_at IS NULL AND
+                scheduled_for <= now() AND
+                NOT (newsletter_issue_id = ANY($1))
+            ORDER BY scheduled_for
+            LIMIT 1
+            FOR UPDATE
+            SKIP LOCKED
+            "#,
+            &failed_issue_ids
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("Failed to look up the due issues")?
+        else {
+            break;
+        };
+        match release_issue(&mut transaction, issue_id).await {
+            Ok(()) => {
+                transaction
+                    .commit()
+                    .await
+                    .context("Failed to commit SQL transaction to release an issue.")?;
+                n_released += 1;
+            }
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    %issue_id,
+                    "Failed to release a scheduled issue, it will be tried again later"
+                );
+                failed_issue_ids.push(issue_id);
+            }
+        }
+    }
+    Span::current().record("n_issues", n_released);
+    Ok(n_released)
+}
+
+async fn release_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET published_at = now()
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to publish the issue")?;
+    enqueue_delivery_tasks(transaction, issue_id)
+        .await
+        .context("Failed to enqueue the deliveries of the issue")?;
+    Ok(())
+}
+
+/// Parses a send time typed in an admin form.
+/// It must carry its UTC offset (e.g. `2026-10-19T09:00:00+02:00`): we can not guess
+/// the time zone of the audience.
+pub fn parse_scheduled_for(s: &str) -> Result<DateTime<Utc>, String> {
+    let scheduled_for = DateTime::parse_from_rfc3339(s.trim())
+        .map_err(|_| {
+            format!(
+                "'{}' is not a valid date and time, use the format 2026-10-19T09:00:00+02:00.",
+                s
+            )
+        })?
+        .with_timezone(&Utc);
+    validate_scheduled_for(scheduled_for)
+}
+
+/// Issues can only be scheduled in the future
+pub fn validate_scheduled_for(scheduled_for: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    if scheduled_for <= Utc::now() {
+        return Err("The scheduled time must be in the future.".into());
+    }
+    Ok(scheduled_for)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, Utc};
+    use claims::{assert_err, assert_ok_eq};
+
+    use super::parse_scheduled_for;
+
+    #[test]
+    fn the_utc_offset_is_taken_into_account() {
+        let next_year = Utc::now().format("%Y").to_string().parse::<i32>().unwrap() + 1;
+        let scheduled_for = parse_scheduled_for(&format!("{}-10-19T09:00:00+02:00", next_year));
+        assert_ok_eq!(
+            scheduled_for.map(|t| t.to_rfc3339()),
+            format!("{}-10-19T07:00:00+00:00", next_year)
+        );
+    }
+
+    #[test]
+    fn a_time_without_offset_is_rejected() {
+        assert_err!(parse_scheduled_for("2099-10-19T09:00:00"));
+        assert_err!(parse_scheduled_for("next monday"));
+    }
+
+    #[test]
+    fn a_time_in_the_past_is_rejected() {
+        let yesterday = Utc::now() - TimeDelta::days(1);
+        assert_err!(parse_scheduled_for(&yesterday.to_rfc3339()));
+    }
+}
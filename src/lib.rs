@@ -4,6 +4,18 @@ pub mod authentication;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod html_sanitizer;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod issue_scheduler;
+pub mod mailing_lists;
+pub mod markdown;
+pub mod personal_data;
+pub mod personalization;
 pub mod routes;
+pub mod segments;
+pub mod session_state;
 pub mod startup;
+pub mod subscribers;
 pub mod telemetry;
+pub mod utils;
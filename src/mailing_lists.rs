@@ -0,0 +1,79 @@
+//! src/mailing_lists.rs
+
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// The list subscriptions and issues go to when they don't name one
+pub const DEFAULT_LIST_SLUG: &str = "newsletter";
+
+pub struct MailingList {
+    pub list_id: Uuid,
+    pub slug: String,
+    pub name: String,
+}
+
+impl MailingList {
+    pub fn is_default(&self) -> bool {
+        self.slug == DEFAULT_LIST_SLUG
+    }
+}
+
+/// Every list, sorted by name
+#[tracing::instrument(skip_all)]
+pub async fn get_lists(executor: impl PgExecutor<'_>) -> Result<Vec<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        "SELECT list_id, slug, name FROM lists ORDER BY name, slug"
+    )
+    .fetch_all(executor)
+    .await
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn get_list_by_slug(
+    executor: impl PgExecutor<'_>,
+    slug: &str,
+) -> Result<Option<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        "SELECT list_id, slug, name FROM lists WHERE slug = $1",
+        slug
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FindListsError {
+    #[error("An issue must go out to at least one list.")]
+    NoList,
+    #[error("'{0}' is not a known list.")]
+    UnknownList(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] sqlx::Error),
+}
+
+/// Looks up the lists an issue goes out to, all of them must exist
+#[tracing::instrument(skip(executor))]
+pub async fn find_lists(
+    executor: impl PgExecutor<'_>,
+    slugs: &[String],
+) -> Result<Vec<MailingList>, FindListsError> {
+    if slugs.is_empty() {
+        return Err(FindListsError::NoList);
+    }
+    let lists = sqlx::query_as!(
+        MailingList,
+        "SELECT list_id, slug, name FROM lists WHERE slug = ANY($1)",
+        slugs
+    )
+    .fetch_all(executor)
+    .await?;
+    if let Some(unknown) = slugs
+        .iter()
+        .find(|slug| !lists.iter().any(|list| &list.slug == *slug))
+    {
+        return Err(FindListsError::UnknownList(unknown.clone()));
+    }
+    Ok(lists)
+}
@@ -1,5 +1,8 @@
+use std::fmt::{Debug, Display};
+use tokio::task::JoinError;
 use zero2prod::{
     configuration::get_configuration,
+    issue_delivery_worker::run_worker_until_stopped,
     startup::Application,
     telemetry::{get_subscriber, init_subscriber_as_global_default},
 };
@@ -11,8 +14,38 @@ async fn main() -> Result<(), anyhow::Error> {
     // Panic if we cant read configuration
     let configuration = get_configuration().expect("Failed to read configuration");
 
-    let application = Application::build(configuration).await?;
+    let application = Application::build(configuration.clone()).await?;
+    let application_task = tokio::spawn(application.run_until_stopped());
+    let worker_task = tokio::spawn(run_worker_until_stopped(configuration));
 
-    application.run_until_stopped().await?;
+    // The process exits as soon as either the API or the background worker stops
+    tokio::select! {
+        o = application_task => report_exit("API", o),
+        o = worker_task => report_exit("Background worker", o),
+    };
     Ok(())
 }
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "'{}' task failed to complete",
+                task_name
+            )
+        }
+    }
+}
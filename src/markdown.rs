@@ -0,0 +1,398 @@
+//! src/markdown.rs
+
+use pulldown_cmark::{
+    CodeBlockKind, CowStr, Event, HeadingLevel, Options, Parser, Tag, TagEnd, html,
+};
+
+use crate::personalization;
+
+/// The two bodies of an email, produced from a single Markdown source
+#[derive(Debug)]
+pub struct RenderedMarkdown {
+    pub html: String,
+    pub text: String,
+}
+
+/// Renders a Markdown document to HTML and to a plain-text alternative.
+///
+/// Raw HTML in the source is escaped: it shows up as text in the HTML body, authors
+/// who need markup beyond Markdown have to send the `html`/`text` pair themselves.
+/// Link and image destinations other than http(s), `mailto:` and the placeholders
+/// for a URL are blanked out, `javascript:` links included. In the plain-text body, links are turned into
+/// numbered footnotes.
+pub fn render(markdown: &str) -> RenderedMarkdown {
+    let options = Options::ENABLE_STRIKETHROUGH;
+
+    let events = Parser::new_ext(markdown, options).map(|event| match event {
+        Event::Html(s) | Event::InlineHtml(s) => Event::Text(s),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: safe_destination(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: safe_destination(dest_url),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    let mut html = String::new();
+    html::push_html(&mut html, events);
+    // The braces of the placeholders have been percent-encoded with the rest of the URL
+    for name in personalization::URL_PLACEHOLDERS {
+        html = html.replace(
+            &format!("%7B%7B%20{}%20%7D%7D", name),
+            &format!("{{{{ {} }}}}", name),
+        );
+    }
+
+    let mut renderer = TextRenderer::default();
+    for event in Parser::new_ext(markdown, options) {
+        renderer.handle(event);
+    }
+
+    RenderedMarkdown {
+        html,
+        text: renderer.finish(),
+    }
+}
+
+/// The destination of a link or image, blanked out if it could run a script when clicked
+fn safe_destination(dest_url: CowStr<'_>) -> CowStr<'_> {
+    match personalization::url_placeholder(&dest_url) {
+        Some(name) => format!("{{{{ {} }}}}", name).into(),
+        None if is_safe_destination(&dest_url) => dest_url,
+        None => "".into(),
+    }
+}
+
+/// Whether a link or image destination can be kept as is: only web and email
+/// addresses, anything else could run a script when clicked. A placeholder for a URL,
+/// e.g. `{{unsubscribe_url}}`, is kept too: it is filled in when the issue is sent
+fn is_safe_destination(dest_url: &str) -> bool {
+    if personalization::url_placeholder(dest_url).is_some() {
+        return true;
+    }
+    let dest_url = dest_url.trim_start().to_ascii_lowercase();
+    ["http://", "https://", "mailto:"]
+        .iter()
+        .any(|scheme| dest_url.starts_with(scheme))
+}
+
+#[derive(Default)]
+struct TextRenderer {
+    out: String,
+    /// Written at the beginning of every line: quote markers and list indentation
+    prefixes: Vec<String>,
+    at_line_start: bool,
+    /// The next item number of the enclosing lists, `None` for bullet lists
+    lists: Vec<Option<u64>>,
+    /// Destination of the open links, with the position of their text in `out`
+    open_links: Vec<(String, usize)>,
+    footnotes: Vec<String>,
+}
+
+impl TextRenderer {
+    fn handle(&mut self, event: Event<'_>) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(s) | Event::Code(s) | Event::Html(s) | Event::InlineHtml(s) => {
+                self.push(&s)
+            }
+            Event::InlineMath(s) | Event::DisplayMath(s) => self.push(&s),
+            Event::SoftBreak | Event::HardBreak => self.push("\n"),
+            Event::Rule => {
+                self.push("----------");
+                self.blank_line();
+            }
+            Event::TaskListMarker(checked) => self.push(if checked { "[x] " } else { "[ ] " }),
+            Event::FootnoteReference(label) => self.push(&format!("[{}]", label)),
+        }
+    }
+
+    fn start(&mut self, tag: Tag<'_>) {
+        match tag {
+            Tag::BlockQuote(_) => {
+                self.new_line();
+                self.prefixes.push("> ".into());
+            }
+            Tag::CodeBlock(kind) => {
+                self.new_line();
+                // Indented code blocks keep their indentation in the source
+                if let CodeBlockKind::Fenced(_) = kind {
+                    self.prefixes.push("    ".into());
+                } else {
+                    self.prefixes.push(String::new());
+                }
+            }
+            Tag::List(first_number) => {
+                self.new_line();
+                self.lists.push(first_number);
+            }
+            Tag::Item => {
+                self.new_line();
+                let marker = match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}. ", *n - 1)
+                    }
+                    _ => "- ".into(),
+                };
+                self.push(&marker);
+                self.prefixes.push(" ".repeat(marker.len()));
+            }
+            Tag::Link { dest_url, .. } => {
+                self.open_links.push((dest_url.to_string(), self.out.len()));
+            }
+            Tag::Image { dest_url, .. } => {
+                self.open_links.push((dest_url.to_string(), self.out.len()));
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph | TagEnd::HtmlBlock => self.blank_line(),
+            TagEnd::Heading(level) => {
+                // Setext-style underlines for the top levels, to keep them visible
+                let underline = match level {
+                    HeadingLevel::H1 => Some('='),
+                    HeadingLevel::H2 => Some('-'),
+                    _ => None,
+                };
+                if let Some(c) = underline {
+                    let heading_length = self
+                        .out
+                        .rsplit('\n')
+                        .next()
+                        .map_or(0, |line| line.chars().count());
+                    self.push("\n");
+                    self.push(&c.to_string().repeat(heading_length));
+                }
+                self.blank_line();
+            }
+            TagEnd::BlockQuote(_) | TagEnd::CodeBlock => {
+                self.prefixes.pop();
+                self.blank_line();
+            }
+            TagEnd::List(_) => {
+                self.lists.pop();
+                // Nested lists belong to the enclosing item
+                if self.lists.is_empty() {
+                    self.blank_line();
+                }
+            }
+            TagEnd::Item => {
+                self.prefixes.pop();
+                self.new_line();
+            }
+            TagEnd::Link | TagEnd::Image => {
+                let Some((dest_url, text_start)) = self.open_links.pop() else {
+                    return;
+                };
+                let text = &self.out[text_start..];
+                // Autolinks (`<https://...>`) already show their destination
+                if text == dest_url || Some(text) == dest_url.strip_prefix("mailto:") {
+                    return;
+                }
+                if !is_safe_destination(&dest_url) {
+                    return;
+                }
+                self.footnotes.push(dest_url);
+                self.push(&format!(" [{}]", self.footnotes.len()));
+            }
+            _ => {}
+        }
+    }
+
+    /// Writes `s`, adding the line prefixes after every line break
+    fn push(&mut self, s: &str) {
+        for c in s.chars() {
+            if self.at_line_start && c != '\n' {
+                for prefix in &self.prefixes {
+                    self.out.push_str(prefix);
+                }
+                self.at_line_start = false;
+            }
+            self.out.push(c);
+            if c == '\n' {
+                self.at_line_start = true;
+            }
+        }
+    }
+
+    /// Makes sure the next text starts on its own line
+    fn new_line(&mut self) {
+        if !self.out.is_empty() && !self.at_line_start {
+            self.push("\n");
+        }
+    }
+
+    /// Makes sure there is an empty line before the next block
+    fn blank_line(&mut self) {
+        self.new_line();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn finish(self) -> String {
+        let mut text = self.out.trim_end().to_string();
+        if !self.footnotes.is_empty() {
+            text.push_str("\n\n");
+            for (i, url) in self.footnotes.iter().enumerate() {
+                text.push_str(&format!("[{}] {}\n", i + 1, url));
+            }
+        } else {
+            text.push('\n');
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+
+    #[test]
+    fn markdown_is_rendered_to_html() {
+        let rendered = render("# Title\n\nSome *emphasis* and a [link](https://example.com).");
+        assert_eq!(
+            rendered.html,
+            "<h1>Title</h1>\n\
+            <p>Some <em>emphasis</em> and a <a href=\"https://example.com\">link</a>.</p>\n"
+        );
+    }
+
+    #[test]
+    fn raw_html_is_escaped() {
+        let rendered = render("Hi <script>alert(1)</script>\n\n<div>block</div>\n");
+        assert!(!rendered.html.contains("<script>"));
+        assert!(!rendered.html.contains("<div>"));
+        assert!(rendered.html.contains("&lt;script&gt;"));
+    }
+
+    #[test]
+    fn only_web_and_email_destinations_are_kept() {
+        let rendered = render(
+            "[x](javascript:alert(1)) [y](JavaScript:alert(1)) ![z](data:image/svg+xml,1) \
+            [ok](https://example.com) [mail](mailto:hello@example.com)",
+        );
+        assert!(!rendered.html.to_lowercase().contains("javascript:"));
+        assert!(!rendered.html.contains("data:"));
+        assert!(rendered.html.contains("<a href=\"\">x</a>"));
+        assert!(rendered.html.contains("<img src=\"\" alt=\"z\" />"));
+        assert!(
+            rendered
+                .html
+                .contains("<a href=\"https://example.com\">ok</a>")
+        );
+        assert!(
+            rendered
+                .html
+                .contains("<a href=\"mailto:hello@example.com\">mail</a>")
+        );
+        assert!(!rendered.text.to_lowercase().contains("javascript:"));
+    }
+
+    #[test]
+    fn url_placeholders_can_be_link_destinations() {
+        let rendered = render("[Unsubscribe]({{unsubscribe_url}}), [me](<{{ unsubscribe_url }}>)");
+        assert_eq!(
+            rendered.html,
+            "<p><a href=\"{{ unsubscribe_url }}\">Unsubscribe</a>, \
+            <a href=\"{{ unsubscribe_url }}\">me</a></p>\n"
+        );
+        assert_eq!(
+            rendered.text,
+            "Unsubscribe [1], me [2]\n\n\
+            [1] {{unsubscribe_url}}\n\
+            [2] {{ unsubscribe_url }}\n"
+        );
+        // Only placeholders for a URL, and only on their own
+        let rendered = render("[x]({{name}}) [y](javascript:{{unsubscribe_url}})");
+        assert_eq!(
+            rendered.html,
+            "<p><a href=\"\">x</a> <a href=\"\">y</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn links_become_footnotes_in_the_plain_text_body() {
+        let rendered = render(
+            "Read [the post](https://example.com/post) and [the docs](https://example.com/docs).",
+        );
+        assert_eq!(
+            rendered.text,
+            "Read the post [1] and the docs [2].\n\n\
+            [1] https://example.com/post\n\
+            [2] https://example.com/docs\n"
+        );
+    }
+
+    #[test]
+    fn autolinks_are_not_repeated_as_footnotes() {
+        let rendered = render("See <https://example.com> or <hello@example.com>.");
+        assert_eq!(
+            rendered.text,
+            "See https://example.com or hello@example.com.\n"
+        );
+    }
+
+    #[test]
+    fn the_plain_text_body_keeps_the_structure() {
+        let markdown = "\
+# Title
+
+Intro
+paragraph.
+
+- one
+- two
+  1. nested
+  2. again
+
+> quoted
+> text
+
+```
+let x = 1;
+```
+";
+        assert_eq!(
+            render(markdown).text,
+            "\
+Title
+=====
+
+Intro
+paragraph.
+
+- one
+- two
+  1. nested
+  2. again
+
+> quoted
+> text
+
+    let x = 1;
+"
+        );
+    }
+}
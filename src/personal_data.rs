@@ -0,0 +1,358 @@
+//! src/personal_data.rs
+
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use sqlx::{Executor, PgExecutor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+
+/// The hash of an address that must not be imported again.
+///
+/// An HMAC of the normalized address: without the key, a list of candidate addresses
+/// is not enough to find out who asked to be erased.
+pub fn suppression_hash(email: &str, suppression_key: &Secret<String>) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(suppression_key.expose_secret().as_bytes())
+        .expect("HMAC can take keys of any size");
+    mac.update(SubscriberEmail::normalize(email).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Keeps track of what a subscriber agreed to on a list, e.g. `subscribed` or `confirmed`
+#[tracing::instrument(skip(transaction))]
+pub async fn record_consent_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    event: &str,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO consent_events (subscriber_id, list_id, event, occurred_at)
+        VALUES ($1, $2, $3, now())
+        "#,
+        subscriber_id,
+        list_id,
+        event
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+/// The addresses among `emails` whose owner asked to be erased
+#[tracing::instrument(skip_all)]
+pub async fn suppressed_emails(
+    executor: impl PgExecutor<'_>,
+    emails: &[String],
+    suppression_key: &Secret<String>,
+) -> Result<HashSet<String>, sqlx::Error> {
+    let hashes: Vec<String> = emails
+        .iter()
+        .map(|e| suppression_hash(e, suppression_key))
+        .collect();
+    let suppressed: HashSet<String> = sqlx::query_scalar!(
+        "SELECT email_hash FROM suppressed_emails WHERE email_hash = ANY($1)",
+        &hashes
+    )
+    .fetch_all(executor)
+    .await?
+    .into_iter()
+    .collect();
+    Ok(emails
+        .iter()
+        .zip(hashes)
+        .filter(|(_, hash)| suppressed.contains(hash))
+        .map(|(email, _)| email.clone())
+        .collect())
+}
+
+/// Everything we store about a subscriber, as they download it
+#[derive(serde::Serialize)]
+pub struct PersonalData {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub lists: Vec<ListSubscription>,
+    pub tags: Vec<String>,
+    pub attributes: BTreeMap<String, String>,
+    pub confirmation_tokens: Vec<ConfirmationToken>,
+    pub deliveries: Vec<Delivery>,
+    pub consent_events: Vec<ConsentEvent>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ListSubscription {
+    pub list: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+/// The token itself is left out: an unused one still confirms the subscription,
+/// and the export may end up anywhere
+#[derive(serde::Serialize)]
+pub struct ConfirmationToken {
+    pub list: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub consumed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct Delivery {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    /// `delivered`, `pending` or `failed`
+    pub status: String,
+    /// When it was delivered, or when it failed for good; `None` while pending
+    pub at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ConsentEvent {
+    pub list: String,
+    pub event: String,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/// `None` if there is no such subscriber
+#[tracing::instrument(skip(pool))]
+pub async fn get_personal_data(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<PersonalData>, anyhow::Error> {
+    // A single snapshot, so the parts of the export agree with each other
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    transaction
+        .execute("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+        .await
+        .context("Failed to start a read-only transaction")?;
+    let Some(subscriber) = sqlx::query!(
+        "SELECT email, name, subscribed_at FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the subscriber")?
+    else {
+        return Ok(None);
+    };
+    let lists = sqlx::query_as!(
+        ListSubscription,
+        r#"
+        SELECT l.slug AS list, ls.status, ls.subscribed_at
+        FROM list_subscriptions ls
+        JOIN lists l ON l.list_id = ls.list_id
+        WHERE ls.subscriber_id = $1
+        ORDER BY l.slug
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to look up the list subscriptions")?;
+    let tags = sqlx::query_scalar!(
+        "SELECT tag FROM subscriber_tags WHERE subscriber_id = $1 ORDER BY tag",
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to look up the tags")?;
+    let attributes = sqlx::query!(
+        "SELECT name, value FROM subscriber_attributes WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to look up the attributes")?
+    .into_iter()
+    .map(|a| (a.name, a.value))
+    .collect();
+    let confirmation_tokens = sqlx::query_as!(
+        ConfirmationToken,
+        r#"
+        SELECT l.slug AS list, t.created_at, t.expires_at, t.consumed_at
+        FROM subscription_tokens t
+        JOIN lists l ON l.list_id = t.list_id
+        WHERE t.subscriber_id = $1
+        ORDER BY t.created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to look up the confirmation tokens")?;
+    let deliveries = sqlx::query_as!(
+        Delivery,
+        r#"
+        SELECT
+            i.newsletter_issue_id,
+            i.title,
+            d.status AS "status!",
+            d.at
+        FROM (
+            SELECT newsletter_issue_id, 'delivered' AS status, delivered_at AS at
+            FROM issue_deliveries WHERE subscriber_email = $1
+            UNION ALL
+            SELECT newsletter_issue_id, 'pending', NULL
+            FROM issue_delivery_queue WHERE subscriber_email = $1
+            UNION ALL
+            SELECT newsletter_issue_id, 'failed', failed_at
+            FROM issue_delivery_dead_letters WHERE subscriber_email = $1
+        ) d
+        JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+        ORDER BY i.published_at, i.newsletter_issue_id
+        "#,
+        subscriber.email
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to look up the deliveries")?;
+    let consent_events = sqlx::query_as!(
+        ConsentEvent,
+        r#"
+        SELECT l.slug AS list, c.event, c.occurred_at
+        FROM consent_events c
+        JOIN lists l ON l.list_id = c.list_id
+        WHERE c.subscriber_id = $1
+        ORDER BY c.occurred_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to look up the consent events")?;
+    Ok(Some(PersonalData {
+        id: subscriber_id,
+        email: subscriber.email,
+        name: subscriber.name,
+        subscribed_at: subscriber.subscribed_at,
+        lists,
+        tags,
+        attributes,
+        confirmation_tokens,
+        deliveries,
+        consent_events,
+    }))
+}
+
+/// Erases a subscriber and every trace of their address, leaving only its suppression
+/// hash behind. `false` if there is no such subscriber
+#[tracing::instrument(skip(pool, suppression_key))]
+pub async fn erase_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    suppression_key: &Secret<String>,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let Some(email) = sqlx::query_scalar!(
+        "SELECT email FROM subscriptions WHERE id = $1 FOR UPDATE",
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the subscriber")?
+    else {
+        return Ok(false);
+    };
+    // The deliveries only know the address
+    for query in [
+        sqlx::query!(
+            "DELETE FROM issue_delivery_queue WHERE subscriber_email = $1",
+            email
+        ),
+        sqlx::query!(
+            "DELETE FROM issue_delivery_dead_letters WHERE subscriber_email = $1",
+            email
+        ),
+        sqlx::query!(
+            "DELETE FROM issue_deliveries WHERE subscriber_email = $1",
+            email
+        ),
+    ] {
+        transaction
+            .execute(query)
+            .await
+            .context("Failed to delete the deliveries")?;
+    }
+    let query = sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+        subscriber_id
+    );
+    transaction
+        .execute(query)
+        .await
+        .context("Failed to delete the subscription tokens")?;
+    // Their list subscriptions, tags, attributes and consent events go with them
+    let query = sqlx::query!("DELETE FROM subscriptions WHERE id = $1", subscriber_id);
+    transaction
+        .execute(query)
+        .await
+        .context("Failed to delete the subscriber")?;
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO suppressed_emails (email_hash, suppressed_at)
+        VALUES ($1, now())
+        ON CONFLICT DO NOTHING
+        "#,
+        suppression_hash(&email, suppression_key)
+    );
+    transaction
+        .execute(query)
+        .await
+        .context("Failed to store the suppression hash")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber.")?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::suppression_hash;
+    use secrecy::Secret;
+
+    fn key() -> Secret<String> {
+        Secret::new("a-very-secret-key".to_string())
+    }
+
+    #[test]
+    fn the_suppression_hash_ignores_case_and_surrounding_spaces() {
+        assert_eq!(
+            suppression_hash(" Ursula@Example.com ", &key()),
+            suppression_hash("ursula@example.com", &key())
+        );
+        assert_ne!(
+            suppression_hash("ursula@example.com", &key()),
+            suppression_hash("ged@example.com", &key())
+        );
+    }
+
+    #[test]
+    fn the_suppression_hash_can_not_be_computed_without_the_key() {
+        assert_ne!(
+            suppression_hash("ursula@example.com", &key()),
+            suppression_hash(
+                "ursula@example.com",
+                &Secret::new("another-key".to_string())
+            )
+        );
+        // Not a plain SHA-256 of the address
+        assert_ne!(
+            suppression_hash("ursula@example.com", &key()),
+            "00b41d24b65242f8c998ceca4fa6b9a6cea2a78423b24557ad6e72ae5050276f"
+        );
+    }
+}
@@ -0,0 +1,185 @@
+//! src/personalization.rs
+
+/// The placeholders authors can use in the content of an issue, e.g. `Hello {{ name }}!`
+const PLACEHOLDERS: [&str; 3] = ["name", "email", "unsubscribe_url"];
+
+/// The placeholders whose value is a URL we generate, that can be the destination
+/// of a link. `name` and `email` are written by the subscribers
+pub const URL_PLACEHOLDERS: [&str; 1] = ["unsubscribe_url"];
+
+/// The values of the placeholders for one recipient
+pub struct Recipient<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+    pub unsubscribe_url: &'a str,
+}
+
+impl Recipient<'_> {
+    /// For the web version of the issues, which is the same for everybody
+    pub fn anonymous() -> Recipient<'static> {
+        Recipient {
+            name: "reader",
+            email: "",
+            unsubscribe_url: "#",
+        }
+    }
+
+    fn value(&self, placeholder: &str) -> Option<&str> {
+        match placeholder {
+            "name" => Some(self.name),
+            "email" => Some(self.email),
+            "unsubscribe_url" => Some(self.unsubscribe_url),
+            _ => None,
+        }
+    }
+}
+
+enum Segment<'a> {
+    Text(&'a str),
+    /// The trimmed name between the braces
+    Placeholder(&'a str),
+}
+
+/// Splits `content` into text and `{{ placeholder }}`s
+fn segments(mut content: &str) -> Result<Vec<Segment<'_>>, String> {
+    let mut segments = Vec::new();
+    while let Some(start) = content.find("{{") {
+        let Some(length) = content[start..].find("}}") else {
+            return Err("A placeholder is missing its closing braces.".into());
+        };
+        segments.push(Segment::Text(&content[..start]));
+        segments.push(Segment::Placeholder(
+            content[start + 2..start + length].trim(),
+        ));
+        content = &content[start + length + 2..];
+    }
+    segments.push(Segment::Text(content));
+    Ok(segments)
+}
+
+/// The name of the placeholder for a URL that `s` is made of, e.g. `unsubscribe_url`
+/// for `{{ unsubscribe_url }}`
+pub fn url_placeholder(s: &str) -> Option<&'static str> {
+    let name = s.strip_prefix("{{")?.strip_suffix("}}")?.trim();
+    URL_PLACEHOLDERS.into_iter().find(|p| *p == name)
+}
+
+/// Checks that both bodies of an issue only use placeholders we know how to fill in
+pub fn validate_bodies(html_content: &str, text_content: &str) -> Result<(), String> {
+    validate(html_content)?;
+    validate(text_content)
+}
+
+/// Checks that `content` only uses placeholders we know how to fill in
+pub fn validate(content: &str) -> Result<(), String> {
+    for segment in segments(content)? {
+        if let Segment::Placeholder(name) = segment
+            && !PLACEHOLDERS.contains(&name)
+        {
+            let available: Vec<_> = PLACEHOLDERS
+                .iter()
+                .map(|p| format!("{{{{ {} }}}}", p))
+                .collect();
+            return Err(format!(
+                "'{{{{ {} }}}}' is not a known placeholder, use one of {}.",
+                name,
+                available.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Fills in the placeholders of a plain-text body
+pub fn render_text(content: &str, recipient: &Recipient) -> String {
+    render(content, recipient, |value, out| out.push_str(value))
+}
+
+/// Fills in the placeholders of an HTML body, the values are escaped
+pub fn render_html(content: &str, recipient: &Recipient) -> String {
+    render(content, recipient, escape_html)
+}
+
+fn render(content: &str, recipient: &Recipient, push_value: fn(&str, &mut String)) -> String {
+    // Content is validated at publication time, this is not supposed to happen
+    let Ok(segments) = segments(content) else {
+        return content.to_string();
+    };
+    let mut out = String::with_capacity(content.len());
+    for segment in segments {
+        match segment {
+            Segment::Text(text) => out.push_str(text),
+            Segment::Placeholder(name) => match recipient.value(name) {
+                Some(value) => push_value(value, &mut out),
+                None => out.push_str(&format!("{{{{ {} }}}}", name)),
+            },
+        }
+    }
+    out
+}
+
+fn escape_html(value: &str, out: &mut String) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use super::{Recipient, render_html, render_text, validate};
+
+    fn recipient() -> Recipient<'static> {
+        Recipient {
+            name: "Ursula <Le Guin>",
+            email: "ursula@example.com",
+            unsubscribe_url: "https://example.com/unsubscribe?id=1&token=abc",
+        }
+    }
+
+    #[test]
+    fn known_placeholders_are_valid() {
+        assert_ok!(validate(
+            "Hi {{ name }} ({{email}}), {{  unsubscribe_url }}"
+        ));
+        assert_ok!(validate("No placeholders at all"));
+    }
+
+    #[test]
+    fn unknown_placeholders_are_rejected() {
+        assert_err!(validate("Hi {{ first_name }}"));
+        assert_err!(validate("Hi {{ }}"));
+    }
+
+    #[test]
+    fn unclosed_placeholders_are_rejected() {
+        assert_err!(validate("Hi {{ name"));
+    }
+
+    #[test]
+    fn placeholders_are_filled_in_the_plain_text_body() {
+        assert_eq!(
+            render_text("Hi {{ name }}, bye: {{unsubscribe_url}}", &recipient()),
+            "Hi Ursula <Le Guin>, bye: https://example.com/unsubscribe?id=1&token=abc"
+        );
+    }
+
+    #[test]
+    fn values_are_escaped_in_the_html_body() {
+        assert_eq!(
+            render_html(
+                r#"<p>Hi {{ name }}</p><a href="{{ unsubscribe_url }}">bye</a>"#,
+                &recipient()
+            ),
+            r#"<p>Hi Ursula &lt;Le Guin&gt;</p><a href="https://example.com/unsubscribe?id=1&amp;token=abc">bye</a>"#
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use askama::Template;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool};
+use uuid::Uuid;
+
+use crate::utils::{e500, flash_messages, render_html, see_other};
+
+struct DeadLetter {
+    newsletter_issue_id: Uuid,
+    title: String,
+    subscriber_email: String,
+    n_attempts: i32,
+    last_error: String,
+    failed_at: DateTime<Utc>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/dead_letters.html")]
+struct DeadLettersTemplate {
+    flash_messages: Vec<String>,
+    dead_letters: Vec<DeadLetter>,
+}
+
+/// Lists the deliveries that failed after all the retries
+pub async fn dead_letters(
+    incoming_flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let dead_letters = get_dead_letters(&pool).await.map_err(e500)?;
+    render_html(&DeadLettersTemplate {
+        flash_messages: flash_messages(&incoming_flash_messages),
+        dead_letters,
+    })
+}
+
+#[derive(serde::Deserialize)]
+pub struct RequeueFormData {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+}
+
+/// Moves a dead letter back into the delivery queue, with a fresh retry budget
+#[tracing::instrument(name = "Re-enqueue a failed delivery", skip(form, pool))]
+pub async fn requeue_dead_letter(
+    form: web::Form<RequeueFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    // Locked, so that it can't be re-enqueued twice at the same time
+    let dead_letter = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id FROM issue_delivery_dead_letters
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        FOR UPDATE
+        "#,
+        form.newsletter_issue_id,
+        form.subscriber_email
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the dead letter")
+    .map_err(e500)?;
+    if dead_letter.is_none() {
+        FlashMessage::error("The failed delivery could not be found.").send();
+        return Ok(see_other("/admin/dead_letters"));
+    }
+    // The dead letter only goes away if the delivery was not in the queue already
+    let query = sqlx::query!(
+        r#"
+        WITH requeued AS (
+            INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            RETURNING newsletter_issue_id, subscriber_email
+        )
+        DELETE FROM issue_delivery_dead_letters d
+        USING requeued r
+        WHERE
+            d.newsletter_issue_id = r.newsletter_issue_id AND
+            d.subscriber_email = r.subscriber_email
+        "#,
+        form.newsletter_issue_id,
+        form.subscriber_email
+    );
+    let n_requeued = transaction
+        .execute(query)
+        .await
+        .context("Failed to move a dead letter back into the delivery queue")
+        .map_err(e500)?
+        .rows_affected();
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to re-enqueue a failed delivery.")
+        .map_err(e500)?;
+
+    if n_requeued > 0 {
+        FlashMessage::info("The delivery has been queued again.").send();
+    } else {
+        FlashMessage::error("The delivery is already queued.").send();
+    }
+    Ok(see_other("/admin/dead_letters"))
+}
+
+#[tracing::instrument(name = "Get dead letters", skip(pool))]
+async fn get_dead_letters(pool: &PgPool) -> Result<Vec<DeadLetter>, anyhow::Error> {
+    let dead_letters = sqlx::query_as!(
+        DeadLetter,
+        r#"
+        SELECT
+            d.newsletter_issue_id,
+            i.title,
+            d.subscriber_email,
+            d.n_attempts,
+            d.last_error,
+            d.failed_at
+        FROM issue_delivery_dead_letters d
+        JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+        ORDER BY d.failed_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the dead letters")?;
+    Ok(dead_letters)
+}
@@ -0,0 +1,242 @@
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use askama::Template;
+use chrono::{DateTime, Utc};
+use similar::TextDiff;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{Draft, DraftVersion, get_draft};
+use crate::{
+    mailing_lists::{MailingList, get_lists},
+    utils::{e500, flash_messages, render_html},
+};
+
+struct DraftSummary {
+    draft_id: Uuid,
+    title: String,
+    version: i32,
+    saved_at: DateTime<Utc>,
+    newsletter_issue_id: Option<Uuid>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/drafts/list.html")]
+struct DraftsTemplate {
+    flash_messages: Vec<String>,
+    drafts: Vec<DraftSummary>,
+}
+
+/// Lists the drafts, the most recently saved first
+pub async fn list_drafts(
+    incoming_flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let drafts = get_draft_summaries(&pool).await.map_err(e500)?;
+    render_html(&DraftsTemplate {
+        flash_messages: flash_messages(&incoming_flash_messages),
+        drafts,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "admin/drafts/edit.html")]
+struct EditDraftTemplate {
+    flash_messages: Vec<String>,
+    /// `None` for a draft that has not been saved yet
+    draft: Option<Draft>,
+    idempotency_key: String,
+    /// The lists the draft can be published to
+    lists: Vec<MailingList>,
+}
+
+impl EditDraftTemplate {
+    fn title(&self) -> &str {
+        self.draft.as_ref().map_or("", |d| &d.latest.title)
+    }
+
+    fn html_content(&self) -> &str {
+        self.draft.as_ref().map_or("", |d| &d.latest.html_content)
+    }
+
+    fn text_content(&self) -> &str {
+        self.draft.as_ref().map_or("", |d| &d.latest.text_content)
+    }
+}
+
+pub async fn new_draft_form(
+    incoming_flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render_html(&EditDraftTemplate {
+        flash_messages: flash_messages(&incoming_flash_messages),
+        draft: None,
+        idempotency_key: Uuid::new_v4().to_string(),
+        lists: Vec::new(),
+    })
+}
+
+/// Editor of an existing draft, with its test send and publication forms
+pub async fn edit_draft_form(
+    draft_id: web::Path<Uuid>,
+    incoming_flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(draft) = get_draft(pool.get_ref(), *draft_id).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let lists = get_lists(pool.get_ref())
+        .await
+        .context("Failed to retrieve the lists")
+        .map_err(e500)?;
+    render_html(&EditDraftTemplate {
+        flash_messages: flash_messages(&incoming_flash_messages),
+        draft: Some(draft),
+        // See `publish_newsletter_form`
+        idempotency_key: Uuid::new_v4().to_string(),
+        lists,
+    })
+}
+
+/// A version of a draft, with what changed since the previous one
+struct VersionChanges {
+    version: DraftVersion,
+    title_diff: String,
+    text_diff: String,
+    html_diff: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/drafts/versions.html")]
+struct DraftVersionsTemplate {
+    draft_id: Uuid,
+    versions: Vec<VersionChanges>,
+}
+
+/// History of a draft, the most recent version first
+pub async fn draft_versions(
+    draft_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let versions = get_draft_versions(&pool, *draft_id).await.map_err(e500)?;
+    if versions.is_empty() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let mut changes = Vec::with_capacity(versions.len());
+    let mut previous: Option<&DraftVersion> = None;
+    for version in &versions {
+        let (title, text, html) = previous.map_or(("", "", ""), |p| {
+            (&p.title, &p.text_content, &p.html_content)
+        });
+        changes.push((
+            diff(title, &version.title),
+            diff(text, &version.text_content),
+            diff(html, &version.html_content),
+        ));
+        previous = Some(version);
+    }
+    let mut versions: Vec<_> = versions
+        .into_iter()
+        .zip(changes)
+        .map(
+            |(version, (title_diff, text_diff, html_diff))| VersionChanges {
+                version,
+                title_diff,
+                text_diff,
+                html_diff,
+            },
+        )
+        .collect();
+    versions.reverse();
+
+    render_html(&DraftVersionsTemplate {
+        draft_id: *draft_id,
+        versions,
+    })
+}
+
+/// Unified line diff from `old` to `new`, empty if they are the same
+fn diff(old: &str, new: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(2)
+        .to_string()
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_draft_summaries(pool: &PgPool) -> Result<Vec<DraftSummary>, anyhow::Error> {
+    let drafts = sqlx::query_as!(
+        DraftSummary,
+        r#"
+        SELECT
+            draft_id as "draft_id!",
+            title as "title!",
+            version as "version!",
+            saved_at as "saved_at!",
+            newsletter_issue_id
+        FROM (
+            SELECT DISTINCT ON (d.draft_id)
+                d.draft_id,
+                v.title,
+                v.version,
+                v.saved_at,
+                d.newsletter_issue_id
+            FROM newsletter_issue_drafts d
+            JOIN newsletter_issue_draft_versions v ON v.draft_id = d.draft_id
+            ORDER BY d.draft_id, v.version DESC
+        ) latest
+        ORDER BY saved_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the drafts")?;
+    Ok(drafts)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_draft_versions(
+    pool: &PgPool,
+    draft_id: Uuid,
+) -> Result<Vec<DraftVersion>, anyhow::Error> {
+    let versions = sqlx::query_as!(
+        DraftVersion,
+        r#"
+        SELECT
+            version,
+            title,
+            text_content,
+            html_content,
+            saved_at,
+            test_sent_to,
+            test_sent_at
+        FROM newsletter_issue_draft_versions
+        WHERE draft_id = $1
+        ORDER BY version
+        "#,
+        draft_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the draft versions")?;
+    Ok(versions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diff;
+
+    #[test]
+    fn unchanged_content_has_an_empty_diff() {
+        assert_eq!(diff("Hello\nworld\n", "Hello\nworld\n"), "");
+    }
+
+    #[test]
+    fn the_diff_shows_the_changed_lines() {
+        let diff = diff("Hello\nworld\n", "Hello\nthere\n");
+        assert!(diff.contains("-world\n"));
+        assert!(diff.contains("+there\n"));
+        assert!(diff.contains(" Hello\n"));
+    }
+}
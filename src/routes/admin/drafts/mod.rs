@@ -0,0 +1,138 @@
+mod get;
+mod post;
+pub use get::{draft_versions, edit_draft_form, list_drafts, new_draft_form};
+pub use post::{create_draft, publish_draft, save_draft, test_send_draft};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::personalization;
+
+/// The content of a draft, as it was saved at `version`
+pub struct DraftVersion {
+    pub version: i32,
+    pub title: String,
+    pub text_content: String,
+    pub html_content: String,
+    pub saved_at: DateTime<Utc>,
+    pub test_sent_to: Option<String>,
+    pub test_sent_at: Option<DateTime<Utc>>,
+}
+
+impl DraftVersion {
+    /// Test sends and publication need every field, and valid placeholders
+    fn validate(&self) -> Result<(), String> {
+        if [&self.title, &self.text_content, &self.html_content]
+            .iter()
+            .any(|field| field.trim().is_empty())
+        {
+            return Err("The title and both versions of the content are required.".into());
+        }
+        personalization::validate_bodies(&self.html_content, &self.text_content)
+    }
+}
+
+pub struct Draft {
+    pub draft_id: Uuid,
+    /// The issue the draft was published as, if any
+    pub newsletter_issue_id: Option<Uuid>,
+    pub latest: DraftVersion,
+}
+
+/// Fetches a draft with its latest version
+#[tracing::instrument(skip(executor))]
+async fn get_draft(
+    executor: impl PgExecutor<'_>,
+    draft_id: Uuid,
+) -> Result<Option<Draft>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            d.newsletter_issue_id,
+            v.version,
+            v.title,
+            v.text_content,
+            v.html_content,
+            v.saved_at,
+            v.test_sent_to,
+            v.test_sent_at
+        FROM newsletter_issue_drafts d
+        JOIN newsletter_issue_draft_versions v ON v.draft_id = d.draft_id
+        WHERE d.draft_id = $1
+        ORDER BY v.version DESC
+        LIMIT 1
+        "#,
+        draft_id
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to retrieve the draft")?;
+    Ok(row.map(|r| Draft {
+        draft_id,
+        newsletter_issue_id: r.newsletter_issue_id,
+        latest: DraftVersion {
+            version: r.version,
+            title: r.title,
+            text_content: r.text_content,
+            html_content: r.html_content,
+            saved_at: r.saved_at,
+            test_sent_to: r.test_sent_to,
+            test_sent_at: r.test_sent_at,
+        },
+    }))
+}
+
+/// Locks the draft until the end of the transaction, so that concurrent saves
+/// don't compete for the same version number. Returns `false` if the draft does not exist.
+async fn lock_draft(
+    transaction: &mut Transaction<'_, Postgres>,
+    draft_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let draft = sqlx::query!(
+        r#"
+        SELECT draft_id
+        FROM newsletter_issue_drafts
+        WHERE draft_id = $1
+        FOR UPDATE
+        "#,
+        draft_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to lock the draft")?;
+    Ok(draft.is_some())
+}
+
+async fn insert_draft_version(
+    transaction: &mut Transaction<'_, Postgres>,
+    draft_id: Uuid,
+    version: i32,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_draft_versions (
+            draft_id,
+            version,
+            title,
+            text_content,
+            html_content,
+            saved_at
+        )
+        VALUES ($1, $2, $3, $4, $5, now())
+        "#,
+        draft_id,
+        version,
+        title,
+        text_content,
+        html_content
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to store the draft version")?;
+    Ok(())
+}
@@ -0,0 +1,369 @@
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{get_draft, insert_draft_version, lock_draft};
+use crate::{
+    authentication::UserId,
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    html_sanitizer::HtmlSanitizer,
+    idempotency::{IdempotencyKey, NextAction, save_response, try_processing},
+    issue_scheduler::parse_scheduled_for,
+    mailing_lists::{DEFAULT_LIST_SLUG, get_list_by_slug},
+    personalization::{self, Recipient},
+    routes::{Audience, NewIssue, publish_issue, send_sanitization_warning},
+    segments::Segment,
+    utils::{e400, e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct DraftFormData {
+    title: String,
+    html_content: String,
+    text_content: String,
+}
+
+fn draft_url(draft_id: Uuid) -> String {
+    format!("/admin/drafts/{}", draft_id)
+}
+
+/// Stores a new draft, as its first version
+#[tracing::instrument(
+    name = "Create a draft",
+    skip(form, pool, user_id),
+    fields(user_id=%*user_id)
+)]
+pub async fn create_draft(
+    form: web::Form<DraftFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if form.title.trim().is_empty() {
+        FlashMessage::error("A draft needs a title.").send();
+        return Ok(see_other("/admin/drafts/new"));
+    }
+
+    let draft_id = Uuid::new_v4();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_drafts (draft_id, author_user_id, created_at)
+        VALUES ($1, $2, now())
+        "#,
+        draft_id,
+        **user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the draft")
+    .map_err(e500)?;
+    insert_draft_version(
+        &mut transaction,
+        draft_id,
+        1,
+        &form.title,
+        &form.text_content,
+        &form.html_content,
+    )
+    .await
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new draft.")
+        .map_err(e500)?;
+
+    FlashMessage::info("The draft has been saved as version 1.").send();
+    Ok(see_other(&draft_url(draft_id)))
+}
+
+/// Saves the edited draft as a new version
+#[tracing::instrument(name = "Save a draft", skip(form, pool))]
+pub async fn save_draft(
+    draft_id: web::Path<Uuid>,
+    form: web::Form<DraftFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft_id = draft_id.into_inner();
+    if form.title.trim().is_empty() {
+        FlashMessage::error("A draft needs a title.").send();
+        return Ok(see_other(&draft_url(draft_id)));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    if !lock_draft(&mut transaction, draft_id).await.map_err(e500)? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let draft = get_draft(&mut *transaction, draft_id)
+        .await
+        .map_err(e500)?
+        .context("The draft has no version")
+        .map_err(e500)?;
+    if draft.newsletter_issue_id.is_some() {
+        FlashMessage::error("The draft has already been published, it can't be edited.").send();
+        return Ok(see_other(&draft_url(draft_id)));
+    }
+    let latest = &draft.latest;
+    if latest.title == form.title
+        && latest.text_content == form.text_content
+        && latest.html_content == form.html_content
+    {
+        FlashMessage::info("There are no changes to save.").send();
+        return Ok(see_other(&draft_url(draft_id)));
+    }
+
+    let version = latest.version + 1;
+    insert_draft_version(
+        &mut transaction,
+        draft_id,
+        version,
+        &form.title,
+        &form.text_content,
+        &form.html_content,
+    )
+    .await
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to save a draft.")
+        .map_err(e500)?;
+
+    FlashMessage::info(format!("The draft has been saved as version {}.", version)).send();
+    Ok(see_other(&draft_url(draft_id)))
+}
+
+#[derive(serde::Deserialize)]
+pub struct TestSendFormData {
+    email: String,
+}
+
+/// Sends the latest version of the draft to a single address, to check how it looks
+/// in a mail client before it goes out to every subscriber
+#[tracing::instrument(
+    name = "Send a test of a draft",
+    skip(form, pool, email_client, sanitizer)
+)]
+pub async fn test_send_draft(
+    draft_id: web::Path<Uuid>,
+    form: web::Form<TestSendFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    sanitizer: web::Data<HtmlSanitizer>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft_id = draft_id.into_inner();
+    let recipient = match SubscriberEmail::parse(form.0.email) {
+        Ok(recipient) => recipient,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&draft_url(draft_id)));
+        }
+    };
+    let Some(draft) = get_draft(pool.get_ref(), draft_id).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let latest = &draft.latest;
+    if let Err(e) = latest.validate() {
+        FlashMessage::error(e).send();
+        return Ok(see_other(&draft_url(draft_id)));
+    }
+
+    // The test shows what the subscribers will get, as if it was sent to a subscriber
+    // named after the address
+    let sanitized = sanitizer.sanitize(&latest.html_content);
+    let test_recipient = Recipient {
+        name: recipient.as_ref(),
+        email: recipient.as_ref(),
+        unsubscribe_url: "#",
+    };
+    email_client
+        .send_email(
+            &recipient,
+            &format!("[Test] {}", latest.title),
+            &personalization::render_html(&sanitized.html, &test_recipient),
+            &personalization::render_text(&latest.text_content, &test_recipient),
+        )
+        .await
+        .context("Failed to send the test email")
+        .map_err(e500)?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issue_draft_versions
+        SET test_sent_to = $3, test_sent_at = now()
+        WHERE draft_id = $1 AND version = $2
+        "#,
+        draft_id,
+        latest.version,
+        recipient.as_ref()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to record the test send")
+    .map_err(e500)?;
+
+    FlashMessage::info(format!(
+        "Version {} has been sent to {}.",
+        latest.version,
+        recipient.as_ref()
+    ))
+    .send();
+    send_sanitization_warning(&sanitized.report);
+    Ok(see_other(&draft_url(draft_id)))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PublishFormData {
+    idempotency_key: String,
+    /// Left empty to publish right away
+    #[serde(default)]
+    scheduled_for: String,
+    /// Left empty to send to every subscriber of the list
+    #[serde(default)]
+    segment: String,
+    /// The slug of the list the draft goes out to, the main newsletter if omitted
+    list: Option<String>,
+}
+
+fn success_message(scheduled: bool) -> FlashMessage {
+    if scheduled {
+        FlashMessage::info("The draft has been scheduled for publication.")
+    } else {
+        FlashMessage::info("The draft has been published - emails will go out shortly.")
+    }
+}
+
+/// Publishes the latest version of the draft to the confirmed subscribers of a list
+#[tracing::instrument(
+    name = "Publish a draft",
+    skip(form, pool, sanitizer, user_id),
+    fields(user_id=%*user_id)
+)]
+pub async fn publish_draft(
+    draft_id: web::Path<Uuid>,
+    form: web::Form<PublishFormData>,
+    pool: web::Data<PgPool>,
+    sanitizer: web::Data<HtmlSanitizer>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft_id = draft_id.into_inner();
+    let user_id = user_id.into_inner();
+    let PublishFormData {
+        idempotency_key,
+        scheduled_for,
+        segment,
+        list,
+    } = form.0;
+    let scheduled_for = if scheduled_for.trim().is_empty() {
+        None
+    } else {
+        match parse_scheduled_for(&scheduled_for) {
+            Ok(scheduled_for) => Some(scheduled_for),
+            Err(e) => {
+                FlashMessage::error(e).send();
+                return Ok(see_other(&draft_url(draft_id)));
+            }
+        }
+    };
+
+    let list = list.unwrap_or_else(|| DEFAULT_LIST_SLUG.to_string());
+    let Some(list) = get_list_by_slug(pool.get_ref(), &list)
+        .await
+        .context("Failed to look up the list")
+        .map_err(e500)?
+    else {
+        FlashMessage::error(format!("'{}' is not a known list.", list)).send();
+        return Ok(see_other(&draft_url(draft_id)));
+    };
+    let segment = if segment.trim().is_empty() {
+        None
+    } else {
+        match Segment::parse(&segment) {
+            Ok(segment) => Some(segment),
+            Err(e) => {
+                FlashMessage::error(e).send();
+                return Ok(see_other(&draft_url(draft_id)));
+            }
+        }
+    };
+
+    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
+    let mut transaction = match try_processing(&pool, &idempotency_key, *user_id)
+        .await
+        .map_err(e500)?
+    {
+        NextAction::StartProcessing(t) => t,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            success_message(scheduled_for.is_some()).send();
+            return Ok(saved_response);
+        }
+    };
+
+    if !lock_draft(&mut transaction, draft_id).await.map_err(e500)? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let draft = get_draft(&mut *transaction, draft_id)
+        .await
+        .map_err(e500)?
+        .context("The draft has no version")
+        .map_err(e500)?;
+    if draft.newsletter_issue_id.is_some() {
+        FlashMessage::error("The draft has already been published.").send();
+        return Ok(see_other(&draft_url(draft_id)));
+    }
+    let latest = &draft.latest;
+    if let Err(e) = latest.validate() {
+        FlashMessage::error(e).send();
+        return Ok(see_other(&draft_url(draft_id)));
+    }
+
+    let published_issue = publish_issue(
+        &mut transaction,
+        *user_id,
+        &NewIssue {
+            title: &latest.title,
+            text_content: &latest.text_content,
+            html_content: &latest.html_content,
+        },
+        scheduled_for,
+        &Audience {
+            list_ids: &[list.list_id],
+            segment: segment.as_ref(),
+        },
+        &sanitizer,
+    )
+    .await
+    .context("Failed to publish the draft")
+    .map_err(e500)?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issue_drafts
+        SET newsletter_issue_id = $2
+        WHERE draft_id = $1
+        "#,
+        draft_id,
+        published_issue.newsletter_issue_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to mark the draft as published")
+    .map_err(e500)?;
+
+    let response = see_other(&draft_url(draft_id));
+    let response = save_response(transaction, &idempotency_key, *user_id, response)
+        .await
+        .map_err(e500)?;
+    success_message(scheduled_for.is_some()).send();
+    send_sanitization_warning(&published_issue.sanitization);
+    Ok(response)
+}
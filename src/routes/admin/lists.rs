@@ -0,0 +1,107 @@
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use askama::Template;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::ListSlug;
+use crate::utils::{e500, flash_messages, render_html, see_other};
+
+struct ListSummary {
+    slug: String,
+    name: String,
+    n_confirmed: i64,
+    n_pending: i64,
+}
+
+#[derive(Template)]
+#[template(path = "admin/lists.html")]
+struct ListsTemplate {
+    flash_messages: Vec<String>,
+    lists: Vec<ListSummary>,
+}
+
+/// The mailing lists, with how many people subscribed to each of them
+pub async fn lists(
+    incoming_flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let lists = get_list_summaries(&pool).await.map_err(e500)?;
+    render_html(&ListsTemplate {
+        flash_messages: flash_messages(&incoming_flash_messages),
+        lists,
+    })
+}
+
+#[derive(serde::Deserialize)]
+pub struct CreateListFormData {
+    slug: String,
+    name: String,
+}
+
+#[tracing::instrument(name = "Create a list", skip(form, pool))]
+pub async fn create_list(
+    form: web::Form<CreateListFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let CreateListFormData { slug, name } = form.0;
+    let slug = match ListSlug::parse(slug) {
+        Ok(slug) => slug,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/lists"));
+        }
+    };
+    if name.trim().is_empty() {
+        FlashMessage::error("A list needs a name.").send();
+        return Ok(see_other("/admin/lists"));
+    }
+
+    let n_inserted = sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, slug, name, created_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (slug) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        slug.as_ref(),
+        name.trim(),
+        Utc::now()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store the list")
+    .map_err(e500)?
+    .rows_affected();
+
+    if n_inserted > 0 {
+        FlashMessage::info(format!("The list '{}' has been created.", slug.as_ref())).send();
+    } else {
+        FlashMessage::error(format!("There is already a list '{}'.", slug.as_ref())).send();
+    }
+    Ok(see_other("/admin/lists"))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_list_summaries(pool: &PgPool) -> Result<Vec<ListSummary>, anyhow::Error> {
+    let lists = sqlx::query_as!(
+        ListSummary,
+        r#"
+        SELECT
+            l.slug,
+            l.name,
+            COUNT(*) FILTER (WHERE ls.status = 'confirmed') AS "n_confirmed!",
+            COUNT(*) FILTER (WHERE ls.status = 'pending_confirmation') AS "n_pending!"
+        FROM lists l
+        LEFT JOIN list_subscriptions ls ON ls.list_id = l.list_id
+        GROUP BY l.list_id
+        ORDER BY l.name, l.slug
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the lists")?;
+    Ok(lists)
+}
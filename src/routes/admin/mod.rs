@@ -0,0 +1,27 @@
+mod dashboard;
+mod dead_letters;
+mod drafts;
+mod lists;
+mod logout;
+mod newsletters;
+mod password;
+mod scheduled_issues;
+mod segments;
+mod subscribers;
+mod subscribers_export;
+mod subscribers_import;
+pub use dashboard::admin_dashboard;
+pub use dead_letters::{dead_letters, requeue_dead_letter};
+pub use drafts::*;
+pub use lists::{create_list, lists};
+pub use logout::log_out;
+pub use newsletters::*;
+pub use password::*;
+pub use scheduled_issues::{cancel_scheduled_issue, reschedule_issue, scheduled_issues};
+pub use segments::{segments, update_subscriber_tags};
+pub use subscribers::{
+    admin_confirm_subscriber, admin_delete_subscriber, admin_rename_subscriber, admin_subscriber,
+    admin_subscribers,
+};
+pub use subscribers_export::export_subscribers;
+pub use subscribers_import::{MAX_IMPORT_SIZE, import_subscribers, import_subscribers_form};
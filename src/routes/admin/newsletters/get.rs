@@ -0,0 +1,36 @@
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    mailing_lists::{MailingList, get_lists},
+    utils::{e500, flash_messages, render_html},
+};
+
+#[derive(Template)]
+#[template(path = "admin/newsletters.html")]
+struct PublishNewsletterTemplate {
+    flash_messages: Vec<String>,
+    idempotency_key: String,
+    lists: Vec<MailingList>,
+}
+
+pub async fn publish_newsletter_form(
+    incoming_flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let lists = get_lists(pool.get_ref())
+        .await
+        .context("Failed to retrieve the lists")
+        .map_err(e500)?;
+    render_html(&PublishNewsletterTemplate {
+        flash_messages: flash_messages(&incoming_flash_messages),
+        // Every rendering of the form gets a new key: submitting the same form twice
+        // (double click, browser retry...) publishes the issue only once
+        idempotency_key: Uuid::new_v4().to_string(),
+        lists,
+    })
+}
@@ -0,0 +1,163 @@
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::UserId,
+    html_sanitizer::{HtmlSanitizer, SanitizationReport},
+    idempotency::{IdempotencyKey, NextAction, save_response, try_processing},
+    issue_scheduler::parse_scheduled_for,
+    mailing_lists::{DEFAULT_LIST_SLUG, get_list_by_slug},
+    personalization,
+    routes::{Audience, NewIssue, publish_issue},
+    segments::Segment,
+    utils::{e400, e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    title: String,
+    html_content: String,
+    text_content: String,
+    idempotency_key: String,
+    /// Left empty to publish right away
+    #[serde(default)]
+    scheduled_for: String,
+    /// Left empty to send to every subscriber of the list
+    #[serde(default)]
+    segment: String,
+    /// The slug of the list the issue goes out to, the main newsletter if omitted
+    list: Option<String>,
+}
+
+fn success_message(scheduled: bool) -> FlashMessage {
+    if scheduled {
+        FlashMessage::info(
+            "The newsletter issue has been scheduled - \
+            emails will go out at the chosen time.",
+        )
+    } else {
+        FlashMessage::info(
+            "The newsletter issue has been accepted - \
+            emails will go out shortly.",
+        )
+    }
+}
+
+/// Tells the admin what the sanitizer removed from the HTML content, if anything
+pub(crate) fn send_sanitization_warning(report: &SanitizationReport) {
+    if !report.is_empty() {
+        FlashMessage::warning(format!(
+            "Some markup is not allowed and was removed from the HTML content: {}.",
+            report
+        ))
+        .send();
+    }
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue from the admin form",
+    skip(form, pool, sanitizer, user_id),
+    fields(user_id=%*user_id)
+)]
+pub async fn publish_newsletter_issue(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    sanitizer: web::Data<HtmlSanitizer>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let FormData {
+        title,
+        html_content,
+        text_content,
+        idempotency_key,
+        scheduled_for,
+        segment,
+        list,
+    } = form.0;
+
+    if [&title, &html_content, &text_content]
+        .iter()
+        .any(|field| field.trim().is_empty())
+    {
+        FlashMessage::error("The title and both versions of the content are required.").send();
+        return Ok(see_other("/admin/newsletters"));
+    }
+    if let Err(e) = personalization::validate_bodies(&html_content, &text_content) {
+        FlashMessage::error(e).send();
+        return Ok(see_other("/admin/newsletters"));
+    }
+    let scheduled_for = if scheduled_for.trim().is_empty() {
+        None
+    } else {
+        match parse_scheduled_for(&scheduled_for) {
+            Ok(scheduled_for) => Some(scheduled_for),
+            Err(e) => {
+                FlashMessage::error(e).send();
+                return Ok(see_other("/admin/newsletters"));
+            }
+        }
+    };
+
+    let list = list.unwrap_or_else(|| DEFAULT_LIST_SLUG.to_string());
+    let Some(list) = get_list_by_slug(pool.get_ref(), &list)
+        .await
+        .context("Failed to look up the list")
+        .map_err(e500)?
+    else {
+        FlashMessage::error(format!("'{}' is not a known list.", list)).send();
+        return Ok(see_other("/admin/newsletters"));
+    };
+    let segment = if segment.trim().is_empty() {
+        None
+    } else {
+        match Segment::parse(&segment) {
+            Ok(segment) => Some(segment),
+            Err(e) => {
+                FlashMessage::error(e).send();
+                return Ok(see_other("/admin/newsletters"));
+            }
+        }
+    };
+
+    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
+    let mut transaction = match try_processing(&pool, &idempotency_key, *user_id)
+        .await
+        .map_err(e500)?
+    {
+        NextAction::StartProcessing(t) => t,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            success_message(scheduled_for.is_some()).send();
+            return Ok(saved_response);
+        }
+    };
+
+    let published_issue = publish_issue(
+        &mut transaction,
+        *user_id,
+        &NewIssue {
+            title: &title,
+            text_content: &text_content,
+            html_content: &html_content,
+        },
+        scheduled_for,
+        &Audience {
+            list_ids: &[list.list_id],
+            segment: segment.as_ref(),
+        },
+        &sanitizer,
+    )
+    .await
+    .context("Failed to publish the newsletter issue")
+    .map_err(e500)?;
+
+    let response = see_other("/admin/newsletters");
+    let response = save_response(transaction, &idempotency_key, *user_id, response)
+        .await
+        .map_err(e500)?;
+    success_message(scheduled_for.is_some()).send();
+    send_sanitization_warning(&published_issue.sanitization);
+    Ok(response)
+}
@@ -0,0 +1,19 @@
+use actix_web::HttpResponse;
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+
+use crate::utils::{flash_messages, render_html};
+
+#[derive(Template)]
+#[template(path = "admin/password.html")]
+struct ChangePasswordTemplate {
+    flash_messages: Vec<String>,
+}
+
+pub async fn change_password_form(
+    incoming_flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render_html(&ChangePasswordTemplate {
+        flash_messages: flash_messages(&incoming_flash_messages),
+    })
+}
@@ -0,0 +1,133 @@
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use askama::Template;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::issue_scheduler::parse_scheduled_for;
+use crate::utils::{e500, flash_messages, render_html, see_other};
+
+struct ScheduledIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    scheduled_for: DateTime<Utc>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/scheduled_issues.html")]
+struct ScheduledIssuesTemplate {
+    flash_messages: Vec<String>,
+    scheduled_issues: Vec<ScheduledIssue>,
+}
+
+/// Lists the issues waiting for their publication time
+pub async fn scheduled_issues(
+    incoming_flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let scheduled_issues = get_scheduled_issues(&pool).await.map_err(e500)?;
+    render_html(&ScheduledIssuesTemplate {
+        flash_messages: flash_messages(&incoming_flash_messages),
+        scheduled_issues,
+    })
+}
+
+#[derive(serde::Deserialize)]
+pub struct CancelFormData {
+    newsletter_issue_id: Uuid,
+}
+
+/// Deletes a scheduled issue before it goes out
+#[tracing::instrument(name = "Cancel a scheduled issue", skip(form, pool))]
+pub async fn cancel_scheduled_issue(
+    form: web::Form<CancelFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    // Issues that have been released in the meantime are left alone
+    let n_cancelled = sqlx::query!(
+        r#"
+        DELETE FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1 AND
+            published_at IS NULL
+        "#,
+        form.newsletter_issue_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to delete a scheduled issue")
+    .map_err(e500)?
+    .rows_affected();
+
+    if n_cancelled > 0 {
+        FlashMessage::info("The scheduled issue has been cancelled.").send();
+    } else {
+        FlashMessage::error("The issue could not be found or has already been published.").send();
+    }
+    Ok(see_other("/admin/scheduled_issues"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct RescheduleFormData {
+    newsletter_issue_id: Uuid,
+    scheduled_for: String,
+}
+
+/// Moves the publication time of a scheduled issue
+#[tracing::instrument(name = "Reschedule an issue", skip(form, pool))]
+pub async fn reschedule_issue(
+    form: web::Form<RescheduleFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let scheduled_for = match parse_scheduled_for(&form.scheduled_for) {
+        Ok(scheduled_for) => scheduled_for,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/scheduled_issues"));
+        }
+    };
+    let n_rescheduled = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET scheduled_for = $2
+        WHERE
+            newsletter_issue_id = $1 AND
+            published_at IS NULL
+        "#,
+        form.newsletter_issue_id,
+        scheduled_for
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to reschedule an issue")
+    .map_err(e500)?
+    .rows_affected();
+
+    if n_rescheduled > 0 {
+        FlashMessage::info("The issue has been rescheduled.").send();
+    } else {
+        FlashMessage::error("The issue could not be found or has already been published.").send();
+    }
+    Ok(see_other("/admin/scheduled_issues"))
+}
+
+#[tracing::instrument(name = "Get scheduled issues", skip(pool))]
+async fn get_scheduled_issues(pool: &PgPool) -> Result<Vec<ScheduledIssue>, anyhow::Error> {
+    let scheduled_issues = sqlx::query_as!(
+        ScheduledIssue,
+        r#"
+        SELECT newsletter_issue_id, title, scheduled_for as "scheduled_for!"
+        FROM newsletter_issues
+        WHERE
+            published_at IS NULL AND
+            scheduled_for IS NOT NULL
+        ORDER BY scheduled_for
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the scheduled issues")?;
+    Ok(scheduled_issues)
+}
@@ -0,0 +1,294 @@
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use askama::Template;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    domain::{SubscriberAttribute, SubscriberEmail, SubscriberTag},
+    mailing_lists::{DEFAULT_LIST_SLUG, MailingList, get_list_by_slug, get_lists},
+    segments::{Segment, count_recipients},
+    utils::{e500, flash_messages, render_html, see_other},
+};
+
+struct TagUsage {
+    tag: String,
+    n_subscribers: i64,
+}
+
+#[derive(Template)]
+#[template(path = "admin/segments.html")]
+struct SegmentsTemplate {
+    flash_messages: Vec<String>,
+    lists: Vec<MailingList>,
+    tags: Vec<TagUsage>,
+    /// The slug of the list of the preview
+    list: String,
+    segment: String,
+    /// The outcome of the preview, if one was asked for
+    recipients: Option<usize>,
+    preview_error: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PreviewQuery {
+    list: Option<String>,
+    segment: Option<String>,
+}
+
+/// Previews how many subscribers of a list are in a segment, and lets admins
+/// tag subscribers
+pub async fn segments(
+    query: web::Query<PreviewQuery>,
+    incoming_flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let PreviewQuery { list, segment } = query.into_inner();
+    let segment = segment.unwrap_or_default();
+    let (recipients, preview_error) = match &list {
+        Some(list) => match preview(&pool, list, &segment).await.map_err(e500)? {
+            Ok(recipients) => (Some(recipients), None),
+            Err(e) => (None, Some(e)),
+        },
+        None => (None, None),
+    };
+    let lists = get_lists(pool.get_ref())
+        .await
+        .context("Failed to retrieve the lists")
+        .map_err(e500)?;
+    let tags = get_tag_usage(&pool).await.map_err(e500)?;
+    render_html(&SegmentsTemplate {
+        flash_messages: flash_messages(&incoming_flash_messages),
+        lists,
+        tags,
+        list: list.unwrap_or_else(|| DEFAULT_LIST_SLUG.to_string()),
+        segment,
+        recipients,
+        preview_error,
+    })
+}
+
+/// The number of recipients, or what is wrong with the list or the segment
+async fn preview(
+    pool: &PgPool,
+    list: &str,
+    segment: &str,
+) -> Result<Result<usize, String>, anyhow::Error> {
+    let Some(list) = get_list_by_slug(pool, list)
+        .await
+        .context("Failed to look up the list")?
+    else {
+        return Ok(Err(format!("'{}' is not a known list.", list)));
+    };
+    let segment = if segment.trim().is_empty() {
+        None
+    } else {
+        match Segment::parse(segment) {
+            Ok(segment) => Some(segment),
+            Err(e) => return Ok(Err(e)),
+        }
+    };
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let recipients = count_recipients(&mut connection, &[list.list_id], segment.as_ref())
+        .await
+        .context("Failed to count the recipients")?;
+    Ok(Ok(recipients))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_tag_usage(pool: &PgPool) -> Result<Vec<TagUsage>, anyhow::Error> {
+    let tags = sqlx::query_as!(
+        TagUsage,
+        r#"
+        SELECT tag, COUNT(*) AS "n_subscribers!"
+        FROM subscriber_tags
+        GROUP BY tag
+        ORDER BY tag
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the tags")?;
+    Ok(tags)
+}
+
+#[derive(serde::Deserialize)]
+pub struct SubscriberTagsFormData {
+    email: String,
+    #[serde(default)]
+    add_tags: String,
+    #[serde(default)]
+    remove_tags: String,
+    /// One `name = value` per line, an empty value removes the attribute
+    #[serde(default)]
+    attributes: String,
+}
+
+/// The attributes to set, and the names of the ones to remove
+fn parse_attributes(s: &str) -> Result<(Vec<SubscriberAttribute>, Vec<String>), String> {
+    let mut to_set = Vec::new();
+    let mut to_remove = Vec::new();
+    for line in s.lines().filter(|line| !line.trim().is_empty()) {
+        let Some((name, value)) = line.split_once('=') else {
+            return Err("Attributes are set with one 'name = value' per line.".into());
+        };
+        if value.trim().is_empty() {
+            let name = SubscriberTag::parse(name.to_string()).map_err(
+                |_| "Attribute names can only use letters, digits, dashes and underscores.",
+            )?;
+            to_remove.push(name.as_ref().to_string());
+        } else {
+            to_set.push(SubscriberAttribute::parse(
+                name.to_string(),
+                value.to_string(),
+            )?);
+        }
+    }
+    Ok((to_set, to_remove))
+}
+
+/// Adds and removes tags of a subscriber, and sets or removes their attributes
+#[tracing::instrument(
+    name = "Update the tags of a subscriber",
+    skip(form, pool),
+    fields(subscriber_email = %form.email)
+)]
+pub async fn update_subscriber_tags(
+    form: web::Form<SubscriberTagsFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let SubscriberTagsFormData {
+        email,
+        add_tags,
+        remove_tags,
+        attributes,
+    } = form.0;
+    let parsed = SubscriberEmail::parse(email).and_then(|email| {
+        Ok((
+            email,
+            SubscriberTag::parse_list(&add_tags)?,
+            SubscriberTag::parse_list(&remove_tags)?,
+            parse_attributes(&attributes)?,
+        ))
+    });
+    let (email, add_tags, remove_tags, (set_attributes, remove_attributes)) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/segments"));
+        }
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let Some(subscriber) = sqlx::query!(
+        "SELECT id FROM subscriptions WHERE email = $1",
+        email.as_ref()
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the subscriber")
+    .map_err(e500)?
+    else {
+        FlashMessage::error(format!(
+            "There is no subscriber with the address {}.",
+            email
+        ))
+        .send();
+        return Ok(see_other("/admin/segments"));
+    };
+    store_tags(&mut transaction, subscriber.id, &add_tags, &remove_tags)
+        .await
+        .context("Failed to store the tags of the subscriber")
+        .map_err(e500)?;
+    store_attributes(
+        &mut transaction,
+        subscriber.id,
+        &set_attributes,
+        &remove_attributes,
+    )
+    .await
+    .context("Failed to store the attributes of the subscriber")
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update the tags of a subscriber.")
+        .map_err(e500)?;
+
+    FlashMessage::info(format!(
+        "The tags and attributes of {} have been updated.",
+        email
+    ))
+    .send();
+    Ok(see_other("/admin/segments"))
+}
+
+async fn store_tags(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    add_tags: &[SubscriberTag],
+    remove_tags: &[SubscriberTag],
+) -> Result<(), sqlx::Error> {
+    let as_strings = |tags: &[SubscriberTag]| {
+        tags.iter()
+            .map(|t| t.as_ref().to_string())
+            .collect::<Vec<_>>()
+    };
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO subscriber_tags (subscriber_id, tag)
+        SELECT $1, tag
+        FROM UNNEST($2::text[]) AS tag
+        ON CONFLICT DO NOTHING
+        "#,
+        subscriber_id,
+        &as_strings(add_tags)
+    );
+    transaction.execute(query).await?;
+    let query = sqlx::query!(
+        "DELETE FROM subscriber_tags WHERE subscriber_id = $1 AND tag = ANY($2)",
+        subscriber_id,
+        &as_strings(remove_tags)
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+async fn store_attributes(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    set_attributes: &[SubscriberAttribute],
+    remove_attributes: &[String],
+) -> Result<(), sqlx::Error> {
+    let (names, values): (Vec<_>, Vec<_>) = set_attributes
+        .iter()
+        .map(|a| (a.name().to_string(), a.value().to_string()))
+        .unzip();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO subscriber_attributes (subscriber_id, name, value)
+        SELECT $1, name, value
+        FROM UNNEST($2::text[], $3::text[]) AS a(name, value)
+        ON CONFLICT (subscriber_id, name) DO UPDATE SET value = EXCLUDED.value
+        "#,
+        subscriber_id,
+        &names,
+        &values
+    );
+    transaction.execute(query).await?;
+    let query = sqlx::query!(
+        "DELETE FROM subscriber_attributes WHERE subscriber_id = $1 AND name = ANY($2)",
+        subscriber_id,
+        remove_attributes
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
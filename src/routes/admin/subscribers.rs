@@ -0,0 +1,170 @@
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    domain::SubscriberName,
+    mailing_lists::{MailingList, get_lists},
+    subscribers::{
+        ManualConfirmError, STATUSES, Subscriber, SubscriberQuery, confirm_list_subscription,
+        delete_subscriber, get_subscriber, list_subscribers, rename_subscriber,
+    },
+    utils::{e500, flash_messages, render_html, see_other},
+};
+
+#[derive(Template)]
+#[template(path = "admin/subscribers/list.html")]
+struct SubscribersTemplate {
+    flash_messages: Vec<String>,
+    lists: Vec<MailingList>,
+    statuses: [&'static str; 3],
+    query: SubscriberQuery,
+    subscribers: Vec<Subscriber>,
+    /// The link to the next page, if there is one
+    next_page: Option<String>,
+    query_error: Option<String>,
+}
+
+impl SubscribersTemplate {
+    /// What the search form shows in a field
+    fn value<'a>(&self, field: &'a Option<String>) -> &'a str {
+        field.as_deref().unwrap_or_default()
+    }
+}
+
+/// The subscribers, filtered by the search form, a page at a time
+pub async fn admin_subscribers(
+    query: web::Query<SubscriberQuery>,
+    incoming_flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let query = query.into_inner();
+    let (subscribers, next_page, query_error) = match query.parse() {
+        Ok((filter, cursor, limit)) => {
+            let page = list_subscribers(pool.get_ref(), &filter, cursor.as_ref(), limit)
+                .await
+                .context("Failed to list the subscribers")
+                .map_err(e500)?;
+            let next_page = page
+                .next_cursor
+                .map(|cursor| format!("/admin/subscribers?{}", query.with_cursor(&cursor)));
+            (page.subscribers, next_page, None)
+        }
+        Err(e) => (vec![], None, Some(e)),
+    };
+    let lists = get_lists(pool.get_ref())
+        .await
+        .context("Failed to retrieve the lists")
+        .map_err(e500)?;
+    render_html(&SubscribersTemplate {
+        flash_messages: flash_messages(&incoming_flash_messages),
+        lists,
+        statuses: STATUSES,
+        query,
+        subscribers,
+        next_page,
+        query_error,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "admin/subscribers/show.html")]
+struct SubscriberTemplate {
+    flash_messages: Vec<String>,
+    subscriber: Subscriber,
+}
+
+/// A subscriber with their lists, and the forms to edit or delete them
+pub async fn admin_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    incoming_flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(subscriber) = get_subscriber(pool.get_ref(), *subscriber_id)
+        .await
+        .context("Failed to retrieve the subscriber")
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    render_html(&SubscriberTemplate {
+        flash_messages: flash_messages(&incoming_flash_messages),
+        subscriber,
+    })
+}
+
+#[derive(serde::Deserialize)]
+pub struct RenameSubscriberFormData {
+    name: String,
+}
+
+#[tracing::instrument(name = "Rename a subscriber", skip(form, pool))]
+pub async fn admin_rename_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<RenameSubscriberFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let location = format!("/admin/subscribers/{}", subscriber_id);
+    let name = match SubscriberName::parse(form.0.name) {
+        Ok(name) => name,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&location));
+        }
+    };
+    let renamed = rename_subscriber(pool.get_ref(), *subscriber_id, &name)
+        .await
+        .context("Failed to rename the subscriber")
+        .map_err(e500)?;
+    if !renamed {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    FlashMessage::info("The name has been changed.").send();
+    Ok(see_other(&location))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ConfirmSubscriberFormData {
+    list: String,
+}
+
+#[tracing::instrument(name = "Confirm a subscriber manually", skip(form, pool))]
+pub async fn admin_confirm_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<ConfirmSubscriberFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let list = form.0.list;
+    match confirm_list_subscription(&pool, *subscriber_id, &list).await {
+        Ok(()) => FlashMessage::info(format!(
+            "The subscription to '{}' has been confirmed.",
+            list
+        ))
+        .send(),
+        Err(ManualConfirmError::UnexpectedError(e)) => return Err(e500(e)),
+        Err(e) => FlashMessage::error(e.to_string()).send(),
+    }
+    Ok(see_other(&format!("/admin/subscribers/{}", subscriber_id)))
+}
+
+#[tracing::instrument(name = "Delete a subscriber", skip(pool))]
+pub async fn admin_delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(subscriber) = get_subscriber(pool.get_ref(), *subscriber_id)
+        .await
+        .context("Failed to retrieve the subscriber")
+        .map_err(e500)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    delete_subscriber(&pool, subscriber.id)
+        .await
+        .map_err(e500)?;
+    FlashMessage::info(format!("{} has been deleted.", subscriber.email)).send();
+    Ok(see_other("/admin/subscribers"))
+}
@@ -0,0 +1,209 @@
+use std::borrow::Cow;
+
+use actix_web::{
+    HttpResponse,
+    http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType},
+    web::{self, Bytes},
+};
+use anyhow::Context;
+use futures_util::{StreamExt, stream};
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+
+use crate::{
+    subscribers::{Subscriber, SubscriberFilter, SubscriberQuery, stream_subscribers},
+    utils::e400,
+};
+
+/// How many chunks can wait for a slow client: with `CHUNK_SIZE`, it bounds the
+/// memory an export takes
+const BUFFERED_CHUNKS: usize = 16;
+/// Rows are sent to the client in chunks of about this many bytes
+const CHUNK_SIZE: usize = 16 * 1024;
+
+#[derive(serde::Deserialize)]
+pub struct ExportQuery {
+    /// `csv` (the default) or `json`
+    format: Option<String>,
+    status: Option<String>,
+    list: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    fn parse(s: Option<&str>) -> Result<ExportFormat, String> {
+        match s.unwrap_or("csv") {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            other => Err(format!(
+                "'{}' is not an export format, use csv or json.",
+                other
+            )),
+        }
+    }
+
+    fn content_type(self) -> ContentType {
+        match self {
+            ExportFormat::Csv => ContentType(mime_csv()),
+            ExportFormat::Json => ContentType::json(),
+        }
+    }
+
+    fn file_name(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "subscribers.csv",
+            ExportFormat::Json => "subscribers.json",
+        }
+    }
+
+    fn header(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "id,email,name,subscribed_at,lists\n",
+            ExportFormat::Json => "[",
+        }
+    }
+
+    fn footer(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "",
+            ExportFormat::Json => "]\n",
+        }
+    }
+
+    /// Appends a subscriber to `buffer`, `first` if nothing was written before
+    fn write(
+        self,
+        buffer: &mut Vec<u8>,
+        subscriber: &Subscriber,
+        first: bool,
+    ) -> Result<(), anyhow::Error> {
+        match self {
+            ExportFormat::Csv => {
+                // e.g. `newsletter:confirmed rust:pending_confirmation`
+                let lists = subscriber
+                    .lists
+                    .iter()
+                    .map(|l| format!("{}:{}", l.list, l.status))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let mut writer = csv::Writer::from_writer(buffer);
+                writer.write_record(
+                    [
+                        subscriber.id.to_string().as_str(),
+                        &subscriber.email,
+                        &subscriber.name,
+                        &subscriber.subscribed_at.to_rfc3339(),
+                        &lists,
+                    ]
+                    .map(csv_cell)
+                    .iter()
+                    .map(|cell| cell.as_bytes()),
+                )?;
+                writer.flush()?;
+            }
+            ExportFormat::Json => {
+                if !first {
+                    buffer.push(b',');
+                }
+                serde_json::to_writer(buffer, subscriber)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Spreadsheets run the cells that start like a formula, e.g. a subscriber named
+/// `=HYPERLINK(...)`: a leading `'` makes them plain text
+fn csv_cell(value: &str) -> Cow<'_, str> {
+    if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        Cow::Owned(format!("'{}", value))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+fn mime_csv() -> actix_web::mime::Mime {
+    "text/csv; charset=utf-8".parse().unwrap()
+}
+
+/// Every subscriber matching the query, as a CSV or a JSON array.
+///
+/// The rows are streamed from the database to the client as they come, so that
+/// exporting a large list doesn't load it in memory.
+#[tracing::instrument(name = "Export subscribers", skip(query, pool))]
+pub async fn export_subscribers(
+    query: web::Query<ExportQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let ExportQuery {
+        format,
+        status,
+        list,
+    } = query.into_inner();
+    let format = ExportFormat::parse(format.as_deref()).map_err(e400)?;
+    let (filter, _, _) = SubscriberQuery {
+        status,
+        list,
+        ..Default::default()
+    }
+    .parse()
+    .map_err(e400)?;
+
+    // The body of the response outlives this handler: the rows are read by a
+    // task of their own, which hands them over through a bounded channel
+    let (sender, receiver) = mpsc::channel(BUFFERED_CHUNKS);
+    tokio::spawn(write_export(pool.get_ref().clone(), filter, format, sender));
+    let body = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format.file_name().into())],
+        })
+        .streaming(body))
+}
+
+type Chunk = Result<Bytes, anyhow::Error>;
+
+async fn write_export(
+    pool: PgPool,
+    filter: SubscriberFilter,
+    format: ExportFormat,
+    sender: mpsc::Sender<Chunk>,
+) {
+    let mut subscribers = stream_subscribers(&pool, &filter, None, None);
+    let mut buffer = format.header().as_bytes().to_vec();
+    let mut first = true;
+    while let Some(subscriber) = subscribers.next().await {
+        let written = subscriber
+            .context("Failed to read the subscribers to export")
+            .and_then(|subscriber| format.write(&mut buffer, &subscriber, first));
+        if let Err(e) = written {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to export the subscribers"
+            );
+            // The response is cut short, the client can't mistake it for a full export
+            let _ = sender.send(Err(e)).await;
+            return;
+        }
+        first = false;
+        if buffer.len() >= CHUNK_SIZE {
+            let chunk = Bytes::from(std::mem::take(&mut buffer));
+            if sender.send(Ok(chunk)).await.is_err() {
+                // The client went away
+                return;
+            }
+        }
+    }
+    buffer.extend_from_slice(format.footer().as_bytes());
+    let _ = sender.send(Ok(Bytes::from(buffer))).await;
+}
@@ -0,0 +1,477 @@
+use std::collections::{HashMap, HashSet};
+
+use actix_web::{HttpRequest, HttpResponse, web};
+use anyhow::Context;
+use askama::Template;
+use secrecy::Secret;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    domain::{NewSubscriber, SubscriberEmail, SubscriberName, SubscriberTag},
+    email_client::EmailClient,
+    mailing_lists::{DEFAULT_LIST_SLUG, MailingList, get_list_by_slug, get_lists},
+    personal_data::{record_consent_event, suppressed_emails},
+    routes::{generate_subscription_token, send_confirmation_email, store_token},
+    startup::{ApplicationBaseUrl, SuppressionKey},
+    subscribers::STATUSES,
+    utils::{e500, prefers_json, render_html},
+};
+
+/// How large the CSV of an import can be, tens of thousands of rows
+pub const MAX_IMPORT_SIZE: usize = 5 * 1024 * 1024;
+
+#[derive(Template)]
+#[template(path = "admin/subscribers/import.html")]
+struct ImportTemplate {
+    lists: Vec<MailingList>,
+    report: Option<ImportReport>,
+    error: Option<String>,
+}
+
+pub async fn import_subscribers_form(
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    render_import_page(&pool, None, None).await
+}
+
+async fn render_import_page(
+    pool: &PgPool,
+    report: Option<ImportReport>,
+    error: Option<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let lists = get_lists(pool)
+        .await
+        .context("Failed to retrieve the lists")
+        .map_err(e500)?;
+    render_html(&ImportTemplate {
+        lists,
+        report,
+        error,
+    })
+}
+
+#[derive(serde::Deserialize)]
+pub struct ImportFormData {
+    /// With a header row: `email` and `name`, optionally `status` and `tags`
+    csv: String,
+    /// The slug of the list to import into, the main newsletter if omitted
+    list: Option<String>,
+    /// Sends a confirmation email to the subscribers imported as pending
+    #[serde(default)]
+    send_confirmation: bool,
+}
+
+#[derive(serde::Deserialize)]
+struct CsvRow {
+    email: String,
+    name: String,
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    tags: Option<String>,
+}
+
+struct ValidRow {
+    new_subscriber: NewSubscriber,
+    status: &'static str,
+    tags: Vec<SubscriberTag>,
+}
+
+/// A row of the CSV, `Err` with the reason if it can't be imported
+struct ParsedRow {
+    line: u64,
+    email: String,
+    row: Result<ValidRow, String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ImportReport {
+    list: String,
+    accepted: usize,
+    rejected: usize,
+    rows: Vec<RowReport>,
+}
+
+#[derive(serde::Serialize)]
+pub struct RowReport {
+    /// The line of the CSV, the header row is line 1
+    line: u64,
+    email: String,
+    /// `accepted` or `rejected`
+    outcome: &'static str,
+    message: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct ErrorBody {
+    message: String,
+}
+
+/// Imports subscribers from a CSV into a list.
+///
+/// Every row is validated on its own: the valid ones are imported, and the report
+/// says what happened to each row. Addresses that are already subscribed, that
+/// appear twice in the file, or whose owner asked to be erased, are rejected.
+/// The report is an HTML page, or JSON for clients that ask for it.
+#[tracing::instrument(
+    name = "Import subscribers",
+    skip(request, form, pool, email_client, base_url, suppression_key),
+    fields(list = ?form.list, send_confirmation = form.send_confirmation)
+)]
+pub async fn import_subscribers(
+    request: HttpRequest,
+    form: web::Form<ImportFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    suppression_key: web::Data<SuppressionKey>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let json = prefers_json(&request);
+    let ImportFormData {
+        csv,
+        list,
+        send_confirmation,
+    } = form.0;
+    let list_slug = list
+        .filter(|list| !list.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_LIST_SLUG.to_string());
+    let list = get_list_by_slug(pool.get_ref(), &list_slug)
+        .await
+        .context("Failed to look up the list")
+        .map_err(e500)?;
+    let (list, rows) = match (list, parse_csv(&csv)) {
+        (Some(list), Ok(rows)) => (list, rows),
+        (None, _) => {
+            let error = format!("'{}' is not a known list.", list_slug);
+            return import_error(&pool, json, error).await;
+        }
+        (_, Err(error)) => return import_error(&pool, json, error).await,
+    };
+    let rows = reject_duplicates(&pool, rows, &suppression_key.0)
+        .await
+        .map_err(e500)?;
+
+    let mut report = ImportReport {
+        list: list.slug.clone(),
+        accepted: 0,
+        rejected: 0,
+        rows: Vec::with_capacity(rows.len()),
+    };
+    // The index of the row in the report, with what its confirmation email needs
+    let mut confirmations = vec![];
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    for ParsedRow { line, email, row } in rows {
+        let row = match row {
+            Ok(row) => row,
+            Err(message) => {
+                report.rejected += 1;
+                report.rows.push(RowReport {
+                    line,
+                    email,
+                    outcome: "rejected",
+                    message: Some(message),
+                });
+                continue;
+            }
+        };
+        let Some(subscriber_id) = insert_imported_subscriber(&mut transaction, &row, list.list_id)
+            .await
+            .context("Failed to store an imported subscriber")
+            .map_err(e500)?
+        else {
+            report.rejected += 1;
+            report.rows.push(RowReport {
+                line,
+                email,
+                outcome: "rejected",
+                message: Some(ALREADY_SUBSCRIBED.into()),
+            });
+            continue;
+        };
+        if send_confirmation && row.status == "pending_confirmation" {
+            let subscription_token = generate_subscription_token();
+            store_token(
+                &mut transaction,
+                subscriber_id,
+                list.list_id,
+                &subscription_token,
+            )
+            .await
+            .context("Failed to store the confirmation token of an imported subscriber")
+            .map_err(e500)?;
+            confirmations.push((report.rows.len(), row.new_subscriber, subscription_token));
+        }
+        report.accepted += 1;
+        report.rows.push(RowReport {
+            line,
+            email,
+            outcome: "accepted",
+            message: None,
+        });
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to import subscribers.")
+        .map_err(e500)?;
+
+    // The subscribers are imported either way, a failed email can be fixed
+    // by subscribing again
+    for (index, new_subscriber, subscription_token) in confirmations {
+        if let Err(e) = send_confirmation_email(
+            &email_client,
+            new_subscriber,
+            &list,
+            &base_url.0,
+            &subscription_token,
+        )
+        .await
+        {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send the confirmation email of an imported subscriber"
+            );
+            report.rows[index].message =
+                Some("Imported, but the confirmation email could not be sent.".into());
+        }
+    }
+
+    if json {
+        return Ok(HttpResponse::Ok().json(report));
+    }
+    render_import_page(&pool, Some(report), None).await
+}
+
+/// The import can't even start: the list or the file is wrong
+async fn import_error(
+    pool: &PgPool,
+    json: bool,
+    message: String,
+) -> Result<HttpResponse, actix_web::Error> {
+    if json {
+        return Ok(HttpResponse::BadRequest().json(ErrorBody { message }));
+    }
+    render_import_page(pool, None, Some(message)).await
+}
+
+/// Reads and validates the rows, the header row is required
+fn parse_csv(csv: &str) -> Result<Vec<ParsedRow>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(csv.as_bytes());
+    let headers: csv::StringRecord = reader
+        .headers()
+        .map_err(|e| format!("The CSV could not be read: {}", e))?
+        .iter()
+        .map(|header| header.to_lowercase())
+        .collect();
+    if !["email", "name"]
+        .iter()
+        .all(|column| headers.iter().any(|header| header == *column))
+    {
+        return Err("The first row of the CSV must name its columns: \
+            email and name, optionally status and tags."
+            .into());
+    }
+
+    let mut rows = vec![];
+    for record in reader.records() {
+        let parsed = match record {
+            Ok(record) => {
+                let line = record.position().map_or(0, |p| p.line());
+                match record.deserialize::<CsvRow>(Some(&headers)) {
+                    Ok(row) => ParsedRow {
+                        line,
+                        email: row.email.clone(),
+                        row: validate_row(row),
+                    },
+                    Err(e) => ParsedRow {
+                        line,
+                        email: String::new(),
+                        row: Err(format!("The row could not be read: {}", e)),
+                    },
+                }
+            }
+            Err(e) => ParsedRow {
+                line: e.position().map_or(0, |p| p.line()),
+                email: String::new(),
+                row: Err(format!("The row could not be read: {}", e)),
+            },
+        };
+        rows.push(parsed);
+    }
+    Ok(rows)
+}
+
+fn validate_row(row: CsvRow) -> Result<ValidRow, String> {
+    let email = SubscriberEmail::parse(row.email)?;
+    let name = SubscriberName::parse(row.name)?;
+    let status = match row.status.as_deref().filter(|s| !s.is_empty()) {
+        None => "pending_confirmation",
+        Some(status) => STATUSES.into_iter().find(|s| *s == status).ok_or_else(|| {
+            format!(
+                "'{}' is not a status, use one of {}.",
+                status,
+                STATUSES.join(", ")
+            )
+        })?,
+    };
+    let tags = SubscriberTag::parse_list(row.tags.as_deref().unwrap_or_default())?;
+    Ok(ValidRow {
+        new_subscriber: NewSubscriber { email, name },
+        status,
+        tags,
+    })
+}
+
+const ALREADY_SUBSCRIBED: &str = "There is already a subscriber with this address.";
+
+/// Rejects the addresses that are already subscribers, the suppressed ones and the
+/// repeated ones
+async fn reject_duplicates(
+    pool: &PgPool,
+    mut rows: Vec<ParsedRow>,
+    suppression_key: &Secret<String>,
+) -> Result<Vec<ParsedRow>, anyhow::Error> {
+    let emails: Vec<String> = rows
+        .iter()
+        .filter(|r| r.row.is_ok())
+        .map(|r| r.email.clone())
+        .collect();
+    let normalized_emails: Vec<String> = emails
+        .iter()
+        .map(|e| SubscriberEmail::normalize(e))
+        .collect();
+    // Case-insensitive, like the suppressions
+    let existing: HashSet<String> = sqlx::query_scalar!(
+        r#"SELECT lower(email) AS "email!" FROM subscriptions WHERE lower(email) = ANY($1)"#,
+        &normalized_emails
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to look up the existing subscribers")?
+    .into_iter()
+    .collect();
+    let suppressed = suppressed_emails(pool, &emails, suppression_key)
+        .await
+        .context("Failed to look up the suppressed addresses")?;
+    let mut first_lines = HashMap::new();
+    for row in rows.iter_mut().filter(|r| r.row.is_ok()) {
+        let email = SubscriberEmail::normalize(&row.email);
+        if existing.contains(&email) {
+            row.row = Err(ALREADY_SUBSCRIBED.into());
+        } else if suppressed.contains(&row.email) {
+            row.row = Err("The owner of this address asked for their data to be erased.".into());
+        } else if let Some(first_line) = first_lines.get(&email) {
+            row.row = Err(format!("The address is already on line {}.", first_line));
+        } else {
+            first_lines.insert(email, row.line);
+        }
+    }
+    Ok(rows)
+}
+
+/// `None` if somebody subscribed with the same address since `reject_duplicates`
+#[tracing::instrument(skip_all)]
+async fn insert_imported_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    row: &ValidRow,
+    list_id: Uuid,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let subscriber_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at)
+        VALUES ($1, $2, $3, now())
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id
+        "#,
+        Uuid::new_v4(),
+        row.new_subscriber.email.as_ref(),
+        row.new_subscriber.name.as_ref()
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    let Some(subscriber_id) = subscriber_id else {
+        return Ok(None);
+    };
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+        VALUES ($1, $2, $3, now())
+        "#,
+        list_id,
+        subscriber_id,
+        row.status
+    );
+    transaction.execute(query).await?;
+    record_consent_event(transaction, subscriber_id, list_id, "imported").await?;
+    let tags: Vec<String> = row.tags.iter().map(|t| t.as_ref().to_string()).collect();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO subscriber_tags (subscriber_id, tag)
+        SELECT $1, tag
+        FROM UNNEST($2::text[]) AS tag
+        ON CONFLICT DO NOTHING
+        "#,
+        subscriber_id,
+        &tags
+    );
+    transaction.execute(query).await?;
+    Ok(Some(subscriber_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_csv;
+
+    fn outcomes(csv: &str) -> Vec<(u64, Result<(), String>)> {
+        parse_csv(csv)
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.line, r.row.map(|_| ())))
+            .collect()
+    }
+
+    #[test]
+    fn rows_are_validated_one_by_one() {
+        let csv = "email,name,status,tags\n\
+            ursula@example.com,Ursula,confirmed,\"beta, paid\"\n\
+            not-an-email,Someone,,\n\
+            le_guin@example.com,,,\n\
+            earthsea@example.com,Ged,active,\n\
+            ged@example.com,Ged,,beta!\n";
+        let outcomes = outcomes(csv);
+        assert_eq!(outcomes.len(), 5);
+        assert_eq!(outcomes[0], (2, Ok(())));
+        for (i, (line, outcome)) in outcomes.iter().enumerate().skip(1) {
+            assert_eq!(*line, i as u64 + 2);
+            assert!(outcome.is_err(), "Line {} should be rejected", line);
+        }
+    }
+
+    #[test]
+    fn status_and_tags_columns_are_optional() {
+        let csv = "Name,Email\nUrsula,ursula@example.com\n";
+        assert_eq!(outcomes(csv), vec![(2, Ok(()))]);
+    }
+
+    #[test]
+    fn the_header_row_is_required() {
+        assert!(parse_csv("ursula@example.com,Ursula\n").is_err());
+        assert!(parse_csv("").is_err());
+    }
+
+    #[test]
+    fn rows_with_missing_fields_are_rejected() {
+        let csv = "email,name\nursula@example.com\n";
+        let outcomes = outcomes(csv);
+        assert_eq!(outcomes.len(), 1);
+        assert!(outcomes[0].1.is_err());
+    }
+}
@@ -0,0 +1,12 @@
+use actix_web::HttpResponse;
+use askama::Template;
+
+use crate::utils::render_html;
+
+#[derive(Template)]
+#[template(path = "home.html")]
+struct HomeTemplate;
+
+pub async fn home() -> Result<HttpResponse, actix_web::Error> {
+    render_html(&HomeTemplate)
+}
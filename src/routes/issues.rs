@@ -0,0 +1,105 @@
+use actix_web::{HttpResponse, web};
+use anyhow::Context;
+use askama::Template;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::mailing_lists::DEFAULT_LIST_SLUG;
+use crate::personalization::{self, Recipient};
+use crate::utils::{e500, render_html};
+
+pub struct IssueSummary {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub published_at: DateTime<Utc>,
+}
+
+#[derive(Template)]
+#[template(path = "issues/list.html")]
+struct IssuesTemplate {
+    issues: Vec<IssueSummary>,
+}
+
+/// Public archive of the published issues, the most recent first.
+/// Only the issues sent to the whole main newsletter are listed: the other lists may be
+/// private, and issues sent to a segment are meant for it only
+pub async fn list_issues(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_issues(&pool).await.map_err(e500)?;
+    render_html(&IssuesTemplate { issues })
+}
+
+pub struct Issue {
+    pub title: String,
+    pub html_content: String,
+    pub published_at: DateTime<Utc>,
+}
+
+#[derive(Template)]
+#[template(path = "issues/show.html")]
+struct IssueTemplate {
+    issue: Issue,
+}
+
+/// Web version of an issue, also linked from the emails ("view in browser").
+/// Issues sent to another list or to a segment are served too, to whoever has the link
+#[tracing::instrument(name = "Show a newsletter issue", skip(pool))]
+pub async fn show_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    match get_issue(&pool, *issue_id).await.map_err(e500)? {
+        Some(mut issue) => {
+            issue.html_content =
+                personalization::render_html(&issue.html_content, &Recipient::anonymous());
+            render_html(&IssueTemplate { issue })
+        }
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issues(pool: &PgPool) -> Result<Vec<IssueSummary>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT i.newsletter_issue_id, i.title, i.published_at as "published_at!"
+        FROM newsletter_issues i
+        WHERE
+            -- Scheduled issues are not public yet
+            i.published_at IS NOT NULL AND
+            i.segment IS NULL AND
+            EXISTS (
+                SELECT 1
+                FROM newsletter_issue_lists il
+                JOIN lists l ON l.list_id = il.list_id
+                WHERE il.newsletter_issue_id = i.newsletter_issue_id AND l.slug = $1
+            )
+        ORDER BY i.published_at DESC
+        "#,
+        DEFAULT_LIST_SLUG
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the newsletter issues")?;
+    Ok(issues)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<Option<Issue>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        Issue,
+        r#"
+        SELECT i.title, i.html_content, i.published_at as "published_at!"
+        FROM newsletter_issues i
+        WHERE
+            i.newsletter_issue_id = $1 AND
+            i.published_at IS NOT NULL
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the newsletter issue")?;
+    Ok(issue)
+}
@@ -1,42 +1,37 @@
-use actix_web::{HttpResponse, cookie::Cookie, http::header::ContentType, web};
-use actix_web_flash_messages::{IncomingFlashMessages, Level};
-use std::fmt::Write;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
 
-pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
-    let mut error_html = String::new();
-    for msg in flash_messages.iter().filter(|m| m.level() == Level::Error) {
-        writeln!(error_html, "<p><i>{}</i></p>", msg.content())
-            .expect("Error writing a flash message")
+use crate::utils::{flash_messages, render_html};
+
+#[derive(Template)]
+#[template(path = "login.html")]
+struct LoginTemplate {
+    flash_messages: Vec<String>,
+}
+
+pub async fn login_form(
+    incoming_flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    render_html(&LoginTemplate {
+        flash_messages: flash_messages(&incoming_flash_messages),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use askama::Template;
+
+    use super::LoginTemplate;
+
+    #[test]
+    fn flash_messages_are_escaped() {
+        let page = LoginTemplate {
+            flash_messages: vec!["<script>alert('hi')</script>".into()],
+        }
+        .render()
+        .unwrap();
+        assert!(!page.contains("<script>"));
+        assert!(page.contains("<p><i>&#60;script&#62;"));
     }
-    HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"<!DOCTYPE html>
-            <html lang="en">
-            <head>
-            <meta http-equiv="content-type" content="text/html; charset=utf-8">
-            <title>Login</title>
-            </head>
-            <body>
-            {error_html}
-            <form action="/login" method="post">
-            <label>Username
-            <input
-            type="text"
-            placeholder="Enter Username"
-            name="username"
-            >
-            </label>
-            <label>Password
-            <input
-            type="password"
-            placeholder="Enter Password"
-            name="password"
-            >
-            </label>
-            <button type="submit">Login</button>
-            </form>
-            </body>
-            </html>"#,
-        ))
 }
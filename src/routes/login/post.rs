@@ -1,4 +1,4 @@
-use actix_web::{HttpResponse, error::InternalError, http::header, web};
+use actix_web::{HttpResponse, error::InternalError, web};
 use actix_web_flash_messages::FlashMessage;
 use secrecy::Secret;
 use sqlx::PgPool;
@@ -6,6 +6,8 @@ use sqlx::PgPool;
 use crate::{
     authentication::{AuthError, Credentials, validate_credentials},
     routes::error_chain_fmt,
+    session_state::TypedSession,
+    utils::see_other,
 };
 
 #[derive(serde::Deserialize)]
@@ -15,12 +17,13 @@ pub struct FormData {
 }
 
 #[tracing::instrument(
-skip(form, pool),
+skip(form, pool, session),
 fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 pub async fn login(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
+    session: TypedSession,
 ) -> Result<HttpResponse, InternalError<LoginError>> {
     let credentials = Credentials {
         username: form.0.username,
@@ -32,24 +35,31 @@ pub async fn login(
     match validate_credentials(credentials, &pool).await {
         Ok(user_id) => {
             tracing::Span::current().record("user_id", tracing::field::display(&user_id));
-            Ok(HttpResponse::SeeOther()
-                .insert_header((header::LOCATION, "/"))
-                .finish())
+            // Rotate the session key on login, so a session identifier planted
+            // before authentication can not be reused afterwards (session fixation)
+            session.renew();
+            session
+                .insert_user_id(user_id)
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            Ok(see_other("/admin/dashboard"))
         }
         Err(e) => {
             let e = match e {
                 AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
                 AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
             };
-            FlashMessage::error(e.to_string()).send();
-            let response = HttpResponse::SeeOther()
-                .insert_header((header::LOCATION, "/login"))
-                .finish();
-            Err(InternalError::from_response(e, response))
+            Err(login_redirect(e))
         }
     }
 }
 
+/// Redirect to the login page with an error message.
+fn login_redirect(e: LoginError) -> InternalError<LoginError> {
+    FlashMessage::error(e.to_string()).send();
+    let response = see_other("/login");
+    InternalError::from_response(e, response)
+}
+
 #[derive(thiserror::Error)]
 pub enum LoginError {
     #[error("authentication failed")]
@@ -1,14 +1,24 @@
 //! src/routes/mod.rs
+mod admin;
 mod health_check;
 mod home;
+mod issues;
 mod login;
 mod newsletters;
+mod subscribers;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_data;
+mod subscriptions_unsubscribe;
 
+pub use admin::*;
 pub use health_check::*;
 pub use home::*;
+pub use issues::*;
 pub use login::*;
 pub use newsletters::*;
+pub use subscribers::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_data::*;
+pub use subscriptions_unsubscribe::*;
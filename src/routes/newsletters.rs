@@ -1,36 +1,58 @@
 use crate::{
-    authentication::{AuthError, Credentials, validate_credentials},
-    domain::SubscriberEmail,
-    email_client::EmailClient,
+    authentication::{AuthError, authenticate_basic, basic_auth_challenge},
+    html_sanitizer::{HtmlSanitizer, SanitizationReport, Sanitized},
+    idempotency::{IdempotencyKey, NextAction, save_response, try_processing},
+    issue_scheduler::validate_scheduled_for,
+    mailing_lists::{DEFAULT_LIST_SLUG, FindListsError, find_lists},
+    markdown::{self, RenderedMarkdown},
+    personalization,
     routes::error_chain_fmt,
+    segments::{Segment, count_recipients, find_recipients},
 };
 use actix_web::{
     HttpRequest, HttpResponse, ResponseError,
-    http::{
-        StatusCode,
-        header::{self, HeaderMap, HeaderValue},
-    },
+    http::{StatusCode, header::HeaderMap},
     web,
 };
 use anyhow::Context;
-use base64::Engine;
-use secrecy::Secret;
-use sqlx::PgPool;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
 pub struct BodyData {
     title: String,
     content: Content,
+    /// If set, the issue is stored and released into delivery at that time
+    /// (RFC 3339, with the UTC offset of the audience)
+    scheduled_for: Option<DateTime<Utc>>,
+    /// The slugs of the lists the issue goes out to, the main newsletter if omitted
+    lists: Option<Vec<String>>,
+    /// Restricts the issue to the subscribers matching a tag expression,
+    /// e.g. `beta and not paid`
+    segment: Option<String>,
 }
 
+/// The body of the issue: either a Markdown source, from which we render both
+/// versions, or the HTML and plain-text versions written by hand
 #[derive(serde::Deserialize)]
-pub struct Content {
-    html: String,
-    text: String,
+#[serde(untagged, deny_unknown_fields)]
+pub enum Content {
+    Markdown { markdown: String },
+    Rendered { html: String, text: String },
 }
 
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
+impl Content {
+    /// The (html, text) bodies
+    fn into_bodies(self) -> (String, String) {
+        match self {
+            Content::Markdown { markdown } => {
+                let RenderedMarkdown { html, text } = markdown::render(&markdown);
+                (html, text)
+            }
+            Content::Rendered { html, text } => (html, text),
+        }
+    }
 }
 
 // Here, thiserror allows us to avoid implementing the Display trait.
@@ -42,6 +64,8 @@ struct ConfirmedSubscriber {
 //
 #[derive(thiserror::Error)]
 pub enum PublishError {
+    #[error("{0}")]
+    ValidationError(String),
     #[error("Authentication")]
     AuthError(#[source] anyhow::Error),
     #[error(transparent)]
@@ -59,140 +83,367 @@ impl ResponseError for PublishError {
     // no need to use fn status_code, it is invoked by error_response by default :D
     fn error_response(&self) -> HttpResponse {
         match self {
+            PublishError::ValidationError(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
             PublishError::UnexpectedError(_) => {
                 HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
             }
 
-            PublishError::AuthError(_) => {
-                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
-                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
+            PublishError::AuthError(_) => basic_auth_challenge("publish"),
+        }
+    }
+}
 
-                response
-                    .headers_mut()
-                    // actix_web::http::header provides a collection of constants
-                    // for the names of several well-known/standart HTTP headers
-                    .insert(header::WWW_AUTHENTICATE, header_value);
-                response
-            }
+impl From<AuthError> for PublishError {
+    // We match on `AuthError`'s variantes, but we pass the **whole** error
+    // into the constructors for `PublishError` variants. This ensures that
+    // the context of the top-level wrapper is preserved when the error is logged by our middleware
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
+            AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
         }
     }
 }
 
 #[tracing::instrument(
     name = "Publish a newsletter issue",
-    skip(body, pool, email_client, request),
+    skip(body, pool, sanitizer, request),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    sanitizer: web::Data<HtmlSanitizer>,
     request: HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
-    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
-    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
-    let user_id = validate_credentials(credentials, &pool)
-        .await
-        // We match on `AuthError`'s variantes, but we pass the **whole** error
-        // into the constructors for `PublishError` variants. This ensures that
-        // the context of the top-level wrapper is preserved when the error is logged by our middleware
-        .map_err(|e| match e {
-            AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
-            AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
-        })?;
-    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
-    let subscribers = get_confirmed_subscribers(&pool).await?;
-
-    for sub in subscribers {
-        // Why use a Result? Well, the rules of what a "valid" email is can change overtime, so
-        // what once was a valid email, in the future might raise an error when validating the
-        // email string
-        match sub {
-            Ok(sub) => {
-                email_client
-                    .send_email(
-                        &sub.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    .with_context(|| format!("Failed to send newsletter issue to {}", sub.email))?;
-            }
-            Err(error) => {
-                tracing::warn!(
-                    // We record the error chain as a structured field
-                    // on the log record.
-                    error.cause_chain = ?error,
-                    // Using `\` to split a long string literal over
-                    // two lines, without creating a `\n` character.
-                    "Skipping a confirmed subscriber. \
-                Their stored contact details are invalid",
-                )
-            }
+    let BodyData {
+        title,
+        content,
+        scheduled_for,
+        lists,
+        segment,
+    } = body.into_inner();
+    let user_id = authenticate_basic(request.headers(), &pool).await?;
+    let scheduled_for = scheduled_for
+        .map(validate_scheduled_for)
+        .transpose()
+        .map_err(PublishError::ValidationError)?;
+    let (html_content, text_content) = content.into_bodies();
+    personalization::validate_bodies(&html_content, &text_content)
+        .map_err(PublishError::ValidationError)?;
+    let (list_ids, segment) = resolve_audience(&pool, lists, segment).await?;
+
+    // Clients can retry a publish request safely by sending the same `Idempotency-Key`:
+    // the issue is delivered once and the retries get the response of the first request
+    let idempotency_key = idempotency_key_from_headers(request.headers())?;
+    let mut transaction = match &idempotency_key {
+        Some(idempotency_key) => match try_processing(&pool, idempotency_key, user_id).await? {
+            NextAction::StartProcessing(t) => t,
+            NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+        },
+        None => pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?,
+    };
+
+    let published_issue = publish_issue(
+        &mut transaction,
+        user_id,
+        &NewIssue {
+            title: &title,
+            text_content: &text_content,
+            html_content: &html_content,
+        },
+        scheduled_for,
+        &Audience {
+            list_ids: &list_ids,
+            segment: segment.as_ref(),
+        },
+        &sanitizer,
+    )
+    .await?;
+
+    let response = HttpResponse::Ok().json(published_issue);
+    match idempotency_key {
+        Some(idempotency_key) => {
+            Ok(save_response(transaction, &idempotency_key, user_id, response).await?)
+        }
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to store a newsletter issue.")?;
+            Ok(response)
         }
     }
+}
 
-    Ok(HttpResponse::Ok().finish())
+#[derive(serde::Deserialize)]
+pub struct PreviewBodyData {
+    lists: Option<Vec<String>>,
+    segment: Option<String>,
 }
 
-fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
-    // The header value, if present, must be a valid UTF8 string
-    let header_value = headers
-        .get("Authorization")
-        .context("The 'Authorization' header was missing")?
-        .to_str()
-        .context("The 'Authorization' header was not a valid UTF8 string")?;
+#[derive(serde::Serialize)]
+struct Preview {
+    recipients: usize,
+}
 
-    let base64encoded_segment = header_value
-        .strip_prefix("Basic ")
-        .context("The Authorization scheme was not 'Basic'.")?;
+/// How many subscribers an issue would go out to, with the same `lists` and
+/// `segment` as the publish request
+#[tracing::instrument(
+    name = "Preview the audience of a newsletter issue",
+    skip(body, pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn preview_newsletter_audience(
+    body: web::Json<PreviewBodyData>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let PreviewBodyData { lists, segment } = body.into_inner();
+    authenticate_basic(request.headers(), &pool).await?;
+    let (list_ids, segment) = resolve_audience(&pool, lists, segment).await?;
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let recipients = count_recipients(&mut connection, &list_ids, segment.as_ref())
+        .await
+        .context("Failed to count the recipients")?;
+    Ok(HttpResponse::Ok().json(Preview { recipients }))
+}
 
-    let decoded_bytes = base64::engine::general_purpose::STANDARD
-        .decode(base64encoded_segment)
-        .context("failed to base64-decode 'Basic' credentials")?;
+/// Looks up the lists of the request, the main newsletter if there are none,
+/// and parses its segment
+async fn resolve_audience(
+    pool: &PgPool,
+    lists: Option<Vec<String>>,
+    segment: Option<String>,
+) -> Result<(Vec<Uuid>, Option<Segment>), PublishError> {
+    let lists = lists.unwrap_or_else(|| vec![DEFAULT_LIST_SLUG.to_string()]);
+    let list_ids = find_lists(pool, &lists)
+        .await
+        .map_err(|e| match e {
+            FindListsError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+            e => PublishError::ValidationError(e.to_string()),
+        })?
+        .into_iter()
+        .map(|list| list.list_id)
+        .collect();
+    let segment = segment
+        .as_deref()
+        .map(Segment::parse)
+        .transpose()
+        .map_err(PublishError::ValidationError)?;
+    Ok((list_ids, segment))
+}
 
-    let decoded_credentials = String::from_utf8(decoded_bytes)
-        .context("The decoded credential string is not valid UTF8.")?;
+/// Reads the optional `Idempotency-Key` header
+fn idempotency_key_from_headers(
+    headers: &HeaderMap,
+) -> Result<Option<IdempotencyKey>, PublishError> {
+    let Some(header_value) = headers.get("Idempotency-Key") else {
+        return Ok(None);
+    };
+    let header_value = header_value
+        .to_str()
+        .map_err(|_| {
+            PublishError::ValidationError(
+                "The 'Idempotency-Key' header was not a valid UTF8 string".into(),
+            )
+        })?
+        .to_owned();
+    let idempotency_key = header_value
+        .try_into()
+        .map_err(|e: anyhow::Error| PublishError::ValidationError(e.to_string()))?;
+    Ok(Some(idempotency_key))
+}
 
-    let mut credentials = decoded_credentials.splitn(2, ':');
-    let username = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth. Check that the base64 encoded string you provide after 'Basic' has the format 'username:password'"))?.to_string();
+/// The body of a successful publish response
+#[derive(serde::Serialize)]
+pub struct PublishedIssue {
+    pub newsletter_issue_id: Uuid,
+    /// What the sanitizer removed from the HTML content
+    pub sanitization: SanitizationReport,
+}
 
-    let password = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth. Check that the base64 encoded string you provide after 'Basic' has the format 'username:password'"))?.to_string();
+/// The content of an issue, before sanitization
+pub(crate) struct NewIssue<'a> {
+    pub title: &'a str,
+    pub text_content: &'a str,
+    pub html_content: &'a str,
+}
 
-    Ok(Credentials {
-        username,
-        password: Secret::new(password),
+/// Who an issue goes out to: the confirmed subscribers of the lists, or the part of
+/// them in the segment
+pub(crate) struct Audience<'a> {
+    pub list_ids: &'a [Uuid],
+    pub segment: Option<&'a Segment>,
+}
+
+/// Stores a new issue and enqueues its deliveries to its audience.
+///
+/// The issue and its delivery tasks are stored in the same transaction, the emails
+/// are sent later on by the `issue_delivery_worker`.
+/// A scheduled issue is only stored: the `issue_scheduler` enqueues its deliveries
+/// when it is due.
+pub(crate) async fn publish_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    author_user_id: Uuid,
+    issue: &NewIssue<'_>,
+    scheduled_for: Option<DateTime<Utc>>,
+    audience: &Audience<'_>,
+    sanitizer: &HtmlSanitizer,
+) -> Result<PublishedIssue, anyhow::Error> {
+    // What we store is what goes out, both in the emails and in the archive
+    let Sanitized {
+        html: html_content,
+        report,
+    } = sanitizer.sanitize(issue.html_content);
+    if !report.is_empty() {
+        tracing::warn!(removed = %report, "Markup was removed from the HTML content");
+    }
+    let issue_id = insert_newsletter_issue(
+        transaction,
+        author_user_id,
+        issue.title,
+        issue.text_content,
+        &html_content,
+        scheduled_for,
+        audience.segment,
+    )
+    .await
+    .context("Failed to store newsletter issue details")?;
+    insert_newsletter_issue_lists(transaction, issue_id, audience.list_ids)
+        .await
+        .context("Failed to store the lists of the newsletter issue")?;
+    if scheduled_for.is_none() {
+        enqueue_delivery_tasks(transaction, issue_id)
+            .await
+            .context("Failed to enqueue delivery tasks")?;
+    }
+    Ok(PublishedIssue {
+        newsletter_issue_id: issue_id,
+        sanitization: report,
     })
 }
 
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-/// We return a `Vec` of `Result`'s in the happy case.
-/// This allows the caller to bubble up error due to network issues or other transient failures
-/// using the `?` operator, while the compiler forces them to handle the subtler mapping error.
-/// See http://sled.rs/errors.html for a deep-dive about this technique.
-async fn get_confirmed_subscribers(
-    pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirmed_subs = sqlx::query!(
+#[tracing::instrument(skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    author_user_id: Uuid,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+    scheduled_for: Option<DateTime<Utc>>,
+    segment: Option<&Segment>,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            published_at,
+            author_user_id,
+            scheduled_for,
+            segment
+        )
+        -- Scheduled issues are published later on, by the scheduler
+        VALUES ($1, $2, $3, $4, CASE WHEN $6::timestamptz IS NULL THEN now() END, $5, $6, $7)
+        "#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+        author_user_id,
+        scheduled_for,
+        segment.map(|s| s.as_ref())
+    );
+    transaction.execute(query).await?;
+    Ok(newsletter_issue_id)
+}
+
+#[tracing::instrument(skip_all)]
+async fn insert_newsletter_issue_lists(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    list_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
         r#"
-        SELECT EMAIL
-        FROM subscriptions
-        WHERE status = 'confirmed'
+        INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+        SELECT $1, list_id
+        FROM UNNEST($2::uuid[]) AS list_id
         "#,
+        newsletter_issue_id,
+        list_ids
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+/// Adds a delivery task to `issue_delivery_queue` for every subscriber confirmed on
+/// at least one of the lists of the issue, and part of its segment if it has one.
+/// Subscribers on several of the lists get it once.
+#[tracing::instrument(skip_all)]
+pub(crate) async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT
+            segment,
+            ARRAY(
+                SELECT list_id FROM newsletter_issue_lists il
+                WHERE il.newsletter_issue_id = i.newsletter_issue_id
+            ) AS "list_ids!"
+        FROM newsletter_issues i
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
     )
-    .fetch_all(pool)
-    .await?
-    .into_iter()
-    .map(|row| match SubscriberEmail::parse(row.email) {
-        Ok(email) => Ok(ConfirmedSubscriber { email }),
-        Err(err) => Err(anyhow::anyhow!(err)),
-    })
-    .collect();
+    .fetch_one(&mut **transaction)
+    .await?;
+    if let Some(segment) = issue.segment {
+        // It was validated when the issue was published
+        let segment = Segment::parse(&segment).map_err(anyhow::Error::msg)?;
+        let emails = find_recipients(transaction, &issue.list_ids, &segment).await?;
+        let query = sqlx::query!(
+            r#"
+            INSERT INTO issue_delivery_queue (
+                newsletter_issue_id,
+                subscriber_email
+            )
+            SELECT $1, email
+            FROM UNNEST($2::text[]) AS email
+            "#,
+            newsletter_issue_id,
+            &emails
+        );
+        transaction.execute(query).await?;
+        return Ok(());
+    }
 
-    Ok(confirmed_subs)
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        SELECT DISTINCT $1::uuid, s.email
+        FROM subscriptions s
+        JOIN list_subscriptions ls ON ls.subscriber_id = s.id
+        JOIN newsletter_issue_lists il ON il.list_id = ls.list_id
+        WHERE il.newsletter_issue_id = $1 AND ls.status = 'confirmed'
+        "#,
+        newsletter_issue_id,
+    );
+    transaction.execute(query).await?;
+    Ok(())
 }
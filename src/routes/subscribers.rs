@@ -0,0 +1,207 @@
+use actix_web::{HttpRequest, HttpResponse, ResponseError, http::StatusCode, web};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{AuthError, authenticate_basic, basic_auth_challenge},
+    domain::SubscriberName,
+    mailing_lists::DEFAULT_LIST_SLUG,
+    routes::error_chain_fmt,
+    subscribers::{self, ManualConfirmError, Subscriber, SubscriberQuery},
+};
+
+#[derive(thiserror::Error)]
+pub enum SubscribersApiError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("There is no such subscriber.")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(String),
+    #[error("Authentication")]
+    AuthError(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscribersApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ErrorBody {
+    message: String,
+}
+
+impl ResponseError for SubscribersApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubscribersApiError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribersApiError::NotFound => StatusCode::NOT_FOUND,
+            SubscribersApiError::Conflict(_) => StatusCode::CONFLICT,
+            SubscribersApiError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            SubscribersApiError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SubscribersApiError::AuthError(_) => basic_auth_challenge("subscribers"),
+            // The cause chain is for our logs only
+            SubscribersApiError::UnexpectedError(_) => {
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+            e => HttpResponse::build(self.status_code()).json(ErrorBody {
+                message: e.to_string(),
+            }),
+        }
+    }
+}
+
+impl From<AuthError> for SubscribersApiError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidCredentials(_) => SubscribersApiError::AuthError(e.into()),
+            AuthError::UnexpectedError(_) => SubscribersApiError::UnexpectedError(e.into()),
+        }
+    }
+}
+
+async fn find_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Subscriber, SubscribersApiError> {
+    subscribers::get_subscriber(pool, subscriber_id)
+        .await
+        .context("Failed to retrieve the subscriber")?
+        .ok_or(SubscribersApiError::NotFound)
+}
+
+#[derive(serde::Serialize)]
+struct SubscribersBody {
+    subscribers: Vec<Subscriber>,
+    next_cursor: Option<String>,
+}
+
+/// The subscribers matching the query, a page at a time: pass the `next_cursor`
+/// of a response as `cursor` to get the next page
+#[tracing::instrument(
+    name = "List subscribers",
+    skip(query, pool, request),
+    fields(username=tracing::field::Empty)
+)]
+pub async fn list_subscribers(
+    query: web::Query<SubscriberQuery>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, SubscribersApiError> {
+    authenticate_basic(request.headers(), &pool).await?;
+    let (filter, cursor, limit) = query
+        .parse()
+        .map_err(SubscribersApiError::ValidationError)?;
+    let page = subscribers::list_subscribers(pool.get_ref(), &filter, cursor.as_ref(), limit)
+        .await
+        .context("Failed to list the subscribers")?;
+    Ok(HttpResponse::Ok().json(SubscribersBody {
+        subscribers: page.subscribers,
+        next_cursor: page.next_cursor,
+    }))
+}
+
+#[tracing::instrument(
+    name = "Get a subscriber",
+    skip(pool, request),
+    fields(username=tracing::field::Empty)
+)]
+pub async fn get_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, SubscribersApiError> {
+    authenticate_basic(request.headers(), &pool).await?;
+    let subscriber = find_subscriber(&pool, *subscriber_id).await?;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+#[derive(serde::Deserialize)]
+pub struct UpdateSubscriberBody {
+    name: String,
+}
+
+#[tracing::instrument(
+    name = "Update a subscriber",
+    skip(body, pool, request),
+    fields(username=tracing::field::Empty)
+)]
+pub async fn update_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    body: web::Json<UpdateSubscriberBody>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, SubscribersApiError> {
+    authenticate_basic(request.headers(), &pool).await?;
+    let name = SubscriberName::parse(body.0.name).map_err(SubscribersApiError::ValidationError)?;
+    let updated = subscribers::rename_subscriber(pool.get_ref(), *subscriber_id, &name)
+        .await
+        .context("Failed to rename the subscriber")?;
+    if !updated {
+        return Err(SubscribersApiError::NotFound);
+    }
+    let subscriber = find_subscriber(&pool, *subscriber_id).await?;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ConfirmSubscriberBody {
+    /// The slug of the list to confirm, the main newsletter if omitted
+    list: Option<String>,
+}
+
+/// Confirms a pending subscription without the confirmation link
+#[tracing::instrument(
+    name = "Confirm a subscriber manually",
+    skip(body, pool, request),
+    fields(username=tracing::field::Empty)
+)]
+pub async fn confirm_subscriber_manually(
+    subscriber_id: web::Path<Uuid>,
+    body: Option<web::Json<ConfirmSubscriberBody>>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, SubscribersApiError> {
+    authenticate_basic(request.headers(), &pool).await?;
+    find_subscriber(&pool, *subscriber_id).await?;
+    let list = body
+        .and_then(|body| body.0.list)
+        .unwrap_or_else(|| DEFAULT_LIST_SLUG.to_string());
+    subscribers::confirm_list_subscription(&pool, *subscriber_id, &list)
+        .await
+        .map_err(|e| match e {
+            ManualConfirmError::UnexpectedError(e) => SubscribersApiError::UnexpectedError(e),
+            e @ ManualConfirmError::Unsubscribed(_) => SubscribersApiError::Conflict(e.to_string()),
+            e => SubscribersApiError::ValidationError(e.to_string()),
+        })?;
+    let subscriber = find_subscriber(&pool, *subscriber_id).await?;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+/// Deletes the subscriber with their list subscriptions and subscription tokens
+#[tracing::instrument(
+    name = "Delete a subscriber",
+    skip(pool, request),
+    fields(username=tracing::field::Empty)
+)]
+pub async fn delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, SubscribersApiError> {
+    authenticate_basic(request.headers(), &pool).await?;
+    if !subscribers::delete_subscriber(&pool, *subscriber_id).await? {
+        return Err(SubscribersApiError::NotFound);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -1,10 +1,11 @@
 use actix_web::{
-    HttpResponse,
-    http::StatusCode,
+    HttpRequest, HttpResponse,
+    http::{StatusCode, header::ContentType},
     web::{self},
 };
 use anyhow::Context;
-use chrono::Utc;
+use askama::Template;
+use chrono::{TimeDelta, Utc};
 use rand::{Rng, distributions::Alphanumeric, thread_rng};
 use sqlx::{Executor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
@@ -12,7 +13,10 @@ use uuid::Uuid;
 use crate::{
     domain::{NewSubscriber, SubscriberEmail, SubscriberName},
     email_client::EmailClient,
+    mailing_lists::{DEFAULT_LIST_SLUG, MailingList, get_list_by_slug},
+    personal_data::record_consent_event,
     startup::ApplicationBaseUrl,
+    utils::prefers_html,
 };
 
 use actix_web::ResponseError;
@@ -80,6 +84,8 @@ impl ResponseError for SubscribeError {
 pub struct FormData {
     email: String,
     name: String,
+    /// The slug of the list to join, the main newsletter if omitted
+    list: Option<String>,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
@@ -92,7 +98,10 @@ impl TryFrom<FormData> for NewSubscriber {
     }
 }
 
-fn generate_subscription_token() -> String {
+/// How long a confirmation link can be used for
+const SUBSCRIPTION_TOKEN_LIFETIME: TimeDelta = TimeDelta::hours(24);
+
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -108,7 +117,7 @@ fn generate_subscription_token() -> String {
     // we can explicitly tell tracing what to ignore using the skip directive.
     // form will be ignored because we will unwrap it inside the fields()
     // and pool will be ignored because its not easily displayable
-    skip(form, pool, email_client, base_url),
+    skip(request, form, pool, email_client, base_url),
     // We can also enrich the span’s context using the fields directive. It leverages the same syntax we have already seen for the info_span! macro.
     fields(
         subscriber_email = %form.email,
@@ -116,44 +125,76 @@ fn generate_subscription_token() -> String {
     )
 )]
 pub async fn subscribe(
+    request: HttpRequest,
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber =
-        NewSubscriber::try_from(form.0).map_err(SubscribeError::ValidationError)?;
-
-    //If the user already exists in the DB, we just resend them the confirmation email, with the
-    //same token.
-    //That only happens if a user tries to subscribe twice
-    if let Some(existing_token) = get_subscriber_token_by_email(&pool, &new_subscriber.email)
+    let mut form = form.0;
+    let list_slug = form
+        .list
+        .take()
+        .unwrap_or_else(|| DEFAULT_LIST_SLUG.to_string());
+    let new_subscriber = NewSubscriber::try_from(form).map_err(SubscribeError::ValidationError)?;
+    let list = get_list_by_slug(pool.get_ref(), &list_slug)
         .await
-        .context("Failed to acquire a Postgres connection from the pool")?
-    {
-        send_confirmation_email(&email_client, new_subscriber, &base_url.0, &existing_token)
-            .await
-            .context("Failed to send a confirmation email.")?;
-
-        return Ok(HttpResponse::Ok().finish());
-    }
-
-    // Starting from here, the code will only run if they were NOT in the database
+        .context("Failed to look up the list in the database.")?
+        .ok_or_else(|| {
+            SubscribeError::ValidationError(format!("'{}' is not a known list.", list_slug))
+        })?;
 
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    // The same person can be on several lists, with a single subscriber record
+    let subscriber_id = match get_subscriber_by_email(&mut transaction, &new_subscriber.email)
+        .await
+        .context("Failed to look up the subscriber in the database.")?
+    {
+        Some(subscriber_id) => subscriber_id,
+        None => insert_subscriber(&mut transaction, &new_subscriber)
+            .await
+            .context("Failed to insert new subscriber in the database.")?,
+    };
+    match get_list_subscription_status(&mut transaction, list.list_id, subscriber_id)
         .await
-        .context("Failed to insert new subscriber in the database.")?;
+        .context("Failed to look up the list subscription in the database.")?
+        .as_deref()
+    {
+        // Nothing left to confirm
+        Some("confirmed") => {
+            return subscribed_page(
+                &request,
+                "Already subscribed",
+                "You are already subscribed, there is nothing left to do.",
+            );
+        }
+        // The user tries to subscribe twice (or again, after unsubscribing): their
+        // previous confirmation links stop working and they get a fresh one
+        Some(_) => reset_subscription_to_pending(&mut transaction, list.list_id, subscriber_id)
+            .await
+            .context("Failed to reset the subscription to pending confirmation.")?,
+        None => insert_list_subscription(&mut transaction, list.list_id, subscriber_id)
+            .await
+            .context("Failed to insert the list subscription in the database.")?,
+    }
+    record_consent_event(&mut transaction, subscriber_id, list.list_id, "subscribed")
+        .await
+        .context("Failed to record the subscription.")?;
 
     let subscription_token = generate_subscription_token();
 
-    store_token(&mut transaction, subscriber_id, &subscription_token)
-        .await
-        .context("Failed to store the confirmation token for a new subscriber.")?;
+    store_token(
+        &mut transaction,
+        subscriber_id,
+        list.list_id,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to store the confirmation token for a new subscriber.")?;
 
     transaction
         .commit()
@@ -163,38 +204,84 @@ pub async fn subscribe(
     send_confirmation_email(
         &email_client,
         new_subscriber,
+        &list,
         &base_url.0,
         &subscription_token,
     )
     .await
     .context("Failed to send a confirmation email.")?;
 
-    Ok(HttpResponse::Ok().finish())
+    subscribed_page(
+        &request,
+        "Check your inbox",
+        "We have sent you an email with a link to confirm your subscription.",
+    )
+}
+
+#[derive(Template)]
+#[template(path = "subscriptions/subscribed.html")]
+struct SubscribedTemplate {
+    title: &'static str,
+    message: &'static str,
+}
+
+/// A page for the browsers that post the subscription form, an empty body for the
+/// API clients
+fn subscribed_page(
+    request: &HttpRequest,
+    title: &'static str,
+    message: &'static str,
+) -> Result<HttpResponse, SubscribeError> {
+    if !prefers_html(request) {
+        return Ok(HttpResponse::Ok().finish());
+    }
+    let body = SubscribedTemplate { title, message }
+        .render()
+        .context("Failed to render the subscription page.")?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[derive(Template)]
+#[template(path = "emails/confirmation.html")]
+struct ConfirmationEmailHtml<'a> {
+    list_name: &'a str,
+    confirmation_link: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "emails/confirmation.txt")]
+struct ConfirmationEmailText<'a> {
+    list_name: &'a str,
+    confirmation_link: &'a str,
 }
 
 #[tracing::instrument(
     name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber, base_url)
+    skip(email_client, new_subscriber, list, base_url)
 )]
 pub async fn send_confirmation_email(
     email_client: &EmailClient,
     new_subscriber: NewSubscriber,
+    list: &MailingList,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
     );
-    let plain_body = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-        confirmation_link
-    );
-    let html_body = format!(
-        "Welcome to our newsletter!<br />\
-Click <a href=\"{}\">here</a> to confirm your subscription.",
-        confirmation_link
-    );
+    let html_body = ConfirmationEmailHtml {
+        list_name: &list.name,
+        confirmation_link: &confirmation_link,
+    }
+    .render()?;
+    let plain_body = ConfirmationEmailText {
+        list_name: &list.name,
+        confirmation_link: &confirmation_link,
+    }
+    .render()?;
     email_client
         .send_email(&new_subscriber.email, "Welcome!", &html_body, &plain_body)
         .await
@@ -213,8 +300,8 @@ pub async fn insert_subscriber(
     let subscriber_id = Uuid::new_v4();
     let query = sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ( $1, $2, $3, $4, 'pending_confirmation')
+        INSERT INTO subscriptions (id, email, name, subscribed_at)
+        VALUES ( $1, $2, $3, $4)
         "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
@@ -225,20 +312,54 @@ pub async fn insert_subscriber(
     Ok(subscriber_id)
 }
 
+/// Adds the subscriber to the list, pending confirmation
+#[tracing::instrument(
+    name = "Saving the list subscription in the database",
+    skip(transaction)
+)]
+pub async fn insert_list_subscription(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+        VALUES ($1, $2, 'pending_confirmation', now())
+        "#,
+        list_id,
+        subscriber_id
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
 /// This function inserts into the subscription_tokens table, the `subscription_token` that is
-/// provided, for the `subscriber_id` that is also provided
+/// provided, for the `subscriber_id` and `list_id` that are also provided.
+/// The token expires after `SUBSCRIPTION_TOKEN_LIFETIME`
 pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
+    list_id: Uuid,
     subscription_token: &str,
 ) -> Result<(), StoreTokenError> {
+    let now = Utc::now();
     let query = sqlx::query!(
         r#"
-        INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-        VALUES ($1, $2)
+        INSERT INTO subscription_tokens (
+            subscription_token,
+            subscriber_id,
+            list_id,
+            created_at,
+            expires_at
+        )
+        VALUES ($1, $2, $3, $4, $5)
         "#,
         subscription_token,
-        subscriber_id
+        subscriber_id,
+        list_id,
+        now,
+        now + SUBSCRIPTION_TOKEN_LIFETIME
     );
     transaction.execute(query).await.map_err(|e| {
         // `e` is originally a sqlx::error
@@ -247,28 +368,83 @@ pub async fn store_token(
     Ok(())
 }
 
-/// This function gets the token an email has, if they have one
-/// If the email does not have any token, it returns `None`, but if it has, it returns `Some(String)`
-pub async fn get_subscriber_token_by_email(
-    pool: &PgPool,
+/// Looks up a subscriber by email, returning `None` if the email never subscribed.
+/// The row stays locked until the end of the transaction, so two concurrent
+/// subscriptions don't both rotate the token
+#[tracing::instrument(name = "Get the subscriber by email", skip(transaction, email))]
+pub async fn get_subscriber_by_email(
+    transaction: &mut Transaction<'_, Postgres>,
     email: &SubscriberEmail,
-) -> Result<Option<String>, sqlx::Error> {
-    let result = sqlx::query!(
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let subscriber = sqlx::query!(
         r#"
-        SELECT subscription_tokens.subscription_token
-        FROM subscription_tokens
-        JOIN subscriptions ON subscription_tokens.subscriber_id = subscriptions.id
-        WHERE subscriptions.email = $1
+        SELECT id
+        FROM subscriptions
+        WHERE email = $1
+        FOR UPDATE
         "#,
         email.as_ref()
     )
-    .fetch_optional(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(subscriber.map(|s| s.id))
+}
+
+/// The status of the subscription to the list, `None` if the subscriber never joined it
+#[tracing::instrument(name = "Get the list subscription status", skip(transaction))]
+pub async fn get_list_subscription_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let subscription = sqlx::query!(
+        r#"
+        SELECT status
+        FROM list_subscriptions
+        WHERE list_id = $1 AND subscriber_id = $2
+        "#,
+        list_id,
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(subscription.map(|s| s.status))
+}
 
-    // If a row was found, extract the string. Otherwise, return None.
-    Ok(result.map(|r| r.subscription_token))
+/// Puts the list subscription back in `pending_confirmation`, and expires the
+/// confirmation tokens for that list that were not used yet
+#[tracing::instrument(
+    name = "Reset the subscription to pending confirmation",
+    skip(transaction)
+)]
+pub async fn reset_subscription_to_pending(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE list_subscriptions
+        SET status = 'pending_confirmation'
+        WHERE list_id = $1 AND subscriber_id = $2
+        "#,
+        list_id,
+        subscriber_id
+    );
+    transaction.execute(query).await?;
+    let query = sqlx::query!(
+        r#"
+        UPDATE subscription_tokens
+        SET expires_at = now()
+        WHERE
+            list_id = $1 AND
+            subscriber_id = $2 AND
+            consumed_at IS NULL AND
+            expires_at > now()
+        "#,
+        list_id,
+        subscriber_id
+    );
+    transaction.execute(query).await?;
+    Ok(())
 }
@@ -1,82 +1,273 @@
-use actix_web::{HttpResponse, web};
-use sqlx::PgPool;
+use actix_web::{
+    HttpRequest, HttpResponse, ResponseError,
+    error::InternalError,
+    http::{StatusCode, header::ContentType},
+    web,
+};
+use anyhow::Context;
+use askama::Template;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+use crate::{routes::error_chain_fmt, subscribers::confirm_subscriber, utils::prefers_json};
+
 #[derive(serde::Deserialize, Debug)]
 pub struct Parameters {
-    subscription_token: String,
+    // Optional, so that a link cut short still gets the "invalid link" page
+    subscription_token: Option<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum ConfirmError {
+    #[error("The subscription token is malformed")]
+    MalformedToken,
+    #[error("The subscription token does not exist")]
+    UnknownToken,
+    /// With the slug of its list, so that the subscriber can ask for a new link
+    #[error("The subscription token has expired")]
+    ExpiredToken { list: String },
+    #[error("The subscription token has already been used")]
+    ConsumedToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ConfirmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ConfirmError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ConfirmError::MalformedToken => StatusCode::BAD_REQUEST,
+            ConfirmError::UnknownToken => StatusCode::UNAUTHORIZED,
+            // The link was valid once, but it's not anymore
+            ConfirmError::ExpiredToken { .. } => StatusCode::GONE,
+            ConfirmError::ConsumedToken => StatusCode::CONFLICT,
+            ConfirmError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl ConfirmError {
+    /// Machine-readable name of the error, for API clients
+    fn code(&self) -> &'static str {
+        match self {
+            ConfirmError::MalformedToken => "malformed_token",
+            ConfirmError::UnknownToken => "unknown_token",
+            ConfirmError::ExpiredToken { .. } => "expired_token",
+            ConfirmError::ConsumedToken => "consumed_token",
+            ConfirmError::UnexpectedError(_) => "unexpected_error",
+        }
+    }
+
+    /// The error page, as HTML or JSON depending on what the client asked for
+    fn to_response(&self, json: bool) -> HttpResponse {
+        if json {
+            let message = match self {
+                // The cause chain is for our logs only
+                ConfirmError::UnexpectedError(_) => "Something went wrong".to_string(),
+                e => e.to_string(),
+            };
+            return HttpResponse::build(self.status_code()).json(ErrorBody {
+                error: self.code(),
+                message,
+            });
+        }
+        let page = match self {
+            ConfirmError::ConsumedToken => ConfirmTemplate {
+                title: "Already confirmed",
+                message: "This confirmation link has already been used, \
+                    there is nothing left to do.",
+                resend_form: false,
+                list: None,
+            },
+            ConfirmError::MalformedToken | ConfirmError::UnknownToken => ConfirmTemplate {
+                title: "Invalid or expired link",
+                message: "This confirmation link is invalid or has expired.",
+                resend_form: true,
+                list: None,
+            },
+            ConfirmError::ExpiredToken { list } => ConfirmTemplate {
+                title: "Invalid or expired link",
+                message: "This confirmation link is invalid or has expired.",
+                resend_form: true,
+                list: Some(list.as_str()),
+            },
+            ConfirmError::UnexpectedError(_) => ConfirmTemplate {
+                title: "Something went wrong",
+                message: "We could not confirm your subscription, please try again later.",
+                resend_form: false,
+                list: None,
+            },
+        };
+        page.to_response(self.status_code())
+    }
+}
+
+/// The outcome page of the confirmation link
+#[derive(Template)]
+#[template(path = "subscriptions/confirm.html")]
+struct ConfirmTemplate<'a> {
+    title: &'static str,
+    message: &'static str,
+    /// Subscribing again sends a fresh confirmation link
+    resend_form: bool,
+    /// The list to subscribe to again, the main newsletter if we don't know it
+    list: Option<&'a str>,
+}
+
+impl ConfirmTemplate<'_> {
+    fn to_response(&self, status: StatusCode) -> HttpResponse {
+        match self.render() {
+            Ok(body) => HttpResponse::build(status)
+                .content_type(ContentType::html())
+                .body(body),
+            Err(e) => {
+                tracing::error!(error.message = %e, "Failed to render the confirmation page");
+                HttpResponse::InternalServerError().finish()
+            }
+        }
+    }
 }
 
+#[derive(serde::Serialize)]
+struct ConfirmedBody {
+    status: &'static str,
+}
+
+#[derive(serde::Serialize)]
+struct ErrorBody {
+    error: &'static str,
+    message: String,
+}
+
+/// The landing page of the link in the confirmation email.
+///
+/// Humans get an HTML page, API clients asking for `application/json` get a JSON body;
+/// the status code is the same for both.
 #[tracing::instrument(
     name = "Confirm a pending subscriber"
-    skip(pool)
+    skip(request, pool)
 )]
-pub async fn confirm(parameters: web::Query<Parameters>, pool: web::Data<PgPool>) -> HttpResponse {
+pub async fn confirm(
+    request: HttpRequest,
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, InternalError<ConfirmError>> {
+    let json = prefers_json(&request);
+    match confirm_token(parameters.0.subscription_token, &pool).await {
+        Ok(()) if json => Ok(HttpResponse::Ok().json(ConfirmedBody {
+            status: "confirmed",
+        })),
+        Ok(()) => Ok(ConfirmTemplate {
+            title: "Subscription confirmed",
+            message: "Thanks for confirming your email address, \
+                you will receive our next issue!",
+            resend_form: false,
+            list: None,
+        }
+        .to_response(StatusCode::OK)),
+        Err(e) => {
+            let response = e.to_response(json);
+            Err(InternalError::from_response(e, response))
+        }
+    }
+}
+
+async fn confirm_token(
+    subscription_token: Option<String>,
+    pool: &PgPool,
+) -> Result<(), ConfirmError> {
     // We have to Validate the token format FIRST
     // It must be exactly 25 characters and only contain alphanumeric ASCII characters.
-    if parameters.subscription_token.len() != 25
-        || !parameters
-            .subscription_token
+    let subscription_token = subscription_token.ok_or(ConfirmError::MalformedToken)?;
+    if subscription_token.len() != 25
+        || !subscription_token
             .chars()
             .all(|c| c.is_ascii_alphanumeric())
     {
-        return HttpResponse::BadRequest().finish();
+        return Err(ConfirmError::MalformedToken);
     }
 
     // Only if it's well-formatted do we hit the database
-    let sub_id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
-    match sub_id {
-        // Non existing token!
-        None => HttpResponse::Unauthorized().finish(),
-        Some(id) => {
-            if confirm_subscriber(&pool, id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
-            }
-            HttpResponse::Ok().finish()
-        }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let token = get_token(&mut transaction, &subscription_token)
+        .await
+        .context("Failed to retrieve the subscription token")?
+        .ok_or(ConfirmError::UnknownToken)?;
+    if token.consumed_at.is_some() {
+        return Err(ConfirmError::ConsumedToken);
     }
+    if token.expires_at <= Utc::now() {
+        return Err(ConfirmError::ExpiredToken {
+            list: token.list_slug,
+        });
+    }
+    consume_token(&mut transaction, &subscription_token)
+        .await
+        .context("Failed to mark the subscription token as used")?;
+    confirm_subscriber(&mut transaction, token.subscriber_id, token.list_id)
+        .await
+        .context("Failed to confirm the subscriber")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber.")?;
+    Ok(())
+}
+
+pub struct SubscriptionToken {
+    pub subscriber_id: Uuid,
+    pub list_id: Uuid,
+    pub list_slug: String,
+    pub expires_at: DateTime<Utc>,
+    pub consumed_at: Option<DateTime<Utc>>,
 }
 
-///Sets the subscription status to confirmed
+/// Gets the token, locking it until the end of the transaction:
+/// a token can only be used once, even if the link is clicked twice at the same time
 #[tracing::instrument(
-    name = "Sets the subscription status to confirmed"
-    skip(pool)
+    name = "Get the subscription token"
+    skip(transaction)
 )]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
-        subscriber_id
+pub async fn get_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<Option<SubscriptionToken>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriptionToken,
+        r#"
+        SELECT t.subscriber_id, t.list_id, l.slug AS list_slug, t.expires_at, t.consumed_at
+        FROM subscription_tokens t
+        JOIN lists l ON l.list_id = t.list_id
+        WHERE t.subscription_token = $1
+        FOR UPDATE OF t
+        "#,
+        subscription_token
     )
-    .execute(pool)
+    .fetch_optional(&mut **transaction)
     .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
-    Ok(())
 }
 
 #[tracing::instrument(
-    name = "Get the subscriber ID from a token"
-    skip(pool)
+    name = "Mark the subscription token as used"
+    skip(transaction)
 )]
-pub async fn get_subscriber_id_from_token(
-    pool: &PgPool,
+async fn consume_token(
+    transaction: &mut Transaction<'_, Postgres>,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        "SELECT subscriber_id FROM subscription_tokens \
-        WHERE subscription_token = $1",
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        "UPDATE subscription_tokens SET consumed_at = now() WHERE subscription_token = $1",
         subscription_token
-    )
-    .fetch_optional(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
-    Ok(result.map(|r| r.subscriber_id))
+    );
+    transaction.execute(query).await?;
+    Ok(())
 }
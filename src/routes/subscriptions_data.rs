@@ -0,0 +1,267 @@
+use actix_web::{
+    HttpResponse,
+    http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType},
+    web,
+};
+use anyhow::Context;
+use askama::Template;
+use chrono::{DateTime, TimeDelta, Utc};
+use sqlx::PgPool;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::{
+    domain::{DataRequestToken, SubscriberEmail},
+    email_client::EmailClient,
+    personal_data::{erase_subscriber, get_personal_data},
+    startup::{ApplicationBaseUrl, HmacSecret, SuppressionKey},
+    utils::{e400, e500, render_html},
+};
+
+/// How long the link in the email can be used for
+const DATA_REQUEST_LINK_LIFETIME: TimeDelta = TimeDelta::hours(24);
+
+#[derive(Template)]
+#[template(path = "subscriptions/data_request.html")]
+struct DataRequestTemplate<'a> {
+    message: Option<&'a str>,
+}
+
+#[derive(Template)]
+#[template(path = "subscriptions/data.html")]
+struct DataTemplate<'a> {
+    email: &'a str,
+    /// The query string of the signed link, for the download and erase actions
+    link_query: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "subscriptions/erased.html")]
+struct ErasedTemplate;
+
+#[derive(Template)]
+#[template(path = "emails/data_request.html")]
+struct DataRequestEmailHtml<'a> {
+    link: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "emails/data_request.txt")]
+struct DataRequestEmailText<'a> {
+    link: &'a str,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DataRequestFormData {
+    email: String,
+}
+
+/// The query string of the link sent by email
+#[derive(serde::Deserialize, Debug)]
+pub struct DataRequestParameters {
+    subscriber_id: Uuid,
+    /// Unix timestamp
+    expires_at: i64,
+    token: String,
+}
+
+impl DataRequestParameters {
+    fn new(subscriber_id: Uuid, secret: &HmacSecret) -> Self {
+        // Whole seconds, that's what the link carries
+        let expires_at = (Utc::now() + DATA_REQUEST_LINK_LIFETIME).timestamp();
+        let token = DataRequestToken::generate(subscriber_id, timestamp(expires_at), &secret.0);
+        Self {
+            subscriber_id,
+            expires_at,
+            token: token.as_ref().to_string(),
+        }
+    }
+
+    fn query(&self) -> String {
+        format!(
+            "subscriber_id={}&expires_at={}&token={}",
+            self.subscriber_id, self.expires_at, self.token
+        )
+    }
+
+    /// Whether the link is genuine and still valid
+    fn verify(&self, secret: &HmacSecret) -> bool {
+        let expires_at = timestamp(self.expires_at);
+        expires_at > Utc::now()
+            && DataRequestToken::from(self.token.clone()).verify(
+                self.subscriber_id,
+                expires_at,
+                &secret.0,
+            )
+    }
+}
+
+fn timestamp(seconds: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(seconds, 0).unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+/// The page that forged, expired or stale links land on
+fn invalid_link() -> Result<HttpResponse, actix_web::Error> {
+    let body = DataRequestTemplate {
+        message: Some("This link is invalid or has expired, you can ask for a new one."),
+    }
+    .render()
+    .map_err(e500)?;
+    Ok(HttpResponse::BadRequest()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+pub async fn data_request_form() -> Result<HttpResponse, actix_web::Error> {
+    render_html(&DataRequestTemplate { message: None })
+}
+
+/// Sends a link to see or erase their data to the subscriber.
+///
+/// The page is the same whether the address is subscribed or not, so that it
+/// can't be used to find out who reads the newsletter.
+#[tracing::instrument(
+    name = "Request access to personal data",
+    skip(form, pool, email_client, base_url, secret)
+)]
+pub async fn request_data(
+    form: web::Form<DataRequestFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = SubscriberEmail::parse(form.0.email).map_err(e400)?;
+    let subscriber_id = sqlx::query_scalar!(
+        "SELECT id FROM subscriptions WHERE email = $1",
+        email.as_ref()
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to look up the subscriber")
+    .map_err(e500)?;
+    if let Some(subscriber_id) = subscriber_id {
+        let parameters = DataRequestParameters::new(subscriber_id, &secret);
+        let link = format!(
+            "{}/subscriptions/data/access?{}",
+            base_url.0,
+            parameters.query()
+        );
+        // Sent off the request path: waiting for the email API would make the answer
+        // slower for subscribed addresses only, and an error page would give them away
+        let task = async move {
+            if let Err(e) = send_data_request_email(&email_client, &email, &link).await {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send the data request email"
+                );
+            }
+        };
+        tokio::spawn(task.instrument(tracing::Span::current()));
+    }
+    render_html(&DataRequestTemplate {
+        message: Some(
+            "If this address is subscribed, you will receive an email with a link \
+            to download or erase your data.",
+        ),
+    })
+}
+
+#[tracing::instrument(name = "Send a data request email", skip_all)]
+async fn send_data_request_email(
+    email_client: &EmailClient,
+    email: &SubscriberEmail,
+    link: &str,
+) -> Result<(), anyhow::Error> {
+    let html_body = DataRequestEmailHtml { link }.render()?;
+    let plain_body = DataRequestEmailText { link }.render()?;
+    email_client
+        .send_email(email, "Your personal data", &html_body, &plain_body)
+        .await
+}
+
+/// Landing page of the link in the email.
+///
+/// Like the unsubscribe page, it only offers the actions: a link scanner must
+/// not be able to erase anybody.
+#[tracing::instrument(
+    name = "Show the personal data page",
+    skip(parameters, pool, secret),
+    fields(subscriber_id=%parameters.subscriber_id)
+)]
+pub async fn data_access(
+    parameters: web::Query<DataRequestParameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !parameters.verify(&secret) {
+        return invalid_link();
+    }
+    let email = sqlx::query_scalar!(
+        "SELECT email FROM subscriptions WHERE id = $1",
+        parameters.subscriber_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to look up the subscriber")
+    .map_err(e500)?;
+    // Already erased
+    let Some(email) = email else {
+        return invalid_link();
+    };
+    render_html(&DataTemplate {
+        email: &email,
+        link_query: &parameters.query(),
+    })
+}
+
+/// Everything we store about the subscriber, as a JSON file
+#[tracing::instrument(
+    name = "Export personal data",
+    skip(parameters, pool, secret),
+    fields(subscriber_id=%parameters.subscriber_id)
+)]
+pub async fn export_data(
+    parameters: web::Query<DataRequestParameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !parameters.verify(&secret) {
+        return invalid_link();
+    }
+    let Some(data) = get_personal_data(&pool, parameters.subscriber_id)
+        .await
+        .map_err(e500)?
+    else {
+        return invalid_link();
+    };
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("personal-data.json".into())],
+        })
+        .json(data))
+}
+
+/// Erases the subscriber, and everything we know about them
+#[tracing::instrument(
+    name = "Erase personal data",
+    skip(parameters, pool, secret, suppression_key),
+    fields(subscriber_id=%parameters.subscriber_id)
+)]
+pub async fn erase_data(
+    parameters: web::Query<DataRequestParameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+    suppression_key: web::Data<SuppressionKey>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !parameters.verify(&secret) {
+        return invalid_link();
+    }
+    // Submitting the form twice is fine, the data is gone either way
+    erase_subscriber(&pool, parameters.subscriber_id, &suppression_key.0)
+        .await
+        .map_err(e500)?;
+    render_html(&ErasedTemplate)
+}
@@ -0,0 +1,111 @@
+use actix_web::{HttpResponse, web};
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    domain::UnsubscribeToken,
+    personal_data::record_consent_event,
+    startup::HmacSecret,
+    utils::{e500, render_html},
+};
+
+#[derive(serde::Deserialize, Debug)]
+pub struct UnsubscribeParameters {
+    subscriber_id: Uuid,
+    token: String,
+}
+
+#[derive(Template)]
+#[template(path = "subscriptions/unsubscribe.html")]
+struct UnsubscribeTemplate<'a> {
+    subscriber_id: Uuid,
+    token: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "subscriptions/unsubscribed.html")]
+struct UnsubscribedTemplate;
+
+/// Landing page of the unsubscribe link in the newsletter footer.
+///
+/// It does not unsubscribe anybody by itself: link scanners and previews follow
+/// links too, so the subscriber has to confirm with a POST.
+#[tracing::instrument(name = "Show the unsubscribe page", skip(parameters, secret))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !parameters.verify(&secret) {
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+    render_html(&UnsubscribeTemplate {
+        subscriber_id: parameters.subscriber_id,
+        token: &parameters.token,
+    })
+}
+
+/// Unsubscribes the subscriber, from every list.
+///
+/// Mail clients that support one-click unsubscription (RFC 8058) call this endpoint
+/// directly, with `List-Unsubscribe=One-Click` as body: the query string carries
+/// everything we need, so we don't look at the body at all.
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(parameters, pool, secret),
+    fields(subscriber_id=%parameters.subscriber_id)
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !parameters.verify(&secret) {
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+    mark_as_unsubscribed(&pool, parameters.subscriber_id)
+        .await
+        .map_err(e500)?;
+    render_html(&UnsubscribedTemplate)
+}
+
+impl UnsubscribeParameters {
+    fn verify(&self, secret: &HmacSecret) -> bool {
+        UnsubscribeToken::from(self.token.clone()).verify(self.subscriber_id, &secret.0)
+    }
+}
+
+#[tracing::instrument(name = "Set the subscription status to unsubscribed", skip(pool))]
+async fn mark_as_unsubscribed(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    // The lists they actually leave, following the link twice is not a new event
+    let list_ids = sqlx::query_scalar!(
+        r#"
+        UPDATE list_subscriptions SET status = 'unsubscribed'
+        WHERE subscriber_id = $1 AND status <> 'unsubscribed'
+        RETURNING list_id
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    for list_id in list_ids {
+        record_consent_event(&mut transaction, subscriber_id, list_id, "unsubscribed").await?;
+    }
+    // A confirmation link that is still around must not subscribe them again
+    sqlx::query!(
+        r#"
+        UPDATE subscription_tokens
+        SET expires_at = now()
+        WHERE
+            subscriber_id = $1 AND
+            consumed_at IS NULL AND
+            expires_at > now()
+        "#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,435 @@
+//! src/segments.rs
+
+use std::collections::{HashMap, HashSet};
+use std::iter::Peekable;
+use std::str::Chars;
+
+use futures_util::{StreamExt, TryStreamExt, future, stream::BoxStream};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+/// A subset of the subscribers of a list, described by a boolean expression over
+/// their tags and attributes, e.g. `beta and (paid or plan = "pro") and not es-locale`.
+///
+/// `and` binds tighter than `or`, tags and attribute names are case-insensitive.
+#[derive(Debug)]
+pub struct Segment {
+    /// The expression as the author wrote it, which is what we store
+    source: String,
+    expression: Expression,
+}
+
+#[derive(Debug)]
+enum Expression {
+    Tag(String),
+    Attribute {
+        name: String,
+        value: String,
+    },
+    Not(Box<Expression>),
+    /// Chains are kept flat, so that only parentheses and `not` make the tree deeper
+    And(Vec<Expression>),
+    Or(Vec<Expression>),
+}
+
+/// How deep parentheses and `not` can be nested: the parser and the evaluation
+/// recurse, a deeper segment would overflow the stack
+const MAX_DEPTH: usize = 32;
+
+/// What we know about a subscriber, as far as segments are concerned
+pub struct SubscriberProfile {
+    pub tags: HashSet<String>,
+    pub attributes: HashMap<String, String>,
+}
+
+impl Segment {
+    pub fn parse(source: &str) -> Result<Segment, String> {
+        let tokens = tokenize(source)?;
+        if tokens.is_empty() {
+            return Err("The segment is empty.".into());
+        }
+        let mut parser = Parser {
+            tokens: tokens.into_iter().peekable(),
+            depth: 0,
+        };
+        let expression = parser.or()?;
+        if let Some(token) = parser.tokens.next() {
+            return Err(format!("Unexpected {} in the segment.", token));
+        }
+        Ok(Segment {
+            source: source.trim().to_string(),
+            expression,
+        })
+    }
+
+    pub fn matches(&self, profile: &SubscriberProfile) -> bool {
+        self.expression.matches(profile)
+    }
+}
+
+impl AsRef<str> for Segment {
+    fn as_ref(&self) -> &str {
+        &self.source
+    }
+}
+
+impl Expression {
+    fn matches(&self, profile: &SubscriberProfile) -> bool {
+        match self {
+            Expression::Tag(tag) => profile.tags.contains(tag),
+            Expression::Attribute { name, value } => profile.attributes.get(name) == Some(value),
+            Expression::Not(e) => !e.matches(profile),
+            Expression::And(expressions) => expressions.iter().all(|e| e.matches(profile)),
+            Expression::Or(expressions) => expressions.iter().any(|e| e.matches(profile)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    And,
+    Or,
+    Not,
+    Equals,
+    OpenParenthesis,
+    CloseParenthesis,
+    /// A tag or an attribute name, lowercased
+    Word(String),
+    /// A quoted attribute value
+    Text(String),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::And => write!(f, "'and'"),
+            Token::Or => write!(f, "'or'"),
+            Token::Not => write!(f, "'not'"),
+            Token::Equals => write!(f, "'='"),
+            Token::OpenParenthesis => write!(f, "'('"),
+            Token::CloseParenthesis => write!(f, "')'"),
+            Token::Word(word) => write!(f, "'{}'", word),
+            Token::Text(text) => write!(f, "\"{}\"", text),
+        }
+    }
+}
+
+fn is_word_character(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' | '=' => {
+                chars.next();
+                tokens.push(match c {
+                    '(' => Token::OpenParenthesis,
+                    ')' => Token::CloseParenthesis,
+                    _ => Token::Equals,
+                });
+            }
+            '"' => {
+                chars.next();
+                tokens.push(Token::Text(quoted_text(&mut chars)?));
+            }
+            c if is_word_character(c) => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek().filter(|c| is_word_character(**c)) {
+                    word.push(c.to_ascii_lowercase());
+                    chars.next();
+                }
+                tokens.push(match word.as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Word(word),
+                });
+            }
+            c => return Err(format!("Unexpected '{}' in the segment.", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Reads up to the closing quote, `\"` and `\\` are escapes
+fn quoted_text(chars: &mut Peekable<Chars<'_>>) -> Result<String, String> {
+    let mut text = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(text),
+            Some('\\') => match chars.next() {
+                Some(c) => text.push(c),
+                None => break,
+            },
+            Some(c) => text.push(c),
+            None => break,
+        }
+    }
+    Err("A value of the segment is missing its closing quote.".into())
+}
+
+/// Recursive descent, from the loosest operator to the tightest
+struct Parser {
+    tokens: Peekable<std::vec::IntoIter<Token>>,
+    /// How many parentheses and `not` we are in
+    depth: usize,
+}
+
+impl Parser {
+    fn or(&mut self) -> Result<Expression, String> {
+        let mut expressions = vec![self.and()?];
+        while self.tokens.next_if_eq(&Token::Or).is_some() {
+            expressions.push(self.and()?);
+        }
+        Ok(match expressions.len() {
+            1 => expressions.pop().unwrap(),
+            _ => Expression::Or(expressions),
+        })
+    }
+
+    fn and(&mut self) -> Result<Expression, String> {
+        let mut expressions = vec![self.not()?];
+        while self.tokens.next_if_eq(&Token::And).is_some() {
+            expressions.push(self.not()?);
+        }
+        Ok(match expressions.len() {
+            1 => expressions.pop().unwrap(),
+            _ => Expression::And(expressions),
+        })
+    }
+
+    fn not(&mut self) -> Result<Expression, String> {
+        if self.tokens.next_if_eq(&Token::Not).is_some() {
+            self.nest()?;
+            let expression = Expression::Not(Box::new(self.not()?));
+            self.depth -= 1;
+            return Ok(expression);
+        }
+        self.term()
+    }
+
+    fn term(&mut self) -> Result<Expression, String> {
+        match self.tokens.next() {
+            Some(Token::OpenParenthesis) => {
+                self.nest()?;
+                let expression = self.or()?;
+                self.depth -= 1;
+                match self.tokens.next() {
+                    Some(Token::CloseParenthesis) => Ok(expression),
+                    _ => Err("A parenthesis of the segment is never closed.".into()),
+                }
+            }
+            Some(Token::Word(name)) if self.tokens.next_if_eq(&Token::Equals).is_some() => {
+                match self.tokens.next() {
+                    Some(Token::Text(value)) => Ok(Expression::Attribute { name, value }),
+                    _ => Err(format!(
+                        "'{} =' must be followed by a quoted value, e.g. {} = \"value\".",
+                        name, name
+                    )),
+                }
+            }
+            Some(Token::Word(tag)) => Ok(Expression::Tag(tag)),
+            Some(token) => Err(format!("Unexpected {} in the segment.", token)),
+            None => Err("The segment ends unexpectedly.".into()),
+        }
+    }
+
+    fn nest(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(format!(
+                "The segment nests parentheses and 'not' more than {} levels deep.",
+                MAX_DEPTH
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How many subscribers an issue sent to `list_ids` and `segment` would go out to
+#[tracing::instrument(skip(connection, segment))]
+pub async fn count_recipients(
+    connection: &mut PgConnection,
+    list_ids: &[Uuid],
+    segment: Option<&Segment>,
+) -> Result<usize, sqlx::Error> {
+    if let Some(segment) = segment {
+        return stream_recipients(connection, list_ids, segment)
+            .try_fold(0, |n, _| future::ready(Ok(n + 1)))
+            .await;
+    }
+    let n_recipients = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(DISTINCT ls.subscriber_id) AS "n!"
+        FROM list_subscriptions ls
+        WHERE ls.list_id = ANY($1) AND ls.status = 'confirmed'
+        "#,
+        list_ids
+    )
+    .fetch_one(connection)
+    .await?;
+    Ok(n_recipients as usize)
+}
+
+/// The addresses of the subscribers of `segment` who are confirmed on at least
+/// one of `list_ids`.
+#[tracing::instrument(skip(connection, segment), fields(segment = segment.as_ref()))]
+pub async fn find_recipients(
+    connection: &mut PgConnection,
+    list_ids: &[Uuid],
+    segment: &Segment,
+) -> Result<Vec<String>, sqlx::Error> {
+    stream_recipients(connection, list_ids, segment)
+        .try_collect()
+        .await
+}
+
+/// The segment is evaluated here rather than in SQL: the candidates come in with
+/// their tags and attributes and are filtered as the rows arrive, so only the
+/// addresses that match are ever held in memory.
+fn stream_recipients<'e>(
+    connection: &'e mut PgConnection,
+    list_ids: &[Uuid],
+    segment: &'e Segment,
+) -> BoxStream<'e, Result<String, sqlx::Error>> {
+    sqlx::query!(
+        r#"
+        SELECT
+            s.email,
+            ARRAY(
+                SELECT tag FROM subscriber_tags t WHERE t.subscriber_id = s.id
+            ) AS "tags!",
+            ARRAY(
+                SELECT name FROM subscriber_attributes a
+                WHERE a.subscriber_id = s.id ORDER BY name
+            ) AS "attribute_names!",
+            ARRAY(
+                SELECT value FROM subscriber_attributes a
+                WHERE a.subscriber_id = s.id ORDER BY name
+            ) AS "attribute_values!"
+        FROM subscriptions s
+        WHERE EXISTS (
+            SELECT 1
+            FROM list_subscriptions ls
+            WHERE
+                ls.subscriber_id = s.id AND
+                ls.list_id = ANY($1) AND
+                ls.status = 'confirmed'
+        )
+        "#,
+        list_ids
+    )
+    .fetch(connection)
+    .try_filter_map(move |s| {
+        let profile = SubscriberProfile {
+            tags: s.tags.into_iter().collect(),
+            attributes: s
+                .attribute_names
+                .into_iter()
+                .zip(s.attribute_values)
+                .collect(),
+        };
+        future::ready(Ok(segment.matches(&profile).then_some(s.email)))
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use super::{Segment, SubscriberProfile};
+
+    fn profile(tags: &[&str], attributes: &[(&str, &str)]) -> SubscriberProfile {
+        SubscriberProfile {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            attributes: attributes
+                .iter()
+                .map(|(n, v)| (n.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    fn matches(segment: &str, profile: &SubscriberProfile) -> bool {
+        Segment::parse(segment).unwrap().matches(profile)
+    }
+
+    #[test]
+    fn tags_are_combined_with_boolean_operators() {
+        let beta = profile(&["beta"], &[]);
+        let paid_beta = profile(&["beta", "paid"], &[]);
+        assert!(matches("beta", &beta));
+        assert!(!matches("paid", &beta));
+        assert!(matches("beta and not paid", &beta));
+        assert!(!matches("beta and not paid", &paid_beta));
+        assert!(matches("paid or beta", &beta));
+        assert!(matches("NOT (beta AND paid)", &beta));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let es = profile(&["es-locale"], &[]);
+        assert!(matches("es-locale or beta and paid", &es));
+        assert!(!matches("(es-locale or beta) and paid", &es));
+    }
+
+    #[test]
+    fn attributes_are_compared_to_quoted_values() {
+        let pro = profile(&[], &[("plan", "pro \"plus\"")]);
+        assert!(matches(r#"plan = "pro \"plus\"""#, &pro));
+        assert!(!matches(r#"plan = "pro""#, &pro));
+        assert!(!matches(r#"country = "es""#, &pro));
+        assert!(matches(r#"Plan = "pro \"plus\"" or beta"#, &pro));
+    }
+
+    #[test]
+    fn the_source_is_kept_as_written() {
+        let segment = assert_ok!(Segment::parse("  beta AND not paid "));
+        assert_eq!(segment.as_ref(), "beta AND not paid");
+    }
+
+    #[test]
+    fn malformed_segments_are_rejected() {
+        for segment in [
+            "",
+            "   ",
+            "beta and",
+            "beta paid",
+            "(beta or paid",
+            "beta)",
+            "plan =",
+            "plan = pro",
+            r#"plan = "pro"#,
+            "beta & paid",
+            "not",
+        ] {
+            assert_err!(Segment::parse(segment), "'{}' should be rejected", segment);
+        }
+    }
+
+    #[test]
+    fn deeply_nested_segments_are_rejected() {
+        let n = 100_000;
+        assert_err!(Segment::parse(&"not ".repeat(n)));
+        assert_err!(Segment::parse(&format!(
+            "{}beta{}",
+            "(".repeat(n),
+            ")".repeat(n)
+        )));
+        assert_ok!(Segment::parse(&format!("{}beta", "not ".repeat(32))));
+    }
+
+    #[test]
+    fn long_chains_are_not_nested() {
+        let segment = vec!["beta"; 100_000].join(" and ");
+        assert!(matches(&segment, &profile(&["beta"], &[])));
+        let segment = vec!["beta"; 100_000].join(" or ");
+        assert!(!matches(&segment, &profile(&["paid"], &[])));
+    }
+}
@@ -0,0 +1,50 @@
+//! src/session_state.rs
+
+use actix_session::{Session, SessionExt, SessionGetError, SessionInsertError};
+use actix_web::{FromRequest, HttpRequest, dev::Payload};
+use std::future::{Ready, ready};
+use uuid::Uuid;
+
+/// Strongly typed wrapper around `actix_session::Session`.
+/// `Session` is a string-keyed map, so using it directly scatters magic keys all over the
+/// codebase. With `TypedSession` the keys and the types of the values live in one place.
+pub struct TypedSession(Session);
+
+impl TypedSession {
+    const USER_ID_KEY: &'static str = "user_id";
+
+    /// Generates a new session key, keeping the session state.
+    /// It must be called when the privilege level changes (e.g. on login) to
+    /// protect against session fixation attacks.
+    pub fn renew(&self) {
+        self.0.renew();
+    }
+
+    pub fn insert_user_id(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::USER_ID_KEY, user_id)
+    }
+
+    pub fn get_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::USER_ID_KEY)
+    }
+
+    /// Removes the session state, both on the client and on the server side
+    pub fn log_out(self) {
+        self.0.purge()
+    }
+}
+
+impl FromRequest for TypedSession {
+    // We are just wrapping the `Session` extractor, so we return
+    // the same error type it returns
+    type Error = <Session as FromRequest>::Error;
+    // Rust does not support `async` syntax in traits yet.
+    // `FromRequest` expects a `Future` as return type, but we do not
+    // need to perform any I/O, so we wrap `TypedSession` into `Ready`
+    // to get a future that resolves immediately.
+    type Future = Ready<Result<TypedSession, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(TypedSession(req.get_session())))
+    }
+}
@@ -1,5 +1,19 @@
-use crate::configuration::{DatabaseSettings, Settings};
-use crate::routes::{confirm, home, login, login_form, publish_newsletter};
+use crate::authentication::reject_anonymous_users;
+use crate::configuration::{ApplicationSettings, DatabaseSettings, Settings};
+use crate::html_sanitizer::HtmlSanitizer;
+use crate::issue_scheduler::run_scheduler_until_stopped;
+use crate::routes::{
+    MAX_IMPORT_SIZE, admin_confirm_subscriber, admin_dashboard, admin_delete_subscriber,
+    admin_rename_subscriber, admin_subscriber, admin_subscribers, cancel_scheduled_issue,
+    change_password, change_password_form, confirm, confirm_subscriber_manually, create_draft,
+    create_list, data_access, data_request_form, dead_letters, delete_subscriber, draft_versions,
+    edit_draft_form, erase_data, export_data, export_subscribers, get_subscriber, home,
+    import_subscribers, import_subscribers_form, list_drafts, list_issues, list_subscribers, lists,
+    log_out, login, login_form, new_draft_form, preview_newsletter_audience, publish_draft,
+    publish_newsletter, publish_newsletter_form, publish_newsletter_issue, request_data,
+    requeue_dead_letter, reschedule_issue, save_draft, scheduled_issues, segments, show_issue,
+    test_send_draft, unsubscribe, unsubscribe_form, update_subscriber, update_subscriber_tags,
+};
 use crate::{
     email_client::EmailClient,
     routes::{health_check, subscribe},
@@ -7,7 +21,7 @@ use crate::{
 use actix_session::SessionMiddleware;
 use actix_session::storage::RedisSessionStore;
 use actix_web::cookie::Key;
-use actix_web::web::Data;
+use actix_web::middleware::from_fn;
 use actix_web::{App, HttpServer, dev::Server, web};
 use actix_web_flash_messages::FlashMessagesFramework;
 use actix_web_flash_messages::storage::CookieMessageStore;
@@ -28,6 +42,7 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
 pub struct Application {
     port: u16,
     server: Server,
+    db_pool: PgPool,
 }
 
 impl Application {
@@ -38,18 +53,7 @@ impl Application {
     /// 3. A server is started with `run`, which can be accesed using `run_until_stopped`
     pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address");
-
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
-        );
+        let email_client = configuration.email_client.client();
 
         let address = format!(
             "{}:{}",
@@ -60,23 +64,32 @@ impl Application {
 
         let server = run(
             listener,
-            connection_pool,
+            connection_pool.clone(),
             email_client,
-            configuration.application.base_url,
-            configuration.application.hmac_secret,
+            configuration.application,
             configuration.redis_uri,
+            HtmlSanitizer::new(&configuration.html_sanitizer),
         )
         .await?;
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            db_pool: connection_pool,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
-    /// This function only returns when the application is stopped
-    pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+    /// This function only returns when the application is stopped.
+    /// The scheduler of newsletter issues runs alongside the server.
+    pub async fn run_until_stopped(self) -> Result<(), anyhow::Error> {
+        tokio::select! {
+            o = self.server => o?,
+            o = run_scheduler_until_stopped(self.db_pool) => o?,
+        };
+        Ok(())
     }
 }
 
@@ -90,16 +103,19 @@ pub struct ApplicationBaseUrl(pub String);
 #[derive(Clone)]
 pub struct HmacSecret(pub Secret<String>);
 
+// And for the key of the suppression hashes
+pub struct SuppressionKey(pub Secret<String>);
+
 pub async fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
-    base_url: String,
-    hmac_secret: Secret<String>,
+    application: ApplicationSettings,
     redis_uri: Secret<String>,
+    html_sanitizer: HtmlSanitizer,
 ) -> Result<Server, anyhow::Error> {
     // web::Data wraps our connection in an Arc<T>
-    let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
+    let secret_key = Key::from(application.hmac_secret.expose_secret().as_bytes());
     let message_store = CookieMessageStore::builder(secret_key.clone()).build();
     // The FlashMessagesFramework middleware takes care of all the heavy-lifting behind the scenes - creating the
     // cookie, signing it, setting the right properties, etc.
@@ -107,8 +123,10 @@ pub async fn run(
     let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
     let db_pool = web::Data::new(db_pool);
     let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
-    let hmac_secret = web::Data::new(HmacSecret(hmac_secret));
+    let base_url = web::Data::new(ApplicationBaseUrl(application.base_url));
+    let hmac_secret = web::Data::new(HmacSecret(application.hmac_secret));
+    let suppression_key = web::Data::new(SuppressionKey(application.suppression_key));
+    let html_sanitizer = web::Data::new(html_sanitizer);
     let server = HttpServer::new(move || {
         App::new()
             .wrap(message_framework.clone())
@@ -120,14 +138,113 @@ pub async fn run(
             .route("/health_check", web::get().to(health_check))
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route("/subscriptions/data", web::get().to(data_request_form))
+            .route("/subscriptions/data", web::post().to(request_data))
+            .route("/subscriptions/data/access", web::get().to(data_access))
+            .route("/subscriptions/data/export", web::get().to(export_data))
+            .route("/subscriptions/data/erase", web::post().to(erase_data))
             .route("/newsletters", web::post().to(publish_newsletter))
+            .route(
+                "/newsletters/preview",
+                web::post().to(preview_newsletter_audience),
+            )
+            .route("/subscribers", web::get().to(list_subscribers))
+            .route(
+                "/subscribers/{subscriber_id}",
+                web::get().to(get_subscriber),
+            )
+            .route(
+                "/subscribers/{subscriber_id}",
+                web::patch().to(update_subscriber),
+            )
+            .route(
+                "/subscribers/{subscriber_id}",
+                web::delete().to(delete_subscriber),
+            )
+            .route(
+                "/subscribers/{subscriber_id}/confirm",
+                web::post().to(confirm_subscriber_manually),
+            )
+            .route("/issues", web::get().to(list_issues))
+            .route("/issues/{issue_id}", web::get().to(show_issue))
             .route("/login", web::get().to(login_form))
             .route("/login", web::post().to(login))
             .route("/", web::get().to(home))
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/newsletters", web::get().to(publish_newsletter_form))
+                    .route("/newsletters", web::post().to(publish_newsletter_issue))
+                    .route("/password", web::get().to(change_password_form))
+                    .route("/password", web::post().to(change_password))
+                    .route("/logout", web::post().to(log_out))
+                    .route("/dead_letters", web::get().to(dead_letters))
+                    .route("/dead_letters/requeue", web::post().to(requeue_dead_letter))
+                    .route("/drafts", web::get().to(list_drafts))
+                    .route("/drafts", web::post().to(create_draft))
+                    .route("/drafts/new", web::get().to(new_draft_form))
+                    .route("/drafts/{draft_id}", web::get().to(edit_draft_form))
+                    .route("/drafts/{draft_id}", web::post().to(save_draft))
+                    .route("/drafts/{draft_id}/versions", web::get().to(draft_versions))
+                    .route(
+                        "/drafts/{draft_id}/test_send",
+                        web::post().to(test_send_draft),
+                    )
+                    .route("/drafts/{draft_id}/publish", web::post().to(publish_draft))
+                    .route("/lists", web::get().to(lists))
+                    .route("/lists", web::post().to(create_list))
+                    .route("/segments", web::get().to(segments))
+                    .route(
+                        "/segments/subscriber",
+                        web::post().to(update_subscriber_tags),
+                    )
+                    .route("/subscribers", web::get().to(admin_subscribers))
+                    // Before `/subscribers/{subscriber_id}`, which would match them too
+                    .route("/subscribers/export", web::get().to(export_subscribers))
+                    .service(
+                        web::resource("/subscribers/import")
+                            .app_data(web::FormConfig::default().limit(MAX_IMPORT_SIZE))
+                            .route(web::get().to(import_subscribers_form))
+                            .route(web::post().to(import_subscribers)),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::get().to(admin_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/name",
+                        web::post().to(admin_rename_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/confirm",
+                        web::post().to(admin_confirm_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/delete",
+                        web::post().to(admin_delete_subscriber),
+                    )
+                    .route("/scheduled_issues", web::get().to(scheduled_issues))
+                    .route(
+                        "/scheduled_issues/cancel",
+                        web::post().to(cancel_scheduled_issue),
+                    )
+                    .route(
+                        "/scheduled_issues/reschedule",
+                        web::post().to(reschedule_issue),
+                    ),
+            )
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
             .app_data(hmac_secret.clone())
+            .app_data(suppression_key.clone())
+            .app_data(html_sanitizer.clone())
     })
     .listen(listener)?
     .run();
@@ -0,0 +1,550 @@
+//! src/subscribers.rs
+
+use anyhow::Context;
+use base64::Engine;
+use chrono::{DateTime, NaiveDate, Utc};
+use futures_util::{StreamExt, TryStreamExt, stream::BoxStream};
+use sqlx::{Executor, PgExecutor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    domain::SubscriberName, mailing_lists::get_list_by_slug, personal_data::record_consent_event,
+};
+
+/// The statuses of a list subscription
+pub const STATUSES: [&str; 3] = ["pending_confirmation", "confirmed", "unsubscribed"];
+
+/// How many subscribers a page holds when the client doesn't say
+pub const DEFAULT_PAGE_SIZE: i64 = 50;
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(serde::Serialize)]
+pub struct Subscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub lists: Vec<ListStatus>,
+}
+
+impl Subscriber {
+    /// The slugs of the lists the subscriber still has to confirm
+    pub fn pending_lists(&self) -> Vec<&str> {
+        self.lists
+            .iter()
+            .filter(|l| l.status == "pending_confirmation")
+            .map(|l| l.list.as_str())
+            .collect()
+    }
+}
+
+/// Where a subscriber stands on one list
+#[derive(serde::Serialize)]
+pub struct ListStatus {
+    /// The slug of the list
+    pub list: String,
+    pub status: String,
+}
+
+/// The parameters of a subscriber search, as sent by API clients and the admin form.
+/// Blank fields are ignored.
+#[derive(serde::Deserialize, Default)]
+pub struct SubscriberQuery {
+    /// Only the subscribers with a subscription in this status
+    pub status: Option<String>,
+    /// Only the subscribers of this list (combined with `status`, the status on this list)
+    pub list: Option<String>,
+    /// RFC 3339, or a date for midnight UTC. Inclusive
+    pub subscribed_after: Option<String>,
+    /// RFC 3339, or a date for midnight UTC. Exclusive
+    pub subscribed_before: Option<String>,
+    /// Part of the email address or of the name, case-insensitive
+    pub search: Option<String>,
+    /// The `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug)]
+pub struct SubscriberFilter {
+    status: Option<String>,
+    list: Option<String>,
+    subscribed_after: Option<DateTime<Utc>>,
+    subscribed_before: Option<DateTime<Utc>>,
+    search: Option<String>,
+}
+
+/// Subscribers are listed from the most recent, the cursor is the last one of a page
+#[derive(Debug, PartialEq)]
+pub struct Cursor {
+    subscribed_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+pub struct SubscriberPage {
+    pub subscribers: Vec<Subscriber>,
+    /// Where the next page starts, `None` on the last page
+    pub next_cursor: Option<String>,
+}
+
+fn non_blank(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+impl SubscriberQuery {
+    pub fn parse(&self) -> Result<(SubscriberFilter, Option<Cursor>, i64), String> {
+        let status = non_blank(&self.status)
+            .map(|status| {
+                if STATUSES.contains(&status) {
+                    Ok(status.to_string())
+                } else {
+                    Err(format!(
+                        "'{}' is not a status, use one of {}.",
+                        status,
+                        STATUSES.join(", ")
+                    ))
+                }
+            })
+            .transpose()?;
+        let filter = SubscriberFilter {
+            status,
+            list: non_blank(&self.list).map(str::to_string),
+            subscribed_after: non_blank(&self.subscribed_after)
+                .map(parse_timestamp)
+                .transpose()?,
+            subscribed_before: non_blank(&self.subscribed_before)
+                .map(parse_timestamp)
+                .transpose()?,
+            search: non_blank(&self.search).map(str::to_string),
+        };
+        let cursor = non_blank(&self.cursor).map(Cursor::decode).transpose()?;
+        let limit = self.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+            return Err(format!(
+                "The limit must be between 1 and {}.",
+                MAX_PAGE_SIZE
+            ));
+        }
+        Ok((filter, cursor, limit))
+    }
+
+    /// The query string of the page starting at `cursor`, with the same filters
+    pub fn with_cursor(&self, cursor: &str) -> String {
+        let mut parameters = vec![];
+        let fields = [
+            ("status", &self.status),
+            ("list", &self.list),
+            ("subscribed_after", &self.subscribed_after),
+            ("subscribed_before", &self.subscribed_before),
+            ("search", &self.search),
+        ];
+        for (name, value) in fields {
+            if let Some(value) = non_blank(value) {
+                parameters.push(format!("{}={}", name, urlencoding::encode(value)));
+            }
+        }
+        if let Some(limit) = self.limit {
+            parameters.push(format!("limit={}", limit));
+        }
+        parameters.push(format!("cursor={}", cursor));
+        parameters.join("&")
+    }
+}
+
+fn parse_timestamp(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(s) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|date| date.and_time(Default::default()).and_utc())
+        .map_err(|_| {
+            format!(
+                "'{}' is not a valid date, use the format 2026-10-19 or 2026-10-19T09:00:00+02:00.",
+                s
+            )
+        })
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        let raw = format!("{}/{}", self.subscribed_at.to_rfc3339(), self.id);
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    pub fn decode(s: &str) -> Result<Cursor, String> {
+        let invalid = || "The cursor is invalid.".to_string();
+        let raw = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(s)
+            .map_err(|_| invalid())?;
+        let raw = String::from_utf8(raw).map_err(|_| invalid())?;
+        let (subscribed_at, id) = raw.split_once('/').ok_or_else(invalid)?;
+        Ok(Cursor {
+            subscribed_at: DateTime::parse_from_rfc3339(subscribed_at)
+                .map_err(|_| invalid())?
+                .with_timezone(&Utc),
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+struct SubscriberRow {
+    id: Uuid,
+    email: String,
+    name: String,
+    subscribed_at: DateTime<Utc>,
+    list_slugs: Vec<String>,
+    list_statuses: Vec<String>,
+}
+
+impl From<SubscriberRow> for Subscriber {
+    fn from(row: SubscriberRow) -> Self {
+        Subscriber {
+            id: row.id,
+            email: row.email,
+            name: row.name,
+            subscribed_at: row.subscribed_at,
+            lists: row
+                .list_slugs
+                .into_iter()
+                .zip(row.list_statuses)
+                .map(|(list, status)| ListStatus { list, status })
+                .collect(),
+        }
+    }
+}
+
+/// A page of the subscribers matching `filter`, the most recent first
+#[tracing::instrument(skip(executor, filter))]
+pub async fn list_subscribers<'e>(
+    executor: impl PgExecutor<'e> + 'e,
+    filter: &SubscriberFilter,
+    cursor: Option<&Cursor>,
+    limit: i64,
+) -> Result<SubscriberPage, sqlx::Error> {
+    // One more than asked for, to know if there is a next page
+    let mut subscribers: Vec<Subscriber> =
+        stream_subscribers(executor, filter, cursor, Some(limit + 1))
+            .try_collect()
+            .await?;
+    let next_cursor = if subscribers.len() as i64 > limit {
+        subscribers.truncate(limit as usize);
+        subscribers.last().map(|last| {
+            Cursor {
+                subscribed_at: last.subscribed_at,
+                id: last.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+    Ok(SubscriberPage {
+        subscribers,
+        next_cursor,
+    })
+}
+
+/// The subscribers matching `filter`, the most recent first, as the rows come in.
+/// Without a `limit`, all of them: exports go through here so that we never hold
+/// the whole table in memory
+pub fn stream_subscribers<'e>(
+    executor: impl PgExecutor<'e> + 'e,
+    filter: &SubscriberFilter,
+    cursor: Option<&Cursor>,
+    limit: Option<i64>,
+) -> BoxStream<'e, Result<Subscriber, sqlx::Error>> {
+    sqlx::query_as!(
+        SubscriberRow,
+        r#"
+        SELECT
+            s.id,
+            s.email,
+            s.name,
+            s.subscribed_at,
+            ARRAY(
+                SELECT l.slug FROM list_subscriptions ls
+                JOIN lists l ON l.list_id = ls.list_id
+                WHERE ls.subscriber_id = s.id ORDER BY l.slug
+            ) AS "list_slugs!",
+            ARRAY(
+                SELECT ls.status FROM list_subscriptions ls
+                JOIN lists l ON l.list_id = ls.list_id
+                WHERE ls.subscriber_id = s.id ORDER BY l.slug
+            ) AS "list_statuses!"
+        FROM subscriptions s
+        WHERE
+            (($1::text IS NULL AND $2::text IS NULL) OR EXISTS (
+                SELECT 1
+                FROM list_subscriptions ls
+                JOIN lists l ON l.list_id = ls.list_id
+                WHERE
+                    ls.subscriber_id = s.id AND
+                    ($1::text IS NULL OR ls.status = $1) AND
+                    ($2::text IS NULL OR l.slug = $2)
+            )) AND
+            ($3::timestamptz IS NULL OR s.subscribed_at >= $3) AND
+            ($4::timestamptz IS NULL OR s.subscribed_at < $4) AND
+            ($5::text IS NULL OR
+                strpos(lower(s.email), lower($5)) > 0 OR
+                strpos(lower(s.name), lower($5)) > 0) AND
+            ($6::timestamptz IS NULL OR (s.subscribed_at, s.id) < ($6, $7::uuid))
+        ORDER BY s.subscribed_at DESC, s.id DESC
+        -- No limit when NULL
+        LIMIT $8
+        "#,
+        filter.status,
+        filter.list,
+        filter.subscribed_after,
+        filter.subscribed_before,
+        filter.search,
+        cursor.map(|c| c.subscribed_at),
+        cursor.map(|c| c.id),
+        limit
+    )
+    .fetch(executor)
+    .map_ok(Subscriber::from)
+    .boxed()
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn get_subscriber(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, sqlx::Error> {
+    let row = sqlx::query_as!(
+        SubscriberRow,
+        r#"
+        SELECT
+            s.id,
+            s.email,
+            s.name,
+            s.subscribed_at,
+            ARRAY(
+                SELECT l.slug FROM list_subscriptions ls
+                JOIN lists l ON l.list_id = ls.list_id
+                WHERE ls.subscriber_id = s.id ORDER BY l.slug
+            ) AS "list_slugs!",
+            ARRAY(
+                SELECT ls.status FROM list_subscriptions ls
+                JOIN lists l ON l.list_id = ls.list_id
+                WHERE ls.subscriber_id = s.id ORDER BY l.slug
+            ) AS "list_statuses!"
+        FROM subscriptions s
+        WHERE s.id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(row.map(Subscriber::from))
+}
+
+/// `false` if there is no such subscriber
+#[tracing::instrument(skip(executor))]
+pub async fn rename_subscriber(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    name: &SubscriberName,
+) -> Result<bool, sqlx::Error> {
+    let n_updated = sqlx::query!(
+        "UPDATE subscriptions SET name = $1 WHERE id = $2",
+        name.as_ref(),
+        subscriber_id
+    )
+    .execute(executor)
+    .await?
+    .rows_affected();
+    Ok(n_updated > 0)
+}
+
+/// Sets the status of the subscription to the list to confirmed, and records the consent.
+/// Only pending subscriptions are confirmed: an old link must not bring back somebody
+/// who unsubscribed
+#[tracing::instrument(
+    name = "Sets the subscription status to confirmed"
+    skip(transaction)
+)]
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE list_subscriptions
+        SET status = 'confirmed'
+        WHERE
+            subscriber_id = $1 AND
+            list_id = $2 AND
+            status = 'pending_confirmation'
+        "#,
+        subscriber_id,
+        list_id
+    );
+    let n_confirmed = transaction.execute(query).await?.rows_affected();
+    if n_confirmed > 0 {
+        record_consent_event(transaction, subscriber_id, list_id, "confirmed").await?;
+    }
+    Ok(())
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ManualConfirmError {
+    #[error("'{0}' is not a known list.")]
+    UnknownList(String),
+    #[error("The subscriber is not on the list '{0}'.")]
+    NotSubscribed(String),
+    /// They asked to stop: only they can subscribe again
+    #[error("The subscriber has unsubscribed from '{0}', they have to subscribe again.")]
+    Unsubscribed(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// Confirms a pending subscription on behalf of the subscriber, e.g. when the
+/// confirmation email got lost. Confirming twice is fine.
+#[tracing::instrument(skip(pool))]
+pub async fn confirm_list_subscription(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    list: &str,
+) -> Result<(), ManualConfirmError> {
+    let Some(list) = get_list_by_slug(pool, list)
+        .await
+        .context("Failed to look up the list")?
+    else {
+        return Err(ManualConfirmError::UnknownList(list.to_string()));
+    };
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let status = sqlx::query_scalar!(
+        r#"
+        SELECT status FROM list_subscriptions
+        WHERE subscriber_id = $1 AND list_id = $2
+        FOR UPDATE
+        "#,
+        subscriber_id,
+        list.list_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the list subscription")?;
+    match status.as_deref() {
+        None => return Err(ManualConfirmError::NotSubscribed(list.slug)),
+        Some("unsubscribed") => return Err(ManualConfirmError::Unsubscribed(list.slug)),
+        Some(_) => {}
+    }
+    confirm_subscriber(&mut transaction, subscriber_id, list.list_id)
+        .await
+        .context("Failed to confirm the subscriber")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber.")?;
+    Ok(())
+}
+
+/// Deletes a subscriber with their tokens, their list subscriptions go with them.
+/// `false` if there is no such subscriber
+#[tracing::instrument(skip(pool))]
+pub async fn delete_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let query = sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+        subscriber_id
+    );
+    transaction
+        .execute(query)
+        .await
+        .context("Failed to delete the subscription tokens")?;
+    let query = sqlx::query!("DELETE FROM subscriptions WHERE id = $1", subscriber_id);
+    let n_deleted = transaction
+        .execute(query)
+        .await
+        .context("Failed to delete the subscriber")?
+        .rows_affected();
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to delete a subscriber.")?;
+    Ok(n_deleted > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use claims::{assert_err, assert_ok};
+    use uuid::Uuid;
+
+    use super::{Cursor, SubscriberQuery};
+
+    #[test]
+    fn cursors_survive_a_round_trip() {
+        let cursor = Cursor {
+            subscribed_at: Utc.with_ymd_and_hms(2026, 10, 17, 9, 30, 0).unwrap(),
+            id: Uuid::new_v4(),
+        };
+        assert_eq!(assert_ok!(Cursor::decode(&cursor.encode())), cursor);
+        assert_err!(Cursor::decode("not-a-cursor"));
+    }
+
+    #[test]
+    fn dates_and_timestamps_are_both_accepted() {
+        let query = SubscriberQuery {
+            subscribed_after: Some("2026-10-01".into()),
+            subscribed_before: Some("2026-10-17T09:00:00+02:00".into()),
+            ..Default::default()
+        };
+        let (filter, _, _) = assert_ok!(query.parse());
+        assert_eq!(
+            filter.subscribed_after,
+            Some(Utc.with_ymd_and_hms(2026, 10, 1, 0, 0, 0).unwrap())
+        );
+        assert_eq!(
+            filter.subscribed_before,
+            Some(Utc.with_ymd_and_hms(2026, 10, 17, 7, 0, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn invalid_queries_are_rejected() {
+        let test_cases = [
+            SubscriberQuery {
+                status: Some("active".into()),
+                ..Default::default()
+            },
+            SubscriberQuery {
+                subscribed_after: Some("last week".into()),
+                ..Default::default()
+            },
+            SubscriberQuery {
+                limit: Some(0),
+                ..Default::default()
+            },
+            SubscriberQuery {
+                limit: Some(1000),
+                ..Default::default()
+            },
+        ];
+        for query in test_cases {
+            assert_err!(query.parse());
+        }
+    }
+
+    #[test]
+    fn blank_fields_are_ignored() {
+        let query = SubscriberQuery {
+            status: Some("".into()),
+            search: Some("  ".into()),
+            ..Default::default()
+        };
+        let (filter, cursor, limit) = assert_ok!(query.parse());
+        assert!(filter.status.is_none() && filter.search.is_none() && cursor.is_none());
+        assert_eq!(limit, 50);
+    }
+}
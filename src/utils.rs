@@ -0,0 +1,127 @@
+//! src/utils.rs
+
+use actix_web::{
+    HttpMessage, HttpRequest, HttpResponse,
+    http::header::{Accept, ContentType, LOCATION},
+    mime,
+};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+
+/// Return an opaque 500 while preserving the error's root cause for logging.
+pub fn e500<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorInternalServerError(e)
+}
+
+/// Return a 400 with the user-representation of the validation error as body.
+/// The error root cause is preserved for logging purposes.
+pub fn e400<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorBadRequest(e)
+}
+
+/// Builds a `303 See Other` response that redirects the client to `location`
+pub fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .finish()
+}
+
+/// Renders `template` as the body of a `200 OK` HTML response
+pub fn render_html(template: &impl Template) -> Result<HttpResponse, actix_web::Error> {
+    let body = template.render().map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// The content of the flash messages, ready to be handed to a template
+/// (they are escaped there, like any other value)
+pub fn flash_messages(incoming: &IncomingFlashMessages) -> Vec<String> {
+    incoming.iter().map(|m| m.content().to_owned()).collect()
+}
+
+/// Content negotiation for the pages that are also used by API clients:
+/// `true` if the client ranks JSON above HTML in its `Accept` header.
+/// Browsers (and clients that don't say anything) get HTML.
+pub fn prefers_json(request: &HttpRequest) -> bool {
+    let Some(accept) = request.get_header::<Accept>() else {
+        return false;
+    };
+    accept
+        .ranked()
+        .into_iter()
+        .find(|m| *m == mime::APPLICATION_JSON || *m == mime::TEXT_HTML)
+        .is_some_and(|m| m == mime::APPLICATION_JSON)
+}
+
+/// For the form posts that are also used by API clients: `true` only if the client
+/// asks for HTML, as browsers do. Clients that don't say anything get no body.
+pub fn prefers_html(request: &HttpRequest) -> bool {
+    let Some(accept) = request.get_header::<Accept>() else {
+        return false;
+    };
+    accept
+        .ranked()
+        .into_iter()
+        .find(|m| *m == mime::APPLICATION_JSON || *m == mime::TEXT_HTML)
+        .is_some_and(|m| m == mime::TEXT_HTML)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prefers_html, prefers_json};
+    use actix_web::{http::header::ACCEPT, test::TestRequest};
+
+    #[test]
+    fn json_is_used_only_if_it_is_preferred_to_html() {
+        let test_cases = [
+            (None, false),
+            (Some("*/*"), false),
+            (Some("text/html"), false),
+            (Some("application/json"), true),
+            (Some("text/html, application/json;q=0.9"), false),
+            (Some("text/html;q=0.5, application/json"), true),
+        ];
+        for (accept, expected) in test_cases {
+            let mut request = TestRequest::default();
+            if let Some(accept) = accept {
+                request = request.insert_header((ACCEPT, accept));
+            }
+            assert_eq!(
+                prefers_json(&request.to_http_request()),
+                expected,
+                "Accept: {:?}",
+                accept
+            );
+        }
+    }
+
+    #[test]
+    fn html_is_used_only_if_it_is_asked_for() {
+        let test_cases = [
+            (None, false),
+            (Some("*/*"), false),
+            (Some("text/html,application/xhtml+xml,*/*;q=0.8"), true),
+            (Some("application/json"), false),
+            (Some("text/html;q=0.5, application/json"), false),
+        ];
+        for (accept, expected) in test_cases {
+            let mut request = TestRequest::default();
+            if let Some(accept) = accept {
+                request = request.insert_header((ACCEPT, accept));
+            }
+            assert_eq!(
+                prefers_html(&request.to_http_request()),
+                expected,
+                "Accept: {:?}",
+                accept
+            );
+        }
+    }
+}
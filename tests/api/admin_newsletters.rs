@@ -0,0 +1,229 @@
+use uuid::Uuid;
+use wiremock::{
+    Mock,
+    matchers::{method, path},
+};
+
+use crate::helpers::{
+    PostmarkBatchResponder, assert_response_is_redirect_to, count_rows,
+    create_confirmed_subscriber, spawn_app,
+};
+
+fn newsletter_form_body(idempotency_key: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": idempotency_key,
+    })
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_newsletter_form() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_admin("/admin/newsletters").await;
+
+    // Assert
+    assert_response_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_publish_a_newsletter() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_admin_form(
+            "/admin/newsletters",
+            &newsletter_form_body(&Uuid::new_v4().to_string()),
+        )
+        .await;
+
+    // Assert
+    assert_response_is_redirect_to(&response, "/login");
+    assert_eq!(count_rows(&app, "newsletter_issues").await, 0);
+}
+
+#[tokio::test]
+async fn the_newsletter_form_carries_an_idempotency_key() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    // Act
+    let html_page = app.get_admin_html("/admin/newsletters").await;
+
+    // Assert
+    assert!(html_page.contains(r#"name="idempotency_key""#));
+}
+
+#[tokio::test]
+async fn newsletters_published_from_the_form_are_delivered() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login_test_user().await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Submit the form
+    let response = app
+        .post_admin_form(
+            "/admin/newsletters",
+            &newsletter_form_body(&Uuid::new_v4().to_string()),
+        )
+        .await;
+    assert_response_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_html("/admin/newsletters").await;
+    assert!(html_page.contains(
+        "<p><i>The newsletter issue has been accepted - emails will go out shortly.</i></p>"
+    ));
+    app.dispatch_all_pending_emails().await;
+
+    // Mock verifies on Drop that we have sent the newsletter email
+}
+
+#[tokio::test]
+async fn submitting_the_newsletter_form_twice_publishes_the_issue_once() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login_test_user().await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let body = newsletter_form_body(&Uuid::new_v4().to_string());
+
+    // Act - Part 1 - Submit the form
+    let response = app.post_admin_form("/admin/newsletters", &body).await;
+    assert_response_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act - Part 2 - Submit the same form **again**
+    let response = app.post_admin_form("/admin/newsletters", &body).await;
+    assert_response_is_redirect_to(&response, "/admin/newsletters");
+
+    // Assert
+    let html_page = app.get_admin_html("/admin/newsletters").await;
+    assert!(html_page.contains("The newsletter issue has been accepted"));
+    assert_eq!(count_rows(&app, "newsletter_issues").await, 1);
+    app.dispatch_all_pending_emails().await;
+
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn the_newsletter_form_rejects_missing_fields() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    let test_cases = ["title", "text_content", "html_content"];
+
+    for field in test_cases {
+        let mut body = newsletter_form_body(&idempotency_key);
+        body[field] = " ".into();
+
+        // Act - Part 1 - Submit the form
+        let response = app.post_admin_form("/admin/newsletters", &body).await;
+        assert_response_is_redirect_to(&response, "/admin/newsletters");
+
+        // Act - Part 2 - Follow the redirect
+        let html_page = app.get_admin_html("/admin/newsletters").await;
+        assert!(
+            html_page
+                .contains("<p><i>The title and both versions of the content are required.</i></p>"),
+            "The form did not complain when {} was empty",
+            field
+        );
+    }
+    assert_eq!(count_rows(&app, "newsletter_issues").await, 0);
+}
+
+#[tokio::test]
+async fn the_newsletter_form_rejects_an_invalid_idempotency_key() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    // Act
+    let response = app
+        .post_admin_form("/admin/newsletters", &newsletter_form_body(""))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(count_rows(&app, "newsletter_issues").await, 0);
+}
+
+#[tokio::test]
+async fn the_admin_is_told_about_the_removed_markup() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    // Act
+    let response = app
+        .post_admin_form(
+            "/admin/newsletters",
+            &serde_json::json!({
+                "title": "Newsletter title",
+                "text_content": "Newsletter body as plain text",
+                "html_content": "<p>Newsletter body</p><script>alert(1)</script>",
+                "idempotency_key": Uuid::new_v4().to_string(),
+            }),
+        )
+        .await;
+    assert_response_is_redirect_to(&response, "/admin/newsletters");
+
+    // Assert
+    let html_page = app.get_admin_html("/admin/newsletters").await;
+    assert!(html_page.contains(
+        "Some markup is not allowed and was removed from the HTML content: &#60;script&#62;."
+    ));
+    let html_content = sqlx::query_scalar!("SELECT html_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(html_content, "<p>Newsletter body</p>");
+}
+
+#[tokio::test]
+async fn unknown_placeholders_are_rejected_by_the_form() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    // Act
+    let response = app
+        .post_admin_form(
+            "/admin/newsletters",
+            &serde_json::json!({
+                "title": "Newsletter title",
+                "text_content": "Hi {{ nickname }}",
+                "html_content": "<p>Hi {{ name }}</p>",
+                "idempotency_key": Uuid::new_v4().to_string(),
+            }),
+        )
+        .await;
+    assert_response_is_redirect_to(&response, "/admin/newsletters");
+
+    // Assert
+    let html_page = app.get_admin_html("/admin/newsletters").await;
+    assert!(html_page.contains("is not a known placeholder"));
+    assert_eq!(count_rows(&app, "newsletter_issues").await, 0);
+}
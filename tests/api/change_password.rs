@@ -0,0 +1,160 @@
+use uuid::Uuid;
+
+use crate::helpers::{assert_response_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_change_password_form() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_admin("/admin/password").await;
+
+    // Assert
+    assert_response_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_change_your_password() {
+    // Arrange
+    let app = spawn_app().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act
+    let response = app
+        .post_admin_form(
+            "/admin/password",
+            &serde_json::json!({
+                "current_password": Uuid::new_v4().to_string(),
+                "new_password": &new_password,
+                "new_password_check": &new_password,
+            }),
+        )
+        .await;
+
+    // Assert
+    assert_response_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn new_password_fields_must_match() {
+    // Arrange
+    let app = spawn_app().await;
+    let new_password = Uuid::new_v4().to_string();
+    let another_new_password = Uuid::new_v4().to_string();
+    app.login_test_user().await;
+
+    // Act - Part 1 - Try to change password
+    let response = app
+        .post_admin_form(
+            "/admin/password",
+            &serde_json::json!({
+                "current_password": &app.test_user.password,
+                "new_password": &new_password,
+                "new_password_check": &another_new_password,
+            }),
+        )
+        .await;
+    assert_response_is_redirect_to(&response, "/admin/password");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_html("/admin/password").await;
+    assert!(html_page.contains(
+        "<p><i>You entered two different new passwords - \
+        the field values must match.</i></p>"
+    ));
+}
+
+#[tokio::test]
+async fn current_password_must_be_valid() {
+    // Arrange
+    let app = spawn_app().await;
+    let new_password = Uuid::new_v4().to_string();
+    let wrong_password = Uuid::new_v4().to_string();
+    app.login_test_user().await;
+
+    // Act - Part 1 - Try to change password
+    let response = app
+        .post_admin_form(
+            "/admin/password",
+            &serde_json::json!({
+                "current_password": &wrong_password,
+                "new_password": &new_password,
+                "new_password_check": &new_password,
+            }),
+        )
+        .await;
+    assert_response_is_redirect_to(&response, "/admin/password");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_html("/admin/password").await;
+    assert!(html_page.contains("<p><i>The current password is incorrect.</i></p>"));
+}
+
+#[tokio::test]
+async fn new_password_must_have_a_valid_length() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let test_cases = vec![("a".repeat(11), "too short"), ("a".repeat(129), "too long")];
+
+    for (new_password, description) in test_cases {
+        // Act - Part 1 - Try to change password
+        let response = app
+            .post_admin_form(
+                "/admin/password",
+                &serde_json::json!({
+                    "current_password": &app.test_user.password,
+                    "new_password": &new_password,
+                    "new_password_check": &new_password,
+                }),
+            )
+            .await;
+        assert_response_is_redirect_to(&response, "/admin/password");
+
+        // Act - Part 2 - Follow the redirect
+        let html_page = app.get_admin_html("/admin/password").await;
+        assert!(
+            html_page.contains(
+                "<p><i>The new password must be between 12 and 128 characters long.</i></p>"
+            ),
+            "The API did not reject a new password that was {}",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn changing_password_works() {
+    // Arrange
+    let app = spawn_app().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Login
+    app.login_test_user().await;
+
+    // Act - Part 2 - Change password
+    let response = app
+        .post_admin_form(
+            "/admin/password",
+            &serde_json::json!({
+                "current_password": &app.test_user.password,
+                "new_password": &new_password,
+                "new_password_check": &new_password,
+            }),
+        )
+        .await;
+    assert_response_is_redirect_to(&response, "/admin/password");
+
+    // Act - Part 3 - Follow the redirect
+    let html_page = app.get_admin_html("/admin/password").await;
+    assert!(html_page.contains("<p><i>Your password has been changed.</i></p>"));
+
+    // Act - Part 4 - Login using the new password
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &new_password
+    });
+    let response = app.post_login(&login_body).await;
+    assert_response_is_redirect_to(&response, "/admin/dashboard");
+}
@@ -0,0 +1,182 @@
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{
+    PostmarkBatchResponder, TestApp, assert_response_is_redirect_to, count_rows,
+    create_confirmed_subscriber, make_pending_deliveries_due, publish_test_newsletter, spawn_app,
+};
+
+/// Publishes an issue to a confirmed subscriber and makes all of its delivery attempts fail
+async fn create_dead_letter(app: &TestApp) {
+    create_confirmed_subscriber(app).await;
+    publish_test_newsletter(app).await;
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    for _ in 0..app.delivery_settings.max_delivery_attempts {
+        make_pending_deliveries_due(app).await;
+        app.dispatch_all_pending_emails().await;
+    }
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_failed_deliveries() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/admin/dead_letters", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_response_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_listed() {
+    // Arrange
+    let app = spawn_app().await;
+    create_dead_letter(&app).await;
+    app.login_test_user().await;
+
+    // Act
+    let html_page = app.get_admin_html("/admin/dead_letters").await;
+
+    // Assert
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+    assert!(html_page.contains("Newsletter title"));
+}
+
+#[tokio::test]
+async fn a_failed_delivery_can_be_enqueued_again() {
+    // Arrange
+    let app = spawn_app().await;
+    create_dead_letter(&app).await;
+    app.login_test_user().await;
+    let newsletter_issue_id =
+        sqlx::query!("SELECT newsletter_issue_id FROM issue_delivery_dead_letters")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .newsletter_issue_id;
+
+    // Act - Part 1 - Re-enqueue the delivery
+    let response = app
+        .post_admin_form(
+            "/admin/dead_letters/requeue",
+            &serde_json::json!({
+                "newsletter_issue_id": newsletter_issue_id,
+                "subscriber_email": "ursula_le_guin@gmail.com",
+            }),
+        )
+        .await;
+    assert_response_is_redirect_to(&response, "/admin/dead_letters");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_html("/admin/dead_letters").await;
+    assert!(html_page.contains("<p><i>The delivery has been queued again.</i></p>"));
+    assert_eq!(count_rows(&app, "issue_delivery_dead_letters").await, 0);
+
+    // Act - Part 3 - The worker delivers the issue
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(count_rows(&app, "issue_delivery_queue").await, 0);
+}
+
+#[tokio::test]
+async fn a_failed_delivery_that_is_already_queued_is_reported_as_such() {
+    // Arrange
+    let app = spawn_app().await;
+    create_dead_letter(&app).await;
+    app.login_test_user().await;
+    let newsletter_issue_id =
+        sqlx::query!("SELECT newsletter_issue_id FROM issue_delivery_dead_letters")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .newsletter_issue_id;
+    // e.g. it was re-enqueued from another tab, and failed again in the meantime
+    sqlx::query!(
+        "INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email) VALUES ($1, $2)",
+        newsletter_issue_id,
+        "ursula_le_guin@gmail.com"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app
+        .post_admin_form(
+            "/admin/dead_letters/requeue",
+            &serde_json::json!({
+                "newsletter_issue_id": newsletter_issue_id,
+                "subscriber_email": "ursula_le_guin@gmail.com",
+            }),
+        )
+        .await;
+
+    // Assert
+    assert_response_is_redirect_to(&response, "/admin/dead_letters");
+    let html_page = app.get_admin_html("/admin/dead_letters").await;
+    assert!(html_page.contains("<p><i>The delivery is already queued.</i></p>"));
+    assert_eq!(count_rows(&app, "issue_delivery_dead_letters").await, 1);
+    assert_eq!(count_rows(&app, "issue_delivery_queue").await, 1);
+}
+
+#[tokio::test]
+async fn a_delivery_that_fails_again_while_dead_lettered_updates_its_dead_letter() {
+    // Arrange
+    let app = spawn_app().await;
+    create_dead_letter(&app).await;
+    let dead_letter =
+        sqlx::query!("SELECT newsletter_issue_id, failed_at FROM issue_delivery_dead_letters")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    // Queued again while its dead letter is still there, on its last attempt
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email, n_retries)
+        VALUES ($1, $2, $3)
+        "#,
+        dead_letter.newsletter_issue_id,
+        "ursula_le_guin@gmail.com",
+        app.delivery_settings.max_delivery_attempts as i32 - 1
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(count_rows(&app, "issue_delivery_queue").await, 0);
+    let failed_at = sqlx::query_scalar!("SELECT failed_at FROM issue_delivery_dead_letters")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(failed_at > dead_letter.failed_at);
+}
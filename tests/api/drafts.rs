@@ -0,0 +1,240 @@
+use uuid::Uuid;
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{
+    PostmarkBatchResponder, TestApp, assert_response_is_redirect_to, count_rows,
+    create_confirmed_subscriber, spawn_app,
+};
+
+fn draft_body(title: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": title,
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+    })
+}
+
+async fn get_draft_id(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT draft_id FROM newsletter_issue_drafts")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .draft_id
+}
+
+/// Logs in and creates a draft through the admin form
+async fn create_draft(app: &TestApp, title: &str) -> Uuid {
+    app.login_test_user().await;
+    let response = app
+        .post_admin_form("/admin/drafts", &draft_body(title))
+        .await;
+    let draft_id = get_draft_id(app).await;
+    assert_response_is_redirect_to(&response, &format!("/admin/drafts/{}", draft_id));
+    draft_id
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_create_a_draft() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_admin_form("/admin/drafts", &draft_body("Draft title"))
+        .await;
+
+    // Assert
+    assert_response_is_redirect_to(&response, "/login");
+    assert_eq!(count_rows(&app, "newsletter_issue_drafts").await, 0);
+}
+
+#[tokio::test]
+async fn drafts_are_saved_and_can_be_edited_later_on() {
+    // Arrange
+    let app = spawn_app().await;
+    let draft_id = create_draft(&app, "Draft title").await;
+    let draft_url = format!("/admin/drafts/{}", draft_id);
+
+    // Act - Part 1 - The draft is listed
+    let html_page = app.get_admin_html("/admin/drafts").await;
+    assert!(html_page.contains("Draft title"));
+
+    // Act - Part 2 - Edit it
+    let response = app
+        .post_admin_form(&draft_url, &draft_body("Edited title"))
+        .await;
+    assert_response_is_redirect_to(&response, &draft_url);
+
+    // Act - Part 3 - The editor shows the latest version
+    let html_page = app.get_admin_html(&draft_url).await;
+    assert!(html_page.contains("The draft has been saved as version 2."));
+    assert!(html_page.contains(r#"value="Edited title""#));
+    assert_eq!(count_rows(&app, "newsletter_issue_draft_versions").await, 2);
+}
+
+#[tokio::test]
+async fn saving_an_unchanged_draft_does_not_create_a_version() {
+    // Arrange
+    let app = spawn_app().await;
+    let draft_id = create_draft(&app, "Draft title").await;
+    let draft_url = format!("/admin/drafts/{}", draft_id);
+
+    // Act
+    app.post_admin_form(&draft_url, &draft_body("Draft title"))
+        .await;
+
+    // Assert
+    let html_page = app.get_admin_html(&draft_url).await;
+    assert!(html_page.contains("There are no changes to save."));
+    assert_eq!(count_rows(&app, "newsletter_issue_draft_versions").await, 1);
+}
+
+#[tokio::test]
+async fn the_history_shows_what_changed_between_versions() {
+    // Arrange
+    let app = spawn_app().await;
+    let draft_id = create_draft(&app, "Draft title").await;
+    let draft_url = format!("/admin/drafts/{}", draft_id);
+    app.post_admin_form(&draft_url, &draft_body("Edited title"))
+        .await;
+
+    // Act
+    let html_page = app.get_admin_html(&format!("{}/versions", draft_url)).await;
+
+    // Assert
+    assert!(html_page.contains("Version 2 - Edited title"));
+    assert!(html_page.contains("-Draft title"));
+    assert!(html_page.contains("+Edited title"));
+}
+
+#[tokio::test]
+async fn a_test_of_the_draft_is_sent_to_the_given_address_only() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let draft_id = create_draft(&app, "Draft title").await;
+    let draft_url = format!("/admin/drafts/{}", draft_id);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_admin_form(
+            &format!("{}/test_send", draft_url),
+            &serde_json::json!({"email": "admin@example.com"}),
+        )
+        .await;
+    assert_response_is_redirect_to(&response, &draft_url);
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "admin@example.com");
+    assert_eq!(body["Subject"], "[Test] Draft title");
+    let html_page = app.get_admin_html(&draft_url).await;
+    assert!(html_page.contains("Version 1 has been sent to admin@example.com."));
+    // Nothing goes out to the subscribers
+    assert_eq!(count_rows(&app, "issue_delivery_queue").await, 0);
+}
+
+#[tokio::test]
+async fn a_test_send_to_an_invalid_address_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let draft_id = create_draft(&app, "Draft title").await;
+    let draft_url = format!("/admin/drafts/{}", draft_id);
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_admin_form(
+            &format!("{}/test_send", draft_url),
+            &serde_json::json!({"email": "not-an-email"}),
+        )
+        .await;
+
+    // Assert
+    assert_response_is_redirect_to(&response, &draft_url);
+    let html_page = app.get_admin_html(&draft_url).await;
+    assert!(html_page.contains("is not a valid subscriber email"));
+}
+
+#[tokio::test]
+async fn published_drafts_are_delivered_and_cannot_be_edited_anymore() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let draft_id = create_draft(&app, "Draft title").await;
+    let draft_url = format!("/admin/drafts/{}", draft_id);
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Publish
+    let publish_body = serde_json::json!({
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    let response = app
+        .post_admin_form(&format!("{}/publish", draft_url), &publish_body)
+        .await;
+    assert_response_is_redirect_to(&response, &draft_url);
+    let html_page = app.get_admin_html(&draft_url).await;
+    assert!(html_page.contains("The draft has been published"));
+    app.dispatch_all_pending_emails().await;
+
+    // Act - Part 2 - Try to edit it
+    app.post_admin_form(&draft_url, &draft_body("Edited title"))
+        .await;
+    let html_page = app.get_admin_html(&draft_url).await;
+    assert!(html_page.contains("it can&#39;t be edited"));
+    assert_eq!(count_rows(&app, "newsletter_issue_draft_versions").await, 1);
+
+    // Act - Part 3 - Publishing again does nothing
+    app.post_admin_form(
+        &format!("{}/publish", draft_url),
+        &serde_json::json!({"idempotency_key": Uuid::new_v4().to_string()}),
+    )
+    .await;
+    assert_eq!(count_rows(&app, "newsletter_issues").await, 1);
+}
+
+#[tokio::test]
+async fn unknown_drafts_are_not_found() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/admin/drafts/{}", app.address, Uuid::new_v4()))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
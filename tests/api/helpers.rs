@@ -2,14 +2,18 @@ use std::sync::LazyLock;
 
 use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
-use reqwest::Request;
 use reqwest::header::LOCATION;
 use secrecy::Secret;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{method, path},
+};
 use zero2prod::{
     configuration::{DatabaseSettings, get_configuration},
+    email_client::EmailClient,
+    issue_delivery_worker::{DeliverySettings, ExecutionOutcome, try_execute_task},
     startup::{Application, get_connection_pool},
     telemetry::{get_subscriber, init_subscriber_as_global_default},
 };
@@ -39,6 +43,28 @@ pub struct ConfirmationLinks {
     pub plain_text: reqwest::Url,
 }
 
+/// Answers like Postmark's batch endpoint does when every message is accepted:
+/// with one successful result per message of the request
+pub struct PostmarkBatchResponder;
+
+impl wiremock::Respond for PostmarkBatchResponder {
+    fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+        let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        let results: Vec<_> = messages
+            .iter()
+            .map(|m| {
+                serde_json::json!({
+                    "ErrorCode": 0,
+                    "Message": "OK",
+                    "MessageID": Uuid::new_v4(),
+                    "To": m["To"],
+                })
+            })
+            .collect();
+        ResponseTemplate::new(200).set_body_json(results)
+    }
+}
+
 pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
@@ -92,9 +118,40 @@ pub struct TestApp {
     pub email_server: MockServer,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
+    pub email_client: EmailClient,
+    pub delivery_settings: DeliverySettings,
 }
 
 impl TestApp {
+    /// The background worker is not running during tests,
+    /// so we have to drain the delivery queue by hand
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_execute_task(&self.db_pool, &self.email_client, &self.delivery_settings)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// The id of the issue, when a single one has been published
+    pub async fn get_issue_id(&self) -> Uuid {
+        sqlx::query_scalar!("SELECT newsletter_issue_id FROM newsletter_issues")
+            .fetch_one(&self.db_pool)
+            .await
+            .unwrap()
+    }
+
+    pub async fn subscriber_id(&self, email: &str) -> Uuid {
+        sqlx::query_scalar!("SELECT id FROM subscriptions WHERE email = $1", email)
+            .fetch_one(&self.db_pool)
+            .await
+            .unwrap()
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         self.api_client
             .post(format!("{}/subscriptions", &self.address))
@@ -117,6 +174,39 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    /// Logs in as `test_user`. The session cookie is kept in the `api_client` cookie store
+    pub async fn login_test_user(&self) {
+        self.post_login(&serde_json::json!({
+            "username": &self.test_user.username,
+            "password": &self.test_user.password
+        }))
+        .await;
+    }
+
+    /// A request to the API, with the credentials of the test user
+    pub fn api_request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.api_client
+            .request(method, format!("{}{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+    }
+
+    pub async fn post_newsletters_with_idempotency_key(
+        &self,
+        body: serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        let username = &self.test_user.username;
+        let password = &self.test_user.password;
+        self.api_client
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(username, Some(password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
@@ -142,6 +232,31 @@ impl TestApp {
             .unwrap()
     }
 
+    /// A page of the admin area, with the session of `api_client`
+    pub async fn get_admin(&self, path: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}{}", &self.address, path))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_admin_html(&self, path: &str) -> String {
+        self.get_admin(path).await.text().await.unwrap()
+    }
+
+    pub async fn post_admin_form<Body>(&self, path: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}{}", &self.address, path))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
     /// Extracts the email confirmation links from the email request body.
     /// It returns a ConfirmationLinks struct, which contains both the html and plain_text versions
     /// of the link
@@ -167,6 +282,36 @@ impl TestApp {
         let plain_text = get_link(body["TextBody"].as_str().unwrap());
         ConfirmationLinks { html, plain_text }
     }
+
+    /// Extracts the "view in browser" link from a message of a batch sent to Postmark
+    pub fn get_issue_link(&self, message: &serde_json::Value) -> reqwest::Url {
+        let raw_link = linkify::LinkFinder::new()
+            .links(message["TextBody"].as_str().unwrap())
+            .map(|l| l.as_str().to_owned())
+            .find(|l| l.contains("/issues/"))
+            .expect("There was no link to the issue in the message");
+        let mut issue_link = reqwest::Url::parse(&raw_link).unwrap();
+        assert_eq!(issue_link.host_str().unwrap(), "127.0.0.1");
+        issue_link.set_port(Some(self.port)).unwrap();
+        issue_link
+    }
+
+    /// Extracts the unsubscribe link from a message of a batch sent to Postmark,
+    /// the one advertised in the `List-Unsubscribe` header
+    pub fn get_unsubscribe_link(&self, message: &serde_json::Value) -> reqwest::Url {
+        let header = message["Headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|h| h["Name"] == "List-Unsubscribe")
+            .expect("There was no `List-Unsubscribe` header in the message");
+        let value = header["Value"].as_str().unwrap();
+        let raw_link = value.trim_start_matches('<').trim_end_matches('>');
+        let mut unsubscribe_link = reqwest::Url::parse(raw_link).unwrap();
+        assert_eq!(unsubscribe_link.host_str().unwrap(), "127.0.0.1");
+        unsubscribe_link.set_port(Some(self.port)).unwrap();
+        unsubscribe_link
+    }
 }
 
 pub async fn spawn_app() -> TestApp {
@@ -205,8 +350,8 @@ pub async fn spawn_app() -> TestApp {
 
     // Launch the server as a background task
     // tokio::spawn returns a handle to the spawned future,
-    // but we have no use for it here, hence the non-binding let
-    let _ = tokio::spawn(application.run_until_stopped());
+    // but we have no use for it here, hence we drop it
+    drop(tokio::spawn(application.run_until_stopped()));
 
     let test_app = TestApp {
         address,
@@ -215,6 +360,8 @@ pub async fn spawn_app() -> TestApp {
         email_server: mock_email_server,
         test_user: TestUser::generate(),
         api_client,
+        delivery_settings: DeliverySettings::new(&configuration),
+        email_client: configuration.email_client.client(),
     };
 
     test_app.test_user.store(&test_app.db_pool).await;
@@ -263,3 +410,125 @@ pub fn assert_response_is_redirect_to(response: &reqwest::Response, target: &str
         target
     )
 }
+
+/// Use the public API of the application under test to create
+/// an unconfirmed subscriber.
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    // This allows us to avoid making a request to Postmark by accident
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // We now inspect the requests received by the mock Postmark server
+    // to retrieve the confirmation link and return it
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    app.get_confirmation_links(email_request)
+}
+
+/// Publishes a newsletter issue through the API, its deliveries are left in the queue
+pub async fn publish_test_newsletter(app: &TestApp) {
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+/// Skips the backoff of the rescheduled deliveries
+pub async fn make_pending_deliveries_due(app: &TestApp) {
+    sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+pub async fn count_rows(app: &TestApp, table: &str) -> i64 {
+    sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) FROM {}", table))
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+/// Subscribes `email` to a list through the public API, without confirming
+pub async fn subscribe_to_list(app: &TestApp, email: &str, list: &str) -> ConfirmationLinks {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let body = format!(
+        "name=le%20guin&email={}&list={}",
+        email.replace('@', "%40"),
+        list
+    );
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(&email_request)
+}
+
+pub async fn create_confirmed_list_subscriber(app: &TestApp, email: &str, list: &str) {
+    let confirmation_links = subscribe_to_list(app, email, list).await;
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+/// The addresses of every message sent in a batch so far
+pub async fn batch_recipients(app: &TestApp) -> Vec<String> {
+    let mut recipients = Vec::new();
+    for request in app.email_server.received_requests().await.unwrap() {
+        if request.url.path() != "/email/batch" {
+            continue;
+        }
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        for message in body.as_array().unwrap() {
+            recipients.push(message["To"].as_str().unwrap().to_string());
+        }
+    }
+    recipients.sort();
+    recipients
+}
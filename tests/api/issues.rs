@@ -0,0 +1,146 @@
+use uuid::Uuid;
+use wiremock::{
+    Mock,
+    matchers::{method, path},
+};
+
+use crate::helpers::{
+    PostmarkBatchResponder, create_confirmed_subscriber, publish_test_newsletter, spawn_app,
+};
+
+#[tokio::test]
+async fn published_issues_are_listed_in_the_archive() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_test_newsletter(&app).await;
+    let issue_id = app.get_issue_id().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/issues", app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Newsletter title"));
+    assert!(html_page.contains(&format!(r#"href="/issues/{}""#, issue_id)));
+}
+
+#[tokio::test]
+async fn the_issue_page_shows_the_html_content() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_test_newsletter(&app).await;
+    let issue_id = app.get_issue_id().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/issues/{}", app.address, issue_id))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<h1>Newsletter title</h1>"));
+    assert!(html_page.contains("<p>Newsletter body as HTML</p>"));
+}
+
+#[tokio::test]
+async fn an_unknown_issue_returns_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/issues/{}", app.address, Uuid::new_v4()))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn the_author_of_an_issue_is_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    publish_test_newsletter(&app).await;
+
+    // Assert
+    let issue = sqlx::query!("SELECT author_user_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.author_user_id, Some(app.test_user.user_id));
+}
+
+#[tokio::test]
+async fn newsletter_emails_link_to_the_web_version_of_the_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_test_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let issue_url = app.delivery_settings.issue_url(app.get_issue_id().await);
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    assert!(
+        messages[0]["HtmlBody"]
+            .as_str()
+            .unwrap()
+            .contains(&issue_url)
+    );
+    assert!(
+        messages[0]["TextBody"]
+            .as_str()
+            .unwrap()
+            .contains(&issue_url)
+    );
+}
+
+#[tokio::test]
+async fn the_issue_page_does_not_show_placeholders() {
+    // Arrange
+    let app = spawn_app().await;
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Hi {{ name }}",
+            "html": "<p>Hi {{ name }}</p>",
+        }
+    });
+    app.post_newsletters(newsletter_request_body)
+        .await
+        .error_for_status()
+        .unwrap();
+    let issue_id = app.get_issue_id().await;
+
+    // Act
+    let html_page = reqwest::get(format!("{}/issues/{}", app.address, issue_id))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html_page.contains("<p>Hi reader</p>"));
+}
@@ -0,0 +1,371 @@
+use std::collections::BTreeSet;
+
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{
+    PostmarkBatchResponder, TestApp, assert_response_is_redirect_to, batch_recipients, count_rows,
+    create_confirmed_list_subscriber, spawn_app, subscribe_to_list,
+};
+
+/// Creates a list through the admin form, the test user has to be logged in
+async fn create_list(app: &TestApp, slug: &str, name: &str) {
+    let response = app
+        .post_admin_form(
+            "/admin/lists",
+            &serde_json::json!({"slug": slug, "name": name}),
+        )
+        .await;
+    assert_response_is_redirect_to(&response, "/admin/lists");
+}
+
+async fn list_subscription_statuses(app: &TestApp) -> Vec<(String, String)> {
+    sqlx::query!(
+        r#"
+        SELECT l.slug, ls.status
+        FROM list_subscriptions ls
+        JOIN lists l ON l.list_id = ls.list_id
+        ORDER BY l.slug
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| (r.slug, r.status))
+    .collect()
+}
+
+fn newsletter_body(lists: serde_json::Value) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "lists": lists,
+    })
+}
+
+#[tokio::test]
+async fn the_default_list_is_used_when_none_is_given() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert_eq!(
+        list_subscription_statuses(&app).await,
+        vec![("newsletter".to_string(), "pending_confirmation".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_returns_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&list=does-not-exist".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(count_rows(&app, "subscriptions").await, 0);
+}
+
+#[tokio::test]
+async fn each_list_subscription_is_confirmed_on_its_own() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_list(&app, "rust", "Rust weekly").await;
+
+    // Act
+    create_confirmed_list_subscriber(&app, "ursula@example.com", "rust").await;
+    subscribe_to_list(&app, "ursula@example.com", "newsletter").await;
+
+    // Assert
+    // A single subscriber, with two subscriptions in different states
+    assert_eq!(count_rows(&app, "subscriptions").await, 1);
+    assert_eq!(
+        list_subscription_statuses(&app).await,
+        vec![
+            ("newsletter".to_string(), "pending_confirmation".to_string()),
+            ("rust".to_string(), "confirmed".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn the_confirmation_email_names_the_list() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_list(&app, "rust", "Rust weekly").await;
+
+    // Act
+    subscribe_to_list(&app, "ursula@example.com", "rust").await;
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(
+        body["TextBody"]
+            .as_str()
+            .unwrap()
+            .contains("Welcome to Rust weekly!")
+    );
+}
+
+#[tokio::test]
+async fn issues_only_go_out_to_the_subscribers_of_their_lists() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_list(&app, "rust", "Rust weekly").await;
+    create_list(&app, "go", "Go weekly").await;
+    create_confirmed_list_subscriber(&app, "rust@example.com", "rust").await;
+    create_confirmed_list_subscriber(&app, "go@example.com", "go").await;
+    create_confirmed_list_subscriber(&app, "newsletter@example.com", "newsletter").await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_body(serde_json::json!(["rust"])))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(batch_recipients(&app).await, vec!["rust@example.com"]);
+}
+
+#[tokio::test]
+async fn subscribers_of_several_target_lists_get_the_issue_once() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_list(&app, "rust", "Rust weekly").await;
+    create_confirmed_list_subscriber(&app, "both@example.com", "rust").await;
+    create_confirmed_list_subscriber(&app, "both@example.com", "newsletter").await;
+    create_confirmed_list_subscriber(&app, "rust@example.com", "rust").await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_body(serde_json::json!(["rust", "newsletter"])))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(
+        batch_recipients(&app).await,
+        vec!["both@example.com", "rust@example.com"]
+    );
+}
+
+#[tokio::test]
+async fn publishing_to_unknown_or_no_lists_returns_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (serde_json::json!(["does-not-exist"]), "unknown list"),
+        (
+            serde_json::json!(["newsletter", "nope"]),
+            "one unknown list",
+        ),
+        (serde_json::json!([]), "no list"),
+    ];
+
+    for (lists, description) in test_cases {
+        // Act
+        let response = app.post_newsletters(newsletter_body(lists)).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            description
+        );
+    }
+    assert_eq!(count_rows(&app, "newsletter_issues").await, 0);
+}
+
+#[tokio::test]
+async fn the_admin_form_publishes_to_the_chosen_list() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_list(&app, "rust", "Rust weekly").await;
+    create_confirmed_list_subscriber(&app, "rust@example.com", "rust").await;
+    create_confirmed_list_subscriber(&app, "newsletter@example.com", "newsletter").await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let html_page = app.get_admin_html("/admin/newsletters").await;
+    assert!(html_page.contains(r#"<option value="rust">Rust weekly</option>"#));
+    let response = app
+        .post_admin_form(
+            "/admin/newsletters",
+            &serde_json::json!({
+                "title": "Newsletter title",
+                "text_content": "Newsletter body as plain text",
+                "html_content": "<p>Newsletter body as HTML</p>",
+                "idempotency_key": uuid::Uuid::new_v4().to_string(),
+                "list": "rust",
+            }),
+        )
+        .await;
+    assert_response_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(batch_recipients(&app).await, vec!["rust@example.com"]);
+}
+
+#[tokio::test]
+async fn admins_can_create_lists_and_see_their_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    // Act
+    create_list(&app, "rust", "Rust weekly").await;
+    let html_page = app.get_admin_html("/admin/lists").await;
+    assert!(html_page.contains("The list &#39;rust&#39; has been created."));
+    create_confirmed_list_subscriber(&app, "ursula@example.com", "rust").await;
+    subscribe_to_list(&app, "le_guin@example.com", "rust").await;
+
+    // Assert
+    let html_page = app.get_admin_html("/admin/lists").await;
+    assert!(html_page.contains(
+        "<td>Rust weekly</td>\n\t\t\t\t<td>rust</td>\n\t\t\t\t<td>1</td>\n\t\t\t\t<td>1</td>"
+    ));
+}
+
+#[tokio::test]
+async fn invalid_or_duplicate_lists_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let test_cases = vec![
+        ("Not A Slug", "Rust weekly", "is not a valid list slug"),
+        ("rust", "  ", "A list needs a name."),
+        (
+            "newsletter",
+            "Another newsletter",
+            "There is already a list",
+        ),
+    ];
+
+    for (slug, name, error_message) in test_cases {
+        // Act
+        create_list(&app, slug, name).await;
+
+        // Assert
+        let html_page = app.get_admin_html("/admin/lists").await;
+        assert!(
+            html_page.contains(error_message),
+            "Expected '{}' when creating the list '{}'",
+            error_message,
+            slug
+        );
+    }
+    let slugs: BTreeSet<_> = sqlx::query!("SELECT slug FROM lists")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.slug)
+        .collect();
+    assert_eq!(slugs, BTreeSet::from(["newsletter".to_string()]));
+}
+
+#[tokio::test]
+async fn issues_sent_to_other_lists_are_not_in_the_public_archive() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_list(&app, "rust", "Rust weekly").await;
+    let response = app
+        .post_newsletters(newsletter_body(serde_json::json!(["rust"])))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act
+    let archive = reqwest::get(format!("{}/issues", app.address))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(!archive.contains("Newsletter title"));
+}
+
+#[tokio::test]
+async fn the_browser_link_of_an_issue_sent_to_another_list_works() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_list(&app, "rust", "Rust weekly").await;
+    create_confirmed_list_subscriber(&app, "ursula@example.com", "rust").await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_body(serde_json::json!(["rust"])))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let batch_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|r| r.url.path() == "/email/batch")
+        .unwrap();
+    let batch: serde_json::Value = serde_json::from_slice(&batch_request.body).unwrap();
+
+    // Act
+    let response = reqwest::get(app.get_issue_link(&batch[0])).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Newsletter title"));
+}
@@ -26,3 +26,21 @@ async fn an_error_flash_message_is_set_on_failure() {
     // Assert - Part 3 - Check that the page no longer has the flash message
     assert!(!html_page.contains(r#"<p><i>authentication failed</i></p>"#));
 }
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Login
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let response = app.post_login(&login_body).await;
+    assert_response_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_html("/admin/dashboard").await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
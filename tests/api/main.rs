@@ -1,7 +1,21 @@
 //! tests/api/main.rs
+mod admin_dashboard;
+mod admin_newsletters;
+mod change_password;
+mod dead_letters;
+mod drafts;
 mod health_check;
 mod helpers;
+mod issues;
+mod lists;
 mod login;
 mod newsletter;
+mod scheduled_issues;
+mod segments;
+mod subscribers;
+mod subscribers_export;
+mod subscribers_import;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_data;
+mod unsubscribe;
@@ -4,7 +4,10 @@ use wiremock::{
     matchers::{method, path},
 };
 
-use crate::helpers::{ConfirmationLinks, TestApp, spawn_app};
+use crate::helpers::{
+    PostmarkBatchResponder, count_rows, create_confirmed_subscriber, create_unconfirmed_subscriber,
+    make_pending_deliveries_due, publish_test_newsletter, spawn_app,
+};
 
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -12,9 +15,9 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     let app = spawn_app().await;
     create_unconfirmed_subscriber(&app).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(PostmarkBatchResponder)
         // We assert that no request is fired at Postmark
         .expect(0)
         .mount(&app.email_server)
@@ -33,7 +36,8 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
 
     let response = app.post_newsletters(newsletter_request_body).await;
     // Assert
-    assert_eq!(response.status().as_u16(), 200)
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
     // Mock verifies on Drop that we haven't sent the newsletter email
 }
 
@@ -43,9 +47,9 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     let app = spawn_app().await;
     create_confirmed_subscriber(&app).await;
 
-    let _mock_guard = Mock::given(path("/email"))
+    let _mock_guard = Mock::given(path("/email/batch"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(PostmarkBatchResponder)
         .expect(1)
         .mount_as_scoped(&app.email_server)
         .await;
@@ -64,10 +68,337 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     let response = app.post_newsletters(newsletter_request_body).await;
 
     // Assert
-    assert_eq!(response.status().as_u16(), 200)
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
     // Mock verifies on Drop that we haven't sent the newsletter email
 }
 
+#[tokio::test]
+async fn newsletters_are_queued_and_delivered_in_the_background() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Publish the newsletter
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Assert - Part 1 - A delivery task is waiting in the queue
+    let n_tasks = sqlx::query!("SELECT COUNT(*) as \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tasks, 1);
+
+    // Act - Part 2 - Run the worker
+    app.dispatch_all_pending_emails().await;
+
+    // Assert - Part 2 - The queue has been drained
+    let n_tasks = sqlx::query!("SELECT COUNT(*) as \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tasks, 0);
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_later() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    publish_test_newsletter(&app).await;
+
+    // Act - Part 1 - The email provider is down
+    let mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    drop(mock_guard);
+
+    // Assert - Part 1 - The task is rescheduled in the future
+    let task = sqlx::query!(
+        "SELECT n_retries, execute_after > now() as \"in_the_future!\", last_error \
+        FROM issue_delivery_queue"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(task.n_retries, 1);
+    assert!(task.in_the_future);
+    assert!(task.last_error.is_some());
+
+    // Act - Part 2 - The email provider is back, and the retry is due
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    make_pending_deliveries_due(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert - Part 2 - The issue has been delivered
+    assert_eq!(count_rows(&app, "issue_delivery_queue").await, 0);
+    assert_eq!(count_rows(&app, "issue_delivery_dead_letters").await, 0);
+}
+
+#[tokio::test]
+async fn an_issue_that_can_not_be_rendered_does_not_block_the_others() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    publish_test_newsletter(&app).await;
+    // Deliveries of an issue that can't be loaded anymore
+    let broken_issue_id = Uuid::new_v4();
+    for statement in [
+        "ALTER TABLE newsletter_issue_lists \
+        DROP CONSTRAINT newsletter_issue_lists_newsletter_issue_id_fkey",
+        "ALTER TABLE issue_delivery_queue \
+        DROP CONSTRAINT issue_delivery_queue_newsletter_issue_id_fkey",
+    ] {
+        sqlx::query(statement).execute(&app.db_pool).await.unwrap();
+    }
+    sqlx::query!(
+        "INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id) \
+        SELECT $1, list_id FROM lists",
+        broken_issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email) \
+        SELECT $1, email FROM subscriptions",
+        broken_issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let task = sqlx::query!("SELECT newsletter_issue_id, n_retries FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(task.newsletter_issue_id, broken_issue_id);
+    assert_eq!(task.n_retries, 1);
+}
+
+#[tokio::test]
+async fn deliveries_are_dead_lettered_after_the_max_number_of_attempts() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    publish_test_newsletter(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(app.delivery_settings.max_delivery_attempts as u64)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    for _ in 0..app.delivery_settings.max_delivery_attempts {
+        make_pending_deliveries_due(&app).await;
+        app.dispatch_all_pending_emails().await;
+    }
+
+    // Assert
+    assert_eq!(count_rows(&app, "issue_delivery_queue").await, 0);
+    let dead_letter =
+        sqlx::query!("SELECT subscriber_email, n_attempts FROM issue_delivery_dead_letters")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(dead_letter.subscriber_email, "ursula_le_guin@gmail.com");
+    assert_eq!(
+        dead_letter.n_attempts as u32,
+        app.delivery_settings.max_delivery_attempts
+    );
+}
+
+#[tokio::test]
+async fn markdown_content_is_delivered_as_html_and_plain_text() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "markdown": "Hello *there*, read [the post](https://example.com/post).",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = body[0]["HtmlBody"].as_str().unwrap();
+    let text = body[0]["TextBody"].as_str().unwrap();
+    assert!(html.contains(
+        r#"<p>Hello <em>there</em>, read <a href="https://example.com/post" rel="noopener noreferrer">the post</a>.</p>"#
+    ));
+    assert!(text.contains("Hello there, read the post [1]."));
+    assert!(text.contains("[1] https://example.com/post"));
+}
+
+#[tokio::test]
+async fn disallowed_markup_is_stripped_and_reported() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": r#"<p onclick="steal()">Newsletter body<script>alert(1)</script><img src="https://t.example.com/pixel.gif"></p>"#,
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body["sanitization"],
+        serde_json::json!({
+            "removed_tags": { "img": 1, "script": 1 },
+            "removed_attributes": { "img[src]": 1, "p[onclick]": 1 },
+        })
+    );
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = email[0]["HtmlBody"].as_str().unwrap();
+    assert!(html.contains("<p>Newsletter body</p>"));
+    assert!(!html.contains("<script"));
+    assert!(!html.contains("alert(1)"));
+    assert!(!html.contains("pixel.gif"));
+}
+
+#[tokio::test]
+async fn placeholders_are_filled_in_for_each_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Hi {{ name }} ({{ email }})",
+            "html": r#"<p>Hi {{ name }}</p><a href="{{ unsubscribe_url }}">Leave</a>"#,
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = email[0]["HtmlBody"].as_str().unwrap();
+    let text = email[0]["TextBody"].as_str().unwrap();
+    assert!(html.contains("<p>Hi le guin</p>"));
+    assert!(html.contains("/subscriptions/unsubscribe?subscriber_id="));
+    assert!(!html.contains("{{"));
+    assert!(text.contains("Hi le guin (ursula_le_guin@gmail.com)"));
+}
+
+#[tokio::test]
+async fn unknown_placeholders_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Hi {{ first_name }}",
+            "html": "<p>Hi {{ name }}</p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(count_rows(&app, "newsletter_issues").await, 0);
+}
+
 #[tokio::test]
 async fn newsletters_returns_400_for_invalid_data() {
     // Arrange
@@ -90,6 +421,24 @@ async fn newsletters_returns_400_for_invalid_data() {
         ),
         (serde_json::json!({}), "empty_json"),
         (serde_json::json!({"lunita": "solcito"}), "nonsense_payload"),
+        (
+            serde_json::json!({
+                "title": "Newsletter!",
+                "content": { "html": "<p>Newsletter body as HTML</p>" }
+            }),
+            "missing_text_content",
+        ),
+        (
+            serde_json::json!({
+                "title": "Newsletter!",
+                "content": {
+                    "markdown": "Newsletter body",
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>"
+                }
+            }),
+            "both_markdown_and_rendered_content",
+        ),
     ];
 
     for (invalid_body, error_msg) in test_cases {
@@ -191,44 +540,102 @@ async fn invalid_password_is_rejected() {
     )
 }
 
-// Use the public API of the application under test to create
-/// an unconfirmed subscriber.
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
 
-    // This allows us to avoid making a request to Postmark by accident
-    let _mock_guard = Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
+        .respond_with(PostmarkBatchResponder)
         .expect(1)
-        .mount_as_scoped(&app.email_server)
+        .mount(&app.email_server)
         .await;
 
-    app.post_subscriptions(body.into())
-        .await
-        .error_for_status()
-        .unwrap();
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let idempotency_key = Uuid::new_v4().to_string();
 
-    // We now inspect the requests received by the mock Postmark server
-    // to retrieve the confirmation link and return it
+    // Act - Part 1 - Publish the newsletter
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body.clone(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
 
-    let email_request = &app
-        .email_server
-        .received_requests()
-        .await
-        .unwrap()
-        .pop()
-        .unwrap();
+    // Act - Part 2 - Publish the newsletter **again**
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body, &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
 
-    app.get_confirmation_links(email_request)
+    app.dispatch_all_pending_emails().await;
+
+    // Mock verifies on Drop that we have sent the newsletter email **once**
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
-    reqwest::get(confirmation_link.html)
-        .await
-        .unwrap()
-        .error_for_status()
-        .unwrap();
+#[tokio::test]
+async fn concurrent_newsletter_submission_is_handled_gracefully() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    // Act - Submit two newsletter forms concurrently
+    let response1 = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body.clone(), &idempotency_key);
+    let response2 =
+        app.post_newsletters_with_idempotency_key(newsletter_request_body, &idempotency_key);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    // Assert
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+    app.dispatch_all_pending_emails().await;
+
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn an_invalid_idempotency_key_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+
+    // Act
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body, &"a".repeat(50))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
 }
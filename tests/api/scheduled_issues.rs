@@ -0,0 +1,305 @@
+use chrono::{TimeDelta, Utc};
+use uuid::Uuid;
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{any, method, path},
+};
+use zero2prod::issue_scheduler::release_due_issues;
+
+use crate::helpers::{
+    PostmarkBatchResponder, TestApp, assert_response_is_redirect_to, count_rows,
+    create_confirmed_subscriber, publish_test_newsletter, spawn_app,
+};
+
+fn scheduled_newsletter_body(scheduled_for: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Scheduled title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "scheduled_for": scheduled_for,
+    })
+}
+
+fn in_one_day() -> String {
+    (Utc::now() + TimeDelta::days(1)).to_rfc3339()
+}
+
+/// Pretends the scheduled time has come
+async fn make_scheduled_issues_due(app: &TestApp) {
+    sqlx::query!("UPDATE newsletter_issues SET scheduled_for = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_not_delivered_nor_listed_before_their_time() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(scheduled_newsletter_body(&in_one_day()))
+        .await;
+    release_due_issues(&app.db_pool).await.unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(count_rows(&app, "issue_delivery_queue").await, 0);
+    let issue_id = app.get_issue_id().await;
+    let archive = reqwest::get(format!("{}/issues", app.address))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(!archive.contains("Scheduled title"));
+    let issue_page = reqwest::get(format!("{}/issues/{}", app.address, issue_id))
+        .await
+        .unwrap();
+    assert_eq!(issue_page.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_delivered_once_due() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_newsletters(scheduled_newsletter_body(&in_one_day()))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    make_scheduled_issues_due(&app).await;
+    release_due_issues(&app.db_pool).await.unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let archive = reqwest::get(format!("{}/issues", app.address))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(archive.contains("Scheduled title"));
+    // Released issues are not released again
+    release_due_issues(&app.db_pool).await.unwrap();
+    assert_eq!(count_rows(&app, "issue_delivery_queue").await, 0);
+}
+
+#[tokio::test]
+async fn an_issue_that_can_not_be_released_does_not_hold_back_the_others() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    for _ in 0..2 {
+        app.post_newsletters(scheduled_newsletter_body(&in_one_day()))
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+    // A segment that can't be parsed anymore, its deliveries can't be enqueued
+    let broken_issue_id = sqlx::query_scalar!(
+        "UPDATE newsletter_issues SET segment = 'beta and' \
+        WHERE newsletter_issue_id = (SELECT newsletter_issue_id FROM newsletter_issues LIMIT 1) \
+        RETURNING newsletter_issue_id"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    make_scheduled_issues_due(&app).await;
+
+    // Act
+    let n_released = release_due_issues(&app.db_pool).await.unwrap();
+
+    // Assert
+    assert_eq!(n_released, 1);
+    let unpublished = sqlx::query_scalar!(
+        "SELECT newsletter_issue_id FROM newsletter_issues WHERE published_at IS NULL"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(unpublished, vec![broken_issue_id]);
+    assert_eq!(count_rows(&app, "issue_delivery_queue").await, 1);
+}
+
+#[tokio::test]
+async fn issues_cannot_be_scheduled_in_the_past() {
+    // Arrange
+    let app = spawn_app().await;
+    let yesterday = (Utc::now() - TimeDelta::days(1)).to_rfc3339();
+
+    // Act
+    let response = app
+        .post_newsletters(scheduled_newsletter_body(&yesterday))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(count_rows(&app, "newsletter_issues").await, 0);
+}
+
+#[tokio::test]
+async fn issues_can_be_scheduled_from_the_admin_form() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    // Act - Part 1 - Submit the form
+    let response = app
+        .post_admin_form(
+            "/admin/newsletters",
+            &serde_json::json!({
+                "title": "Scheduled title",
+                "text_content": "Newsletter body as plain text",
+                "html_content": "<p>Newsletter body as HTML</p>",
+                "idempotency_key": Uuid::new_v4().to_string(),
+                "scheduled_for": in_one_day(),
+            }),
+        )
+        .await;
+    assert_response_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_html("/admin/newsletters").await;
+    assert!(html_page.contains("The newsletter issue has been scheduled"));
+
+    // Act - Part 3 - The issue waits in the scheduled issues page
+    let html_page = app.get_admin_html("/admin/scheduled_issues").await;
+    assert!(html_page.contains("Scheduled title"));
+}
+
+#[tokio::test]
+async fn an_invalid_send_time_is_rejected_by_the_admin_form() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    // Act
+    let response = app
+        .post_admin_form(
+            "/admin/newsletters",
+            &serde_json::json!({
+                "title": "Scheduled title",
+                "text_content": "Newsletter body as plain text",
+                "html_content": "<p>Newsletter body as HTML</p>",
+                "idempotency_key": Uuid::new_v4().to_string(),
+                "scheduled_for": "next monday",
+            }),
+        )
+        .await;
+
+    // Assert
+    assert_response_is_redirect_to(&response, "/admin/newsletters");
+    let html_page = app.get_admin_html("/admin/newsletters").await;
+    assert!(html_page.contains("is not a valid date and time"));
+    assert_eq!(count_rows(&app, "newsletter_issues").await, 0);
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_cancelled() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_newsletters(scheduled_newsletter_body(&in_one_day()))
+        .await
+        .error_for_status()
+        .unwrap();
+    let issue_id = app.get_issue_id().await;
+    app.login_test_user().await;
+
+    // Act
+    let response = app
+        .post_admin_form(
+            "/admin/scheduled_issues/cancel",
+            &serde_json::json!({
+                "newsletter_issue_id": issue_id,
+            }),
+        )
+        .await;
+
+    // Assert
+    assert_response_is_redirect_to(&response, "/admin/scheduled_issues");
+    let html_page = app.get_admin_html("/admin/scheduled_issues").await;
+    assert!(html_page.contains("The scheduled issue has been cancelled."));
+    assert_eq!(count_rows(&app, "newsletter_issues").await, 0);
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_rescheduled() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_newsletters(scheduled_newsletter_body(&in_one_day()))
+        .await
+        .error_for_status()
+        .unwrap();
+    let issue_id = app.get_issue_id().await;
+    app.login_test_user().await;
+    let in_two_days = Utc::now() + TimeDelta::days(2);
+
+    // Act
+    let response = app
+        .post_admin_form(
+            "/admin/scheduled_issues/reschedule",
+            &serde_json::json!({
+                "newsletter_issue_id": issue_id,
+                "scheduled_for": in_two_days.to_rfc3339(),
+            }),
+        )
+        .await;
+
+    // Assert
+    assert_response_is_redirect_to(&response, "/admin/scheduled_issues");
+    let html_page = app.get_admin_html("/admin/scheduled_issues").await;
+    assert!(html_page.contains("The issue has been rescheduled."));
+    let scheduled_for = sqlx::query_scalar!("SELECT scheduled_for FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        scheduled_for.timestamp_micros(),
+        in_two_days.timestamp_micros()
+    );
+}
+
+#[tokio::test]
+async fn published_issues_cannot_be_cancelled() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_test_newsletter(&app).await;
+    let issue_id = app.get_issue_id().await;
+    app.login_test_user().await;
+
+    // Act
+    app.post_admin_form(
+        "/admin/scheduled_issues/cancel",
+        &serde_json::json!({
+            "newsletter_issue_id": issue_id,
+        }),
+    )
+    .await;
+
+    // Assert
+    let html_page = app.get_admin_html("/admin/scheduled_issues").await;
+    assert!(html_page.contains("already been published"));
+    assert_eq!(count_rows(&app, "newsletter_issues").await, 1);
+}
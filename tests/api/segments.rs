@@ -0,0 +1,421 @@
+use std::collections::BTreeSet;
+
+use wiremock::{
+    Mock,
+    matchers::{method, path},
+};
+use zero2prod::issue_scheduler::release_due_issues;
+
+use crate::helpers::{
+    PostmarkBatchResponder, TestApp, assert_response_is_redirect_to, batch_recipients, count_rows,
+    create_confirmed_list_subscriber, spawn_app, subscribe_to_list,
+};
+
+/// Tags a subscriber through the admin form, the test user has to be logged in
+async fn tag_subscriber(app: &TestApp, email: &str, add_tags: &str, attributes: &str) {
+    let response = app
+        .post_admin_form(
+            "/admin/segments/subscriber",
+            &serde_json::json!({
+                "email": email,
+                "add_tags": add_tags,
+                "attributes": attributes,
+            }),
+        )
+        .await;
+    assert_response_is_redirect_to(&response, "/admin/segments");
+}
+
+async fn subscriber_tags(app: &TestApp) -> BTreeSet<(String, String)> {
+    sqlx::query!(
+        r#"
+        SELECT s.email, t.tag
+        FROM subscriber_tags t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| (r.email, r.tag))
+    .collect()
+}
+
+/// Three confirmed subscribers of the main newsletter, and a pending one who
+/// is a beta tester as well
+async fn create_tagged_subscribers(app: &TestApp) {
+    for email in ["beta@example.com", "paid@example.com", "plain@example.com"] {
+        create_confirmed_list_subscriber(app, email, "newsletter").await;
+    }
+    subscribe_to_list(app, "pending@example.com", "newsletter").await;
+    tag_subscriber(app, "beta@example.com", "beta", "plan = free").await;
+    tag_subscriber(app, "paid@example.com", "beta, paid", "plan = pro").await;
+    tag_subscriber(app, "pending@example.com", "beta", "").await;
+}
+
+fn segmented_newsletter_body(segment: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "segment": segment,
+    })
+}
+
+#[tokio::test]
+async fn admins_can_tag_subscribers_and_set_their_attributes() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_confirmed_list_subscriber(&app, "ursula@example.com", "newsletter").await;
+
+    // Act - Part 1 - Tag
+    tag_subscriber(
+        &app,
+        "ursula@example.com",
+        "Beta, paid",
+        "plan = pro\ncountry=es",
+    )
+    .await;
+    let html_page = app.get_admin_html("/admin/segments").await;
+    assert!(html_page.contains("The tags and attributes of ursula@example.com have been updated."));
+
+    // Act - Part 2 - Remove a tag and an attribute
+    let response = app
+        .post_admin_form(
+            "/admin/segments/subscriber",
+            &serde_json::json!({
+                "email": "ursula@example.com",
+                "remove_tags": "paid",
+                "attributes": "country =",
+            }),
+        )
+        .await;
+    assert_response_is_redirect_to(&response, "/admin/segments");
+
+    // Assert
+    assert_eq!(
+        subscriber_tags(&app).await,
+        BTreeSet::from([("ursula@example.com".to_string(), "beta".to_string())])
+    );
+    let attributes = sqlx::query!("SELECT name, value FROM subscriber_attributes")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(attributes.len(), 1);
+    assert_eq!(attributes[0].name, "plan");
+    assert_eq!(attributes[0].value, "pro");
+    let html_page = app.get_admin_html("/admin/segments").await;
+    assert!(html_page.contains("<td>beta</td>\n\t\t\t\t<td>1</td>"));
+}
+
+#[tokio::test]
+async fn invalid_tags_or_unknown_subscribers_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_confirmed_list_subscriber(&app, "ursula@example.com", "newsletter").await;
+    let test_cases = vec![
+        ("ursula@example.com", "be ta!", "", "is not a valid tag"),
+        (
+            "ursula@example.com",
+            "beta",
+            "plan pro",
+            "one &#39;name = value&#39; per line",
+        ),
+        (
+            "nobody@example.com",
+            "beta",
+            "",
+            "There is no subscriber with the address nobody@example.com.",
+        ),
+    ];
+
+    for (email, add_tags, attributes, error_message) in test_cases {
+        // Act
+        tag_subscriber(&app, email, add_tags, attributes).await;
+
+        // Assert
+        let html_page = app.get_admin_html("/admin/segments").await;
+        assert!(
+            html_page.contains(error_message),
+            "Expected '{}' when tagging with '{}' and '{}'",
+            error_message,
+            add_tags,
+            attributes
+        );
+    }
+    assert_eq!(count_rows(&app, "subscriber_tags").await, 0);
+    assert_eq!(count_rows(&app, "subscriber_attributes").await, 0);
+}
+
+#[tokio::test]
+async fn issues_with_a_segment_only_go_out_to_its_confirmed_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_tagged_subscribers(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(segmented_newsletter_body("beta and not paid"))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(batch_recipients(&app).await, vec!["beta@example.com"]);
+}
+
+#[tokio::test]
+async fn segments_can_match_on_attributes() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_tagged_subscribers(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(segmented_newsletter_body(r#"plan = "pro" or not beta"#))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(
+        batch_recipients(&app).await,
+        vec!["paid@example.com", "plain@example.com"]
+    );
+}
+
+#[tokio::test]
+async fn publishing_with_an_invalid_segment_returns_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    for segment in ["beta and", "(beta", "plan = pro", "   "] {
+        // Act
+        let response = app
+            .post_newsletters(segmented_newsletter_body(segment))
+            .await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the segment was '{}'.",
+            segment
+        );
+    }
+    assert_eq!(count_rows(&app, "newsletter_issues").await, 0);
+}
+
+#[tokio::test]
+async fn the_api_previews_how_many_subscribers_an_issue_would_reach() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_tagged_subscribers(&app).await;
+    let test_cases = vec![
+        (serde_json::json!({}), 3),
+        (serde_json::json!({"segment": "beta"}), 2),
+        (serde_json::json!({"segment": "beta and not paid"}), 1),
+        (serde_json::json!({"segment": "nobody-has-this"}), 0),
+    ];
+
+    for (body, expected) in test_cases {
+        // Act
+        let response = app
+            .api_request(reqwest::Method::POST, "/newsletters/preview")
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        let preview: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(
+            preview["recipients"], expected,
+            "Unexpected preview for {}",
+            body
+        );
+    }
+    // Previews are not issues
+    assert_eq!(count_rows(&app, "newsletter_issues").await, 0);
+}
+
+#[tokio::test]
+async fn previews_require_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters/preview", &app.address))
+        .json(&serde_json::json!({"segment": "beta"}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_admin_page_previews_the_audience_of_a_segment() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_tagged_subscribers(&app).await;
+
+    // Act
+    let html_page = app
+        .get_admin_html("/admin/segments?list=newsletter&segment=beta")
+        .await;
+    let invalid_html_page = app
+        .get_admin_html("/admin/segments?list=newsletter&segment=beta%20and")
+        .await;
+
+    // Assert
+    assert!(html_page.contains("This issue would go out to 2 subscriber(s)."));
+    assert!(invalid_html_page.contains("The segment ends unexpectedly."));
+}
+
+#[tokio::test]
+async fn the_admin_form_publishes_to_a_segment() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_tagged_subscribers(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_admin_form(
+            "/admin/newsletters",
+            &serde_json::json!({
+                "title": "Newsletter title",
+                "text_content": "Newsletter body as plain text",
+                "html_content": "<p>Newsletter body as HTML</p>",
+                "idempotency_key": uuid::Uuid::new_v4().to_string(),
+                "list": "newsletter",
+                "segment": "paid",
+            }),
+        )
+        .await;
+    assert_response_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(batch_recipients(&app).await, vec!["paid@example.com"]);
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_segmented_when_they_are_released() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_tagged_subscribers(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .mount(&app.email_server)
+        .await;
+    let mut body = segmented_newsletter_body("beta");
+    body["scheduled_for"] = (chrono::Utc::now() + chrono::TimeDelta::days(1))
+        .to_rfc3339()
+        .into();
+    let response = app.post_newsletters(body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    // Tagged after the issue was scheduled, but before it goes out
+    tag_subscriber(&app, "plain@example.com", "beta", "").await;
+
+    // Act
+    sqlx::query!("UPDATE newsletter_issues SET scheduled_for = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    release_due_issues(&app.db_pool).await.unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(
+        batch_recipients(&app).await,
+        vec!["beta@example.com", "paid@example.com", "plain@example.com"]
+    );
+}
+
+#[tokio::test]
+async fn issues_sent_to_a_segment_are_not_in_the_public_archive() {
+    // Arrange
+    let app = spawn_app().await;
+    let response = app
+        .post_newsletters(segmented_newsletter_body("beta"))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act
+    let archive = reqwest::get(format!("{}/issues", app.address))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(!archive.contains("Newsletter title"));
+}
+
+#[tokio::test]
+async fn the_browser_link_of_an_issue_sent_to_a_segment_works() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_confirmed_list_subscriber(&app, "beta@example.com", "newsletter").await;
+    tag_subscriber(&app, "beta@example.com", "beta", "").await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(segmented_newsletter_body("beta"))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let batch_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|r| r.url.path() == "/email/batch")
+        .unwrap();
+    let batch: serde_json::Value = serde_json::from_slice(&batch_request.body).unwrap();
+
+    // Act
+    let response = reqwest::get(app.get_issue_link(&batch[0])).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Newsletter title"));
+}
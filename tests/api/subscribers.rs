@@ -0,0 +1,401 @@
+use reqwest::Method;
+use uuid::Uuid;
+
+use crate::helpers::{
+    TestApp, assert_response_is_redirect_to, count_rows, create_confirmed_list_subscriber,
+    spawn_app, subscribe_to_list,
+};
+
+/// The emails of a page of the API, and its cursor to the next one
+async fn get_subscribers(app: &TestApp, query: &str) -> (Vec<String>, Option<String>) {
+    let response = app
+        .api_request(Method::GET, &format!("/subscribers?{}", query))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let emails = body["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["email"].as_str().unwrap().to_string())
+        .collect();
+    (emails, body["next_cursor"].as_str().map(str::to_string))
+}
+
+async fn newsletter_status(app: &TestApp, email: &str) -> String {
+    sqlx::query_scalar!(
+        r#"
+        SELECT ls.status
+        FROM list_subscriptions ls
+        JOIN subscriptions s ON s.id = ls.subscriber_id
+        JOIN lists l ON l.list_id = ls.list_id
+        WHERE s.email = $1 AND l.slug = 'newsletter'
+        "#,
+        email
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn the_subscribers_api_requires_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/subscribers", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        r#"Basic realm="subscribers""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_status_list_and_search() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let response = app
+        .post_admin_form(
+            "/admin/lists",
+            &serde_json::json!({"slug": "rust", "name": "Rust weekly"}),
+        )
+        .await;
+    assert_response_is_redirect_to(&response, "/admin/lists");
+    create_confirmed_list_subscriber(&app, "ursula@example.com", "newsletter").await;
+    subscribe_to_list(&app, "pending@example.com", "newsletter").await;
+    create_confirmed_list_subscriber(&app, "rustacean@example.com", "rust").await;
+    let test_cases = vec![
+        (
+            "status=confirmed",
+            vec!["rustacean@example.com", "ursula@example.com"],
+        ),
+        ("status=pending_confirmation", vec!["pending@example.com"]),
+        ("list=rust", vec!["rustacean@example.com"]),
+        (
+            "list=newsletter&status=confirmed",
+            vec!["ursula@example.com"],
+        ),
+        ("search=URSULA", vec!["ursula@example.com"]),
+        ("search=nobody", vec![]),
+    ];
+
+    for (query, expected) in test_cases {
+        // Act
+        let (mut emails, _) = get_subscribers(&app, query).await;
+
+        // Assert
+        emails.sort();
+        assert_eq!(emails, expected, "Unexpected subscribers for '{}'", query);
+    }
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_subscription_date() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_list_subscriber(&app, "old@example.com", "newsletter").await;
+    create_confirmed_list_subscriber(&app, "new@example.com", "newsletter").await;
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = '2020-06-01T12:00:00Z' WHERE email = $1",
+        "old@example.com"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let (before, _) = get_subscribers(&app, "subscribed_before=2021-01-01").await;
+    let (after, _) = get_subscribers(&app, "subscribed_after=2020-06-01T12:00:01%2B00:00").await;
+    let (between, _) = get_subscribers(
+        &app,
+        "subscribed_after=2020-06-01&subscribed_before=2020-06-02",
+    )
+    .await;
+
+    // Assert
+    assert_eq!(before, vec!["old@example.com"]);
+    assert_eq!(after, vec!["new@example.com"]);
+    assert_eq!(between, vec!["old@example.com"]);
+}
+
+#[tokio::test]
+async fn subscribers_are_paginated_with_a_cursor() {
+    // Arrange
+    let app = spawn_app().await;
+    for email in ["a@example.com", "b@example.com", "c@example.com"] {
+        create_confirmed_list_subscriber(&app, email, "newsletter").await;
+    }
+
+    // Act
+    let (first_page, cursor) = get_subscribers(&app, "limit=2").await;
+    let cursor = cursor.expect("The first page should have a cursor");
+    let (second_page, last_cursor) =
+        get_subscribers(&app, &format!("limit=2&cursor={}", cursor)).await;
+
+    // Assert
+    // The most recent subscribers first
+    assert_eq!(first_page, vec!["c@example.com", "b@example.com"]);
+    assert_eq!(second_page, vec!["a@example.com"]);
+    assert!(last_cursor.is_none());
+}
+
+#[tokio::test]
+async fn invalid_subscriber_queries_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    for query in [
+        "status=active",
+        "subscribed_after=yesterday",
+        "cursor=not-a-cursor",
+        "limit=0",
+        "limit=101",
+    ] {
+        // Act
+        let response = app
+            .api_request(Method::GET, &format!("/subscribers?{}", query))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request for '{}'.",
+            query
+        );
+    }
+}
+
+#[tokio::test]
+async fn a_subscriber_can_be_renamed() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_list_subscriber(&app, "ursula@example.com", "newsletter").await;
+    let id = app.subscriber_id("ursula@example.com").await;
+    let path = format!("/subscribers/{}", id);
+
+    // Act
+    let response = app
+        .api_request(Method::PATCH, &path)
+        .json(&serde_json::json!({"name": "Ursula K. Le Guin"}))
+        .send()
+        .await
+        .unwrap();
+    let invalid_response = app
+        .api_request(Method::PATCH, &path)
+        .json(&serde_json::json!({"name": "<script>"}))
+        .send()
+        .await
+        .unwrap();
+    let unknown_response = app
+        .api_request(Method::PATCH, &format!("/subscribers/{}", Uuid::new_v4()))
+        .json(&serde_json::json!({"name": "Ursula"}))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["name"], "Ursula K. Le Guin");
+    assert_eq!(invalid_response.status().as_u16(), 400);
+    assert_eq!(unknown_response.status().as_u16(), 404);
+    let subscriber = app
+        .api_request(Method::GET, &path)
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(subscriber["name"], "Ursula K. Le Guin");
+}
+
+#[tokio::test]
+async fn pending_subscribers_can_be_confirmed_manually() {
+    // Arrange
+    let app = spawn_app().await;
+    subscribe_to_list(&app, "ursula@example.com", "newsletter").await;
+    let id = app.subscriber_id("ursula@example.com").await;
+
+    // Act
+    let response = app
+        .api_request(Method::POST, &format!("/subscribers/{}/confirm", id))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body["lists"],
+        serde_json::json!([{"list": "newsletter", "status": "confirmed"}])
+    );
+    assert_eq!(
+        newsletter_status(&app, "ursula@example.com").await,
+        "confirmed"
+    );
+}
+
+#[tokio::test]
+async fn confirming_twice_records_a_single_consent_event() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_list_subscriber(&app, "ursula@example.com", "newsletter").await;
+    let id = app.subscriber_id("ursula@example.com").await;
+
+    // Act
+    let response = app
+        .api_request(Method::POST, &format!("/subscribers/{}/confirm", id))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let events = sqlx::query_scalar!("SELECT event FROM consent_events ORDER BY occurred_at")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events, vec!["subscribed", "confirmed"]);
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_can_not_be_confirmed_manually() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_list_subscriber(&app, "ursula@example.com", "newsletter").await;
+    sqlx::query!("UPDATE list_subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let id = app.subscriber_id("ursula@example.com").await;
+
+    // Act
+    let response = app
+        .api_request(Method::POST, &format!("/subscribers/{}/confirm", id))
+        .json(&serde_json::json!({"list": "newsletter"}))
+        .send()
+        .await
+        .unwrap();
+    let unknown_list_response = app
+        .api_request(Method::POST, &format!("/subscribers/{}/confirm", id))
+        .json(&serde_json::json!({"list": "does-not-exist"}))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    assert_eq!(unknown_list_response.status().as_u16(), 400);
+    assert_eq!(
+        newsletter_status(&app, "ursula@example.com").await,
+        "unsubscribed"
+    );
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_removes_their_tokens_and_subscriptions() {
+    // Arrange
+    let app = spawn_app().await;
+    subscribe_to_list(&app, "ursula@example.com", "newsletter").await;
+    let id = app.subscriber_id("ursula@example.com").await;
+    let path = format!("/subscribers/{}", id);
+
+    // Act
+    let response = app.api_request(Method::DELETE, &path).send().await.unwrap();
+    let second_response = app.api_request(Method::DELETE, &path).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(second_response.status().as_u16(), 404);
+    assert_eq!(count_rows(&app, "subscriptions").await, 0);
+    assert_eq!(count_rows(&app, "subscription_tokens").await, 0);
+    assert_eq!(count_rows(&app, "list_subscriptions").await, 0);
+}
+
+#[tokio::test]
+async fn admins_can_browse_and_search_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    for email in ["a@example.com", "b@example.com", "c@example.com"] {
+        create_confirmed_list_subscriber(&app, email, "newsletter").await;
+    }
+
+    // Act
+    let html_page = app.get_admin_html("/admin/subscribers?limit=2").await;
+    let search_page = app.get_admin_html("/admin/subscribers?search=b%40").await;
+    let invalid_page = app.get_admin_html("/admin/subscribers?status=active").await;
+
+    // Assert
+    assert!(html_page.contains("c@example.com"));
+    assert!(html_page.contains("b@example.com"));
+    assert!(!html_page.contains("a@example.com"));
+    assert!(html_page.contains("Next page"));
+    assert!(search_page.contains("b@example.com"));
+    assert!(!search_page.contains("c@example.com"));
+    assert!(invalid_page.contains("&#39;active&#39; is not a status"));
+}
+
+#[tokio::test]
+async fn admins_can_rename_confirm_and_delete_a_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    subscribe_to_list(&app, "ursula@example.com", "newsletter").await;
+    let id = app.subscriber_id("ursula@example.com").await;
+    let page = format!("/admin/subscribers/{}", id);
+
+    // Act - Part 1 - Rename
+    let response = app
+        .post_admin_form(
+            &format!("{}/name", page),
+            &serde_json::json!({"name": "Ursula K. Le Guin"}),
+        )
+        .await;
+    assert_response_is_redirect_to(&response, &page);
+    let html_page = app.get_admin_html(&page).await;
+    assert!(html_page.contains("The name has been changed."));
+    assert!(html_page.contains("Ursula K. Le Guin"));
+
+    // Act - Part 2 - Confirm
+    assert!(html_page.contains("Confirm the subscription to newsletter"));
+    let response = app
+        .post_admin_form(
+            &format!("{}/confirm", page),
+            &serde_json::json!({"list": "newsletter"}),
+        )
+        .await;
+    assert_response_is_redirect_to(&response, &page);
+    let html_page = app.get_admin_html(&page).await;
+    assert!(html_page.contains("The subscription to &#39;newsletter&#39; has been confirmed."));
+    assert_eq!(
+        newsletter_status(&app, "ursula@example.com").await,
+        "confirmed"
+    );
+
+    // Act - Part 3 - Delete
+    let response = app
+        .post_admin_form(&format!("{}/delete", page), &serde_json::json!({}))
+        .await;
+    assert_response_is_redirect_to(&response, "/admin/subscribers");
+    let html_page = app.get_admin_html("/admin/subscribers").await;
+    assert!(html_page.contains("ursula@example.com has been deleted."));
+    assert_eq!(count_rows(&app, "subscriptions").await, 0);
+    assert_eq!(count_rows(&app, "subscription_tokens").await, 0);
+}
@@ -0,0 +1,204 @@
+use crate::helpers::{
+    TestApp, assert_response_is_redirect_to, create_confirmed_list_subscriber, spawn_app,
+    subscribe_to_list,
+};
+
+async fn export(app: &TestApp, query: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!(
+            "{}/admin/subscribers/export?{}",
+            &app.address, query
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// The records of an exported CSV, its header first
+async fn csv_records(response: reqwest::Response) -> Vec<Vec<String>> {
+    let body = response.text().await.unwrap();
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(body.as_bytes())
+        .records()
+        .map(|r| r.unwrap().iter().map(str::to_string).collect())
+        .collect()
+}
+
+#[tokio::test]
+async fn subscribers_are_exported_as_csv_by_default() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_confirmed_list_subscriber(&app, "ursula@example.com", "newsletter").await;
+
+    // Act
+    let response = export(&app, "").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    assert_eq!(
+        response.headers()["Content-Disposition"],
+        "attachment; filename=\"subscribers.csv\""
+    );
+    let records = csv_records(response).await;
+    assert_eq!(records.len(), 2);
+    assert_eq!(
+        records[0],
+        vec!["id", "email", "name", "subscribed_at", "lists"]
+    );
+    assert_eq!(records[1][1], "ursula@example.com");
+    assert_eq!(records[1][4], "newsletter:confirmed");
+}
+
+#[tokio::test]
+async fn subscribers_can_be_exported_as_a_json_array() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_confirmed_list_subscriber(&app, "ursula@example.com", "newsletter").await;
+    subscribe_to_list(&app, "ged@example.com", "newsletter").await;
+
+    // Act
+    let response = export(&app, "format=json").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "application/json");
+    let body: serde_json::Value = response.json().await.unwrap();
+    let mut emails: Vec<_> = body
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["email"].as_str().unwrap().to_string())
+        .collect();
+    emails.sort();
+    assert_eq!(emails, vec!["ged@example.com", "ursula@example.com"]);
+    assert_eq!(body[0]["lists"][0]["list"], "newsletter");
+}
+
+#[tokio::test]
+async fn an_empty_export_is_still_valid() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    // Act
+    let response = export(&app, "format=json").await;
+
+    // Assert
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body, serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn exports_only_contain_the_matching_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_confirmed_list_subscriber(&app, "ursula@example.com", "newsletter").await;
+    subscribe_to_list(&app, "ged@example.com", "newsletter").await;
+
+    // Act
+    let response = export(&app, "status=pending_confirmation&list=newsletter").await;
+
+    // Assert
+    let records = csv_records(response).await;
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[1][1], "ged@example.com");
+}
+
+#[tokio::test]
+async fn large_exports_are_complete() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    // Enough rows for the export to be sent in several chunks
+    let mut csv = String::from("email,name\n");
+    for i in 0..1000 {
+        csv.push_str(&format!("subscriber{}@example.com,Subscriber {}\n", i, i));
+    }
+    app.post_admin_form(
+        "/admin/subscribers/import",
+        &serde_json::json!({"csv": csv, "list": "newsletter"}),
+    )
+    .await;
+
+    // Act
+    let response = export(&app, "format=csv").await;
+
+    // Assert
+    let records = csv_records(response).await;
+    assert_eq!(records.len(), 1001);
+}
+
+#[tokio::test]
+async fn cells_that_look_like_formulas_are_exported_as_text() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let csv = "email,name\n\
+        a@example.com,=1+2\n\
+        b@example.com,+1\n\
+        c@example.com,-1\n\
+        d@example.com,@SUM\n\
+        e@example.com,Ged\n";
+    app.post_admin_form(
+        "/admin/subscribers/import",
+        &serde_json::json!({"csv": csv, "list": "newsletter"}),
+    )
+    .await;
+
+    // Act
+    let response = export(&app, "format=csv").await;
+
+    // Assert
+    let mut names: Vec<_> = csv_records(response)
+        .await
+        .into_iter()
+        .skip(1)
+        .map(|r| r[2].clone())
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["'+1", "'-1", "'=1+2", "'@SUM", "Ged"]);
+    // JSON is read as data, it is left alone
+    let response = export(&app, "format=json").await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body.as_array().unwrap().iter().any(|s| s["name"] == "=1+2"));
+}
+
+#[tokio::test]
+async fn invalid_export_queries_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    for query in ["format=xml", "status=active"] {
+        // Act
+        let response = export(&app, query).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The export did not fail with query {}",
+            query
+        );
+    }
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_export_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = export(&app, "").await;
+
+    // Assert
+    assert_response_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,291 @@
+use std::collections::BTreeSet;
+
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{
+    TestApp, assert_response_is_redirect_to, count_rows, create_confirmed_list_subscriber,
+    spawn_app,
+};
+
+/// Imports `csv` into the main newsletter and returns the JSON report
+async fn import(app: &TestApp, csv: &str, send_confirmation: bool) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/admin/subscribers/import", &app.address))
+        .header("Accept", "application/json")
+        .form(&serde_json::json!({
+            "csv": csv,
+            "list": "newsletter",
+            "send_confirmation": send_confirmation,
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn statuses(app: &TestApp) -> BTreeSet<(String, String)> {
+    sqlx::query!(
+        r#"
+        SELECT s.email, ls.status
+        FROM list_subscriptions ls
+        JOIN subscriptions s ON s.id = ls.subscriber_id
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| (r.email, r.status))
+    .collect()
+}
+
+#[tokio::test]
+async fn valid_rows_are_imported_and_the_others_reported() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_confirmed_list_subscriber(&app, "existing@example.com", "newsletter").await;
+    let csv = "email,name,status,tags\n\
+        ursula@example.com,Ursula,confirmed,\"beta, paid\"\n\
+        ged@example.com,Ged,,\n\
+        not-an-email,Someone,,\n\
+        existing@example.com,Existing,,\n\
+        ursula@example.com,Ursula again,,\n\
+        tenar@example.com,Tenar,active,\n";
+
+    // Act
+    let response = import(&app, csv, false).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["accepted"], 2);
+    assert_eq!(report["rejected"], 4);
+    let outcomes: Vec<_> = report["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| (r["line"].as_u64().unwrap(), r["outcome"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        outcomes,
+        vec![
+            (2, "accepted"),
+            (3, "accepted"),
+            (4, "rejected"),
+            (5, "rejected"),
+            (6, "rejected"),
+            (7, "rejected"),
+        ]
+    );
+    assert_eq!(
+        report["rows"][3]["message"],
+        "There is already a subscriber with this address."
+    );
+    assert_eq!(
+        report["rows"][4]["message"],
+        "The address is already on line 2."
+    );
+    assert_eq!(
+        statuses(&app).await,
+        BTreeSet::from([
+            ("existing@example.com".into(), "confirmed".into()),
+            ("ged@example.com".into(), "pending_confirmation".into()),
+            ("ursula@example.com".into(), "confirmed".into()),
+        ])
+    );
+    assert_eq!(count_rows(&app, "subscriber_tags").await, 2);
+}
+
+#[tokio::test]
+async fn duplicates_are_detected_whatever_the_case() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_confirmed_list_subscriber(&app, "existing@example.com", "newsletter").await;
+    let csv = "email,name\n\
+        Existing@Example.com,Existing\n\
+        ged@example.com,Ged\n\
+        GED@example.com,Ged again\n";
+
+    // Act
+    let response = import(&app, csv, false).await;
+
+    // Assert
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["accepted"], 1);
+    assert_eq!(
+        report["rows"][0]["message"],
+        "There is already a subscriber with this address."
+    );
+    assert_eq!(
+        report["rows"][2]["message"],
+        "The address is already on line 3."
+    );
+    assert_eq!(count_rows(&app, "subscriptions").await, 2);
+}
+
+#[tokio::test]
+async fn imported_subscribers_are_not_duplicated_by_a_signup_in_another_case() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    import(&app, "email,name\nursula@example.com,Ursula\n", false).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=Ursula&email=Ursula%40Example.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let emails = sqlx::query_scalar!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(emails, vec!["ursula@example.com"]);
+}
+
+#[tokio::test]
+async fn confirmation_emails_are_only_sent_to_pending_subscribers_when_asked() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let csv = "email,name,status\n\
+        ursula@example.com,Ursula,confirmed\n\
+        ged@example.com,Ged,pending_confirmation\n";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = import(&app, csv, true).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "ged@example.com");
+    // The link in the email confirms the subscription
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert!(
+        statuses(&app)
+            .await
+            .contains(&("ged@example.com".into(), "confirmed".into()))
+    );
+}
+
+#[tokio::test]
+async fn no_email_is_sent_unless_asked() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = import(&app, "email,name\nged@example.com,Ged\n", false).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(count_rows(&app, "subscription_tokens").await, 0);
+}
+
+#[tokio::test]
+async fn the_admin_page_shows_the_report() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    // Act
+    let response = app
+        .post_admin_form(
+            "/admin/subscribers/import",
+            &serde_json::json!({
+                "csv": "email,name\nged@example.com,Ged\nnot-an-email,Someone\n",
+                "list": "newsletter",
+            }),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("1 subscriber(s) imported into newsletter, 1 row(s) rejected."));
+    assert!(html_page.contains("not-an-email is not a valid subscriber email"));
+}
+
+#[tokio::test]
+async fn imports_with_an_unknown_list_or_no_header_are_refused() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"csv": "email,name\nged@example.com,Ged\n", "list": "nope"}),
+            "'nope' is not a known list.",
+        ),
+        (
+            serde_json::json!({"csv": "ged@example.com,Ged\n", "list": "newsletter"}),
+            "The first row of the CSV must name its columns",
+        ),
+    ];
+
+    for (body, error_message) in test_cases {
+        // Act
+        let response = app
+            .api_client
+            .post(format!("{}/admin/subscribers/import", &app.address))
+            .header("Accept", "application/json")
+            .form(&body)
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert!(
+            body["message"].as_str().unwrap().starts_with(error_message),
+            "Unexpected error: {}",
+            body["message"]
+        );
+    }
+    assert_eq!(count_rows(&app, "subscriptions").await, 0);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_import_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_admin_form(
+            "/admin/subscribers/import",
+            &serde_json::json!({"csv": "email,name\nged@example.com,Ged\n"}),
+        )
+        .await;
+
+    // Assert
+    assert_response_is_redirect_to(&response, "/login");
+    assert_eq!(count_rows(&app, "subscriptions").await, 0);
+}
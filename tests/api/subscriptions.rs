@@ -3,7 +3,7 @@ use wiremock::{
     matchers::{method, path},
 };
 
-use crate::helpers::spawn_app;
+use crate::helpers::{create_confirmed_subscriber, spawn_app};
 
 #[tokio::test]
 async fn suscribe_returns_a_200_for_valid_form_data() {
@@ -51,7 +51,7 @@ async fn subscribe_allows_a_user_subscribing_two_times() {
 }
 
 #[tokio::test]
-async fn a_user_that_subscribes_two_times_has_the_same_token() {
+async fn a_user_that_subscribes_two_times_gets_a_fresh_token() {
     // Arrange
     let app = spawn_app().await;
 
@@ -70,12 +70,39 @@ async fn a_user_that_subscribes_two_times_has_the_same_token() {
     app.post_subscriptions(body.into()).await;
 
     let email_requests = app.email_server.received_requests().await.unwrap();
-    let first_request_body = &email_requests[0].body;
-    let second_request_body = &email_requests[1].body;
+    let first_links = app.get_confirmation_links(&email_requests[0]);
+    let second_links = app.get_confirmation_links(&email_requests[1]);
 
     // Assert
-    // Lets check that they are the same token
-    assert_eq!(first_request_body, second_request_body);
+    assert_ne!(first_links.html, second_links.html);
+    // The old link stops working...
+    let response = reqwest::get(first_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 410);
+    // ...and the new one confirms the subscription
+    let response = reqwest::get(second_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribing_again_after_confirming_does_not_send_another_email() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
 }
 
 #[tokio::test]
@@ -127,10 +154,13 @@ async fn subscribe_persists_the_new_subscriber() {
     // Act
     app.post_subscriptions(body.into()).await;
 
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
-        .fetch_one(&app.db_pool)
-        .await
-        .expect("Failed to fetch saved subscription");
+    let saved = sqlx::query!(
+        "SELECT s.email, s.name, ls.status FROM subscriptions s \
+        JOIN list_subscriptions ls ON ls.subscriber_id = s.id"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription");
 
     assert_eq!(saved.email, "ursula_le_guin@gmail.com");
     assert_eq!(saved.name, "le guin");
@@ -3,7 +3,9 @@ use wiremock::{
     matchers::{method, path},
 };
 
-use crate::helpers::spawn_app;
+use crate::helpers::{
+    assert_response_is_redirect_to, create_unconfirmed_subscriber, spawn_app, subscribe_to_list,
+};
 
 #[tokio::test]
 async fn confirmations_without_token_are_rejected_with_a_400() {
@@ -68,10 +70,13 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
         .unwrap();
 
     // Assert
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
-        .fetch_one(&app.db_pool)
-        .await
-        .expect("Failed to fetch saved subscription");
+    let saved = sqlx::query!(
+        "SELECT s.email, s.name, ls.status FROM subscriptions s \
+        JOIN list_subscriptions ls ON ls.subscriber_id = s.id"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription");
 
     assert_eq!(saved.email, "ursula_le_guin@gmail.com");
     assert_eq!(saved.name, "le guin");
@@ -79,21 +84,10 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
 }
 
 #[tokio::test]
-async fn clicking_on_the_confirmation_link_twice_just_returns_a_200() {
+async fn clicking_on_the_confirmation_link_twice_returns_a_409() {
     // Arrange
     let app = spawn_app().await;
-    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-
-    Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .expect(1)
-        .mount(&app.email_server)
-        .await;
-
-    app.post_subscriptions(body.into()).await;
-    let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(email_request);
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
 
     // Act
     reqwest::get(confirmation_links.html.as_ref())
@@ -102,23 +96,42 @@ async fn clicking_on_the_confirmation_link_twice_just_returns_a_200() {
         .error_for_status()
         .unwrap();
 
-    reqwest::get(confirmation_links.html.as_ref())
+    let response = reqwest::get(confirmation_links.html.as_ref())
         .await
-        .unwrap()
-        .error_for_status()
         .unwrap();
 
     // Assert
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
+    // The token can only be used once, but the subscriber stays confirmed
+    assert_eq!(response.status().as_u16(), 409);
+    let saved = sqlx::query!("SELECT status FROM list_subscriptions")
         .fetch_one(&app.db_pool)
         .await
         .expect("Failed to fetch saved subscription");
-
-    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
-    assert_eq!(saved.name, "le guin");
     assert_eq!(saved.status, "confirmed");
 }
 
+#[tokio::test]
+async fn an_expired_confirmation_link_is_rejected_with_a_410() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    let saved = sqlx::query!("SELECT status FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
 #[tokio::test]
 async fn using_a_well_formatted_but_non_existing_token_fails_with_a_401() {
     // Arrange
@@ -173,3 +186,150 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn confirming_in_a_browser_shows_an_html_page() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/html; charset=utf-8"
+    );
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Subscription confirmed"));
+}
+
+#[tokio::test]
+async fn an_invalid_link_shows_a_form_to_get_a_new_one() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let invalid_links = [
+        confirmation_links.html.to_string(),
+        format!(
+            "{}/subscriptions/confirm?subscription_token=nonexisting25charstoken00",
+            app.address
+        ),
+        format!("{}/subscriptions/confirm", app.address),
+    ];
+
+    for link in invalid_links {
+        // Act
+        let html_page = reqwest::get(&link).await.unwrap().text().await.unwrap();
+
+        // Assert
+        assert!(html_page.contains("invalid or has expired"), "{}", link);
+        assert!(
+            html_page.contains(r#"<form action="/subscriptions" method="post">"#),
+            "{}",
+            link
+        );
+    }
+}
+
+#[tokio::test]
+async fn a_new_link_can_be_asked_for_from_the_page_of_an_expired_one() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let response = app
+        .post_admin_form(
+            "/admin/lists",
+            &serde_json::json!({"slug": "rust", "name": "Rust weekly"}),
+        )
+        .await;
+    assert_response_is_redirect_to(&response, "/admin/lists");
+    let confirmation_links = subscribe_to_list(&app, "ursula@example.com", "rust").await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - The page of the expired link
+    let html_page = reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains(r#"<input type="hidden" name="list" value="rust">"#));
+
+    // Act - Part 2 - Submit its form, as a browser does
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("Accept", "text/html,application/xhtml+xml,*/*;q=0.8")
+        .form(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula@example.com",
+            "list": "rust",
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Check your inbox"));
+    let n_fresh_tokens = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM subscription_tokens t
+        JOIN lists l ON l.list_id = t.list_id
+        WHERE l.slug = 'rust' AND t.expires_at > now()
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(n_fresh_tokens, 1);
+}
+
+#[tokio::test]
+async fn api_clients_get_json_responses() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    let get_json = |link: reqwest::Url| async move {
+        let response = reqwest::Client::new()
+            .get(link)
+            .header("Accept", "application/json")
+            .send()
+            .await
+            .unwrap();
+        let status = response.status().as_u16();
+        let body: serde_json::Value = response.json().await.unwrap();
+        (status, body)
+    };
+
+    // Act - Part 1 - Confirm
+    let (status, body) = get_json(confirmation_links.html.clone()).await;
+
+    // Assert - Part 1
+    assert_eq!(status, 200);
+    assert_eq!(body["status"], "confirmed");
+
+    // Act - Part 2 - Reuse the link
+    let (status, body) = get_json(confirmation_links.html).await;
+
+    // Assert - Part 2
+    assert_eq!(status, 409);
+    assert_eq!(body["error"], "consumed_token");
+    assert!(body["message"].is_string());
+}
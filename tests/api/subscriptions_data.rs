@@ -0,0 +1,352 @@
+use std::time::{Duration, Instant};
+
+use chrono::{TimeDelta, Utc};
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+use zero2prod::domain::DataRequestToken;
+
+use crate::helpers::{
+    PostmarkBatchResponder, TestApp, count_rows, create_confirmed_subscriber,
+    publish_test_newsletter, spawn_app,
+};
+
+/// The address of `create_confirmed_subscriber`
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+async fn post_data_request(app: &TestApp, email: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/subscriptions/data", &app.address))
+        .form(&serde_json::json!({ "email": email }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// Asks for the data of `EMAIL`, and returns the link of the email
+async fn get_data_link(app: &TestApp) -> reqwest::Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let n = n_emails(app).await + 1;
+    post_data_request(app, EMAIL)
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = wait_for_email(app, n).await;
+    app.get_confirmation_links(&email_request).plain_text
+}
+
+/// The email is sent in the background, after the response: waits for
+/// the `n`th email the server receives
+async fn wait_for_email(app: &TestApp, n: usize) -> wiremock::Request {
+    for _ in 0..50 {
+        let mut emails: Vec<_> = app
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|r| r.url.path() == "/email")
+            .collect();
+        if emails.len() >= n {
+            return emails.swap_remove(n - 1);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("The data request email was not sent");
+}
+
+/// How many emails the server received so far
+async fn n_emails(app: &TestApp) -> usize {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|r| r.url.path() == "/email")
+        .count()
+}
+
+/// The same link, for another action on the data
+fn with_path(link: &reqwest::Url, path: &str) -> reqwest::Url {
+    let mut link = link.clone();
+    link.set_path(path);
+    link
+}
+
+#[tokio::test]
+async fn subscribers_receive_a_link_to_their_data() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let link = get_data_link(&app).await;
+    let response = reqwest::get(link.clone()).await.unwrap();
+
+    // Assert
+    assert_eq!(link.path(), "/subscriptions/data/access");
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(EMAIL));
+    assert!(html_page.contains("Erase my data"));
+}
+
+#[tokio::test]
+async fn unknown_addresses_get_the_same_answer_and_no_email() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = post_data_request(&app, "nobody@example.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("If this address is subscribed, you will receive an email"));
+}
+
+#[tokio::test]
+async fn subscribers_get_the_same_answer_when_the_email_can_not_be_sent() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = post_data_request(&app, EMAIL).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("If this address is subscribed, you will receive an email"));
+    // After the confirmation email
+    wait_for_email(&app, 2).await;
+}
+
+#[tokio::test]
+async fn the_answer_does_not_wait_for_the_email() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(30)))
+        .mount(&app.email_server)
+        .await;
+
+    for email in [EMAIL, "nobody@example.com"] {
+        // Act
+        let started_at = Instant::now();
+        let response = post_data_request(&app, email).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        assert!(
+            started_at.elapsed() < Duration::from_secs(5),
+            "The answer for {} waited for the email",
+            email
+        );
+    }
+}
+
+#[tokio::test]
+async fn the_export_holds_the_subscription_deliveries_and_consent_history() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    publish_test_newsletter(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    // Unsubscribe with the link of the issue
+    let batch_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|r| r.url.path() == "/email/batch")
+        .unwrap();
+    let batch: serde_json::Value = serde_json::from_slice(&batch_request.body).unwrap();
+    reqwest::Client::new()
+        .post(app.get_unsubscribe_link(&batch[0]))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let link = get_data_link(&app).await;
+
+    // Act
+    let response = reqwest::get(with_path(&link, "/subscriptions/data/export"))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Disposition"],
+        "attachment; filename=\"personal-data.json\""
+    );
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["email"], EMAIL);
+    assert_eq!(data["name"], "le guin");
+    assert_eq!(data["lists"][0]["list"], "newsletter");
+    assert_eq!(data["lists"][0]["status"], "unsubscribed");
+    let tokens = data["confirmation_tokens"].as_array().unwrap();
+    assert_eq!(tokens.len(), 1);
+    assert!(!tokens[0]["consumed_at"].is_null());
+    assert!(tokens[0].get("subscription_token").is_none());
+    assert_eq!(data["deliveries"][0]["title"], "Newsletter title");
+    assert_eq!(data["deliveries"][0]["status"], "delivered");
+    let events: Vec<_> = data["consent_events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["event"].as_str().unwrap())
+        .collect();
+    assert_eq!(events, vec!["subscribed", "confirmed", "unsubscribed"]);
+}
+
+#[tokio::test]
+async fn erasure_leaves_only_a_suppression_hash_behind() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    publish_test_newsletter(&app).await;
+    let link = get_data_link(&app).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(with_path(&link, "/subscriptions/data/erase"))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    for table in [
+        "subscriptions",
+        "list_subscriptions",
+        "subscription_tokens",
+        "consent_events",
+        "issue_delivery_queue",
+    ] {
+        assert_eq!(count_rows(&app, table).await, 0, "{} is not empty", table);
+    }
+    let email_hash = sqlx::query_scalar!("SELECT email_hash FROM suppressed_emails")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(!email_hash.contains("ursula"));
+    // The link can't be used anymore
+    let response = reqwest::get(link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn erased_addresses_can_not_be_imported_again() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = get_data_link(&app).await;
+    reqwest::Client::new()
+        .post(with_path(&link, "/subscriptions/data/erase"))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.login_test_user().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/subscribers/import", &app.address))
+        .header("Accept", "application/json")
+        .form(&serde_json::json!({
+            "csv": "email,name\nUrsula_Le_Guin@gmail.com,Ursula\n",
+            "list": "newsletter",
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["rejected"], 1);
+    assert_eq!(
+        report["rows"][0]["message"],
+        "The owner of this address asked for their data to be erased."
+    );
+    assert_eq!(count_rows(&app, "subscriptions").await, 0);
+}
+
+#[tokio::test]
+async fn forged_or_expired_links_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = get_data_link(&app).await;
+    let subscriber_id = app.subscriber_id(EMAIL).await;
+    let expired_at = (Utc::now() - TimeDelta::minutes(1)).timestamp();
+    let expired_token = DataRequestToken::generate(
+        subscriber_id,
+        chrono::DateTime::from_timestamp(expired_at, 0).unwrap(),
+        &app.delivery_settings.hmac_secret,
+    );
+    let mut expired_link = link.clone();
+    expired_link.set_query(Some(&format!(
+        "subscriber_id={}&expires_at={}&token={}",
+        subscriber_id,
+        expired_at,
+        expired_token.as_ref()
+    )));
+    // Signed for a day only, pushed back by a year
+    let mut extended_link = link.clone();
+    let extended_query = link
+        .query_pairs()
+        .map(|(k, v)| match k.as_ref() {
+            "expires_at" => format!("{}={}", k, v.parse::<i64>().unwrap() + 365 * 86400),
+            _ => format!("{}={}", k, v),
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    extended_link.set_query(Some(&extended_query));
+
+    for (link, description) in [(expired_link, "expired"), (extended_link, "extended")] {
+        // Act
+        let response = reqwest::Client::new()
+            .post(with_path(&link, "/subscriptions/data/erase"))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The {} link was accepted",
+            description
+        );
+    }
+    assert_eq!(count_rows(&app, "subscriptions").await, 1);
+}
@@ -0,0 +1,213 @@
+use uuid::Uuid;
+use wiremock::{
+    Mock,
+    matchers::{method, path},
+};
+
+use zero2prod::domain::UnsubscribeToken;
+
+use crate::helpers::{
+    PostmarkBatchResponder, TestApp, create_confirmed_subscriber, create_unconfirmed_subscriber,
+    publish_test_newsletter, spawn_app,
+};
+
+/// Publishes a newsletter to the confirmed subscriber and returns the message sent to them
+async fn deliver_test_newsletter(app: &TestApp) -> serde_json::Value {
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    publish_test_newsletter(app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let mut messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(messages.len(), 1);
+    messages.pop().unwrap()
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM list_subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+#[tokio::test]
+async fn newsletters_contain_an_unsubscribe_link() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let message = deliver_test_newsletter(&app).await;
+
+    // Assert
+    let unsubscribe_link = app.get_unsubscribe_link(&message);
+    assert_eq!(unsubscribe_link.path(), "/subscriptions/unsubscribe");
+    let raw_link = unsubscribe_link
+        .as_str()
+        .replace(&format!(":{}", app.port), "");
+    // The `&` of the query string is escaped in the HTML body
+    let html_link = raw_link.replace('&', "&#38;");
+    assert!(message["HtmlBody"].as_str().unwrap().contains(&html_link));
+    assert!(message["TextBody"].as_str().unwrap().contains(&raw_link));
+    let one_click = message["Headers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|h| h["Name"] == "List-Unsubscribe-Post")
+        .unwrap();
+    assert_eq!(one_click["Value"], "List-Unsubscribe=One-Click");
+}
+
+#[tokio::test]
+async fn the_unsubscribe_link_shows_a_confirmation_form_without_unsubscribing() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let message = deliver_test_newsletter(&app).await;
+    let unsubscribe_link = app.get_unsubscribe_link(&message);
+
+    // Act
+    let response = reqwest::get(unsubscribe_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"method="post""#));
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn a_one_click_post_to_the_unsubscribe_link_unsubscribes_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let message = deliver_test_newsletter(&app).await;
+    let unsubscribe_link = app.get_unsubscribe_link(&message);
+
+    // Act - this is what mail clients send (RFC 8058)
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .form(&[("List-Unsubscribe", "One-Click")])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_receive_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let message = deliver_test_newsletter(&app).await;
+    let unsubscribe_link = app.get_unsubscribe_link(&message);
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder)
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_test_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    // Mock verifies on Drop that no newsletter has been sent
+}
+
+#[tokio::test]
+async fn unsubscribing_is_rejected_with_an_invalid_token() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    let test_cases = vec![
+        (subscriber_id, "not-even-hex".to_string()),
+        (subscriber_id, "ab".repeat(32)),
+        // A token for somebody else
+        (
+            Uuid::new_v4(),
+            app.get_unsubscribe_link(&deliver_test_newsletter(&app).await)
+                .query_pairs()
+                .find(|(k, _)| k == "token")
+                .unwrap()
+                .1
+                .into_owned(),
+        ),
+    ];
+
+    for (subscriber_id, token) in test_cases {
+        // Act
+        let response = reqwest::Client::new()
+            .post(format!(
+                "{}/subscriptions/unsubscribe?subscriber_id={}&token={}",
+                app.address, subscriber_id, token
+            ))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400);
+    }
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn an_unused_confirmation_link_does_not_subscribe_again_after_unsubscribing() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    let subscriber_id = sqlx::query_scalar!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let token = UnsubscribeToken::generate(subscriber_id, &app.delivery_settings.hmac_secret);
+    reqwest::Client::new()
+        .post(format!(
+            "{}/subscriptions/unsubscribe?subscriber_id={}&token={}",
+            app.address,
+            subscriber_id,
+            token.as_ref()
+        ))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+}